@@ -1,19 +1,22 @@
 #![allow(dead_code)]
-use std::env;
-use std::env::current_dir;
 use std::fmt::Display;
-use std::io;
-use std::io::Write;
-use std::path::Path;
-use std::process::Command;
-use std::process::Stdio;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::{bail, Context};
 use clap::Args;
 use clap::Parser;
 use clap::Subcommand;
 
-use crate::seed_settings::JobType;
+use crate::pipeline::{
+    cancel_on_sigint, check_seed, CalcRunner, CastepCommand, ChildProcesses, HubArguments,
+    PseudoKind, RecoveryPolicy, ResultReader, RunManifest, SeedFolder, Sequence, Severity,
+    DEFAULT_MAX_JOBS, DEFAULT_MAX_RECOVERIES, GRID_SCALE, INIT_ELEC_ENERGY_TOL, INIT_HUBBARD_U,
+};
+use crate::seed_settings::{HubbardSelector, JobType, KpointSampling};
 
 use super::program_mode::ProgramMode;
 
@@ -37,6 +40,8 @@ pub enum JobCommands {
     Read(ReadArgs),
     /// Start calculations
     Calc(CalcArgs),
+    /// Check a seed folder for mistakes before starting calculations
+    Check(CheckArgs),
 }
 
 #[derive(Debug, Args, Clone, Default)]
@@ -45,21 +50,25 @@ pub struct ReadArgs {
     /// Path to result folder, e.g.: XXX_[jobtype]_[init_input_u]_[step_u]_[final_u]_[perturb_init]_[perturb_step]_[perturb_final]_STEPS_[perturb_times]
     pub(crate) result_path: String,
     #[arg(short, long)]
-    /// Interpreted from the folder name by default.
+    /// Read from `run.toml`, or interpreted from the folder name by default.
     pub(crate) jobtype: Option<JobType>,
     #[arg(long, allow_negative_numbers = true)]
-    /// Interpreted from the folder name by default.
+    /// Read from `run.toml`, or interpreted from the folder name by default.
     pub(crate) init_input_u: Option<f64>,
     #[arg(long, allow_negative_numbers = true)]
-    /// Interpreted from the folder name by default.
+    /// Read from `run.toml`, or interpreted from the folder name by default.
     pub(crate) step_u: Option<f64>,
     #[arg(long, allow_negative_numbers = true)]
-    /// Interpreted from the folder name by default.
+    /// Read from `run.toml`, or interpreted from the folder name by default.
     pub(crate) final_u: Option<f64>,
     #[arg(long)]
-    /// Interpreted from the folder name by default.
+    /// Read from `run.toml`, or interpreted from the folder name by default.
     /// Decide how many rounds of perturbations to be read
     pub(crate) perturb_times: Option<i64>,
+    #[arg(long)]
+    /// Read from `run.toml`. The Hubbard site perturbed by the run, recorded as
+    /// "Perturbed Channel" in the result csv; all channels if not given.
+    pub(crate) site: Option<HubbardSelector>,
 }
 
 impl Display for ReadArgs {
@@ -72,7 +81,14 @@ impl FromStr for ReadArgs {
     type Err = ReadArgsError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        ReadArgs::new_with_folder_name(s)
+        let mut args = Self {
+            result_path: s.to_string(),
+            ..Self::default()
+        };
+        match args.set_from_manifest() {
+            Ok(_) => Ok(args),
+            Err(_) => ReadArgs::new_with_folder_name(s),
+        }
     }
 }
 
@@ -98,6 +114,21 @@ impl ReadArgs {
             Err(e) => Err(e),
         }
     }
+    /// Fill the parameters not given on the command line from `run.toml` in the result folder.
+    pub fn set_from_manifest(&mut self) -> Result<(), anyhow::Error> {
+        let manifest = RunManifest::load(&self.result_path)?;
+        let hub_args = manifest.hub_arguments();
+        self.jobtype.get_or_insert(hub_args.job_type());
+        self.init_input_u.get_or_insert(hub_args.u_start());
+        self.step_u.get_or_insert(hub_args.u_step());
+        self.final_u.get_or_insert(hub_args.u_end());
+        self.perturb_times
+            .get_or_insert(hub_args.perturb_times() as i64);
+        if self.site.is_none() {
+            self.site = hub_args.site().cloned();
+        }
+        Ok(())
+    }
     pub fn set_from_folder_name(&mut self) -> Result<(), ReadArgsError> {
         let result_path = Path::new(&self.result_path);
         let stem = result_path
@@ -126,22 +157,26 @@ impl ReadArgs {
             Err(ReadArgsError)
         }
     }
-    pub fn invoke(&self) -> Result<(), io::Error> {
-        let program = current_dir().unwrap().join("auto_hubbard_linux.sh");
-        let output = Command::new("bash")
-            .arg(program)
-            .arg(&self.result_path)
-            .arg(self.jobtype.unwrap().to_string())
-            .arg("read")
-            .arg(format!("{}", &self.init_input_u.unwrap()))
-            .arg(format!("{}", &self.step_u.unwrap()))
-            .arg(format!("{}", &self.final_u.unwrap()))
-            .arg(format!("{}", &self.perturb_times.unwrap()))
-            .stdout(Stdio::inherit())
-            .output()
-            .expect("Failed to start `auto_hubbard_linux.sh` in read mode; check if `auto_hubbard_linux.sh` is in current working directory");
-        io::stdout().write_all(&output.stdout)?;
-        io::stderr().write_all(&output.stderr)
+    pub fn invoke(&self) -> Result<(), anyhow::Error> {
+        let (Some(job_type), Some(init_u), Some(step_u), Some(final_u), Some(perturb_times)) = (
+            self.jobtype,
+            self.init_input_u,
+            self.step_u,
+            self.final_u,
+            self.perturb_times,
+        ) else {
+            return Err(ReadArgsError.into());
+        };
+        let reader = ResultReader::new(
+            &self.result_path,
+            job_type,
+            Sequence::new(init_u, step_u, final_u),
+            perturb_times.max(0) as usize,
+        )
+        .with_site(self.site.clone());
+        let post_read_path = reader.read()?;
+        println!("Result: {}", post_read_path.display());
+        Ok(())
     }
 }
 
@@ -152,9 +187,12 @@ pub struct CalcArgs {
     pub(crate) seed_path: String,
     /// `u` or `alpha`
     pub(crate) jobtype: JobType,
-    /// `parallel` or `serial`
+    /// `parallel` or `serial` on this machine, or submit the jobs with `pbs` or `slurm`
     #[arg(short, long, default_value_t = ProgramMode::Parallel)]
     pub(crate) mode: ProgramMode,
+    /// Maximum number of jobs running or queued at the same time, except in `serial` mode
+    #[arg(long, default_value_t = DEFAULT_MAX_JOBS)]
+    pub(crate) max_jobs: usize,
     #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
     pub(crate) init_input_u: f64,
     #[arg(long, default_value_t = 2.0, allow_negative_numbers = true)]
@@ -167,27 +205,158 @@ pub struct CalcArgs {
     pub(crate) perturb_step: f64,
     #[arg(long, default_value_t = 0.25, allow_negative_numbers = true)]
     pub(crate) perturb_final: f64,
+    /// The U of the `U = 0` jobs and added to every U, small but non-zero to keep `CASTEP` in LDA+U
+    #[arg(long, default_value_t = INIT_HUBBARD_U)]
+    pub(crate) init_hubbard_u: f64,
+    /// `elec_energy_tol` (eV) of the unperturbed jobs
+    #[arg(long, default_value_t = INIT_ELEC_ENERGY_TOL)]
+    pub(crate) init_elec_energy_tol: f64,
+    /// `elec_energy_tol` (eV) of the perturbed jobs, a tenth of `--init-elec-energy-tol` by default
+    #[arg(long)]
+    pub(crate) perturbed_elec_energy_tol: Option<f64>,
+    /// `cut_off_energy` (eV) of every job, the seed's by default
+    #[arg(long)]
+    pub(crate) cut_off_energy: Option<f64>,
+    /// `grid_scale` of every job
+    #[arg(long, default_value_t = GRID_SCALE)]
+    pub(crate) grid_scale: f64,
+    /// `fine_grid_scale` of every job, left to `CASTEP` by default
+    #[arg(long)]
+    pub(crate) fine_grid_scale: Option<f64>,
+    /// `max_scf_cycles` of every job before any recovery, the seed's by default
+    #[arg(long)]
+    pub(crate) max_scf_cycles: Option<u32>,
+    /// Command to start `CASTEP` in each job folder; the seed name is appended as the last argument.
+    #[arg(short, long, default_value = "castep.serial")]
+    pub(crate) castep_command: String,
+    /// Job script template for `pbs` and `slurm` modes, with the placeholders
+    /// `{job_name}`, `{seed_name}` and `{castep_command}`. A minimal script is used by default.
+    #[arg(long)]
+    pub(crate) job_template: Option<PathBuf>,
+    /// Only set U and alpha on this Hubbard site, `species[:ion_number[:orbital]]`, e.g.: `Fe:1:d`,
+    /// or `Fe:up:1:d` for a labelled species.
+    /// The other sites keep their U in the seed and are not perturbed.
+    #[arg(long)]
+    pub(crate) site: Option<HubbardSelector>,
+    /// Rewrite the k-points of the seed to a Monkhorst-Pack grid, e.g.: `4x4x1`,
+    /// or to the grid for a spacing in 1/Å as `KPOINTS_MP_SPACING` (no 2π factor),
+    /// e.g.: `0.05`. Use `1x1x1` for a cluster seeded with `KPOINTS_LIST`.
+    #[arg(long)]
+    pub(crate) kpoints: Option<KpointSampling>,
+    /// Rewrite `SPECIES_POT` for every species: `nc` (norm-conserving `.recpot`/`.ncp`) or `us`
+    /// (ultrasoft `.usp`/`.uspcc`/`.uspso`) files from `--pseudo-lib`, or `otfg[:library_or_string]`
+    /// for on-the-fly generation, e.g.: `otfg:C19`.
+    #[arg(long)]
+    pub(crate) pseudo: Option<PseudoKind>,
+    /// Directory of pseudopotential files named after their element, e.g.: `Fe_00PBE.uspcc`.
+    /// Also searched for the files in `SPECIES_POT` which are not in the seed folder.
+    #[arg(long)]
+    pub(crate) pseudo_lib: Option<PathBuf>,
+    /// For a seed without `HUBBARD_U`, also set U on the p shell of these ligand elements,
+    /// e.g.: `O,N`. The d and f shells of the metals in the positions always get one.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) ligand_p: Vec<String>,
+    /// Continue an interrupted run in the same result folder from the job states in its `run.toml`.
+    /// Finished jobs are skipped, failed ones restart from their `.check`.
+    #[arg(long)]
+    pub(crate) resume: bool,
+    /// Give up on a job without outcome after this many minutes, counted from its
+    /// submission. A `serial`/`parallel` job is only timed out once the castep command returns.
+    #[arg(long)]
+    pub(crate) job_timeout: Option<u64>,
+    /// Number of reruns with an adjusted `.param` (more SCF cycles, gentler mixing, Pulay mixing,
+    /// EDFT, then a looser `elec_energy_tol` for the unperturbed jobs only) for an unconverged job,
+    /// 0 to disable
+    #[arg(long, default_value_t = DEFAULT_MAX_RECOVERIES)]
+    pub(crate) max_recoveries: u32,
+    /// Write the job folders with their inputs and print the plan of every job, without
+    /// submitting anything. Run again without it to start the jobs from these inputs.
+    #[arg(long, conflicts_with = "resume")]
+    pub(crate) dry_run: bool,
+    /// Replace the `run.toml` of a result folder whose jobs have already started,
+    /// instead of refusing to. Jobs with a complete `.castep` are still skipped.
+    #[arg(long, conflicts_with = "resume")]
+    pub(crate) force: bool,
 }
 
 impl CalcArgs {
-    pub fn invoke(&self) -> Result<(), io::Error> {
-        let program = current_dir().unwrap().join("auto_hubbard_linux.sh");
-        let output = Command::new("bash")
-            .arg(program)
-            .arg(&self.seed_path)
-            .arg(self.jobtype.to_string())
-            .arg(self.mode.to_string())
-            .arg(format!("{}", &self.init_input_u))
-            .arg(format!("{}", &self.step_u))
-            .arg(format!("{}", &self.final_u))
-            .arg(format!("{}", &self.perturb_init))
-            .arg(format!("{}", &self.perturb_step))
-            .arg(format!("{}", &self.perturb_final))
-            .stdout(Stdio::inherit())
-            .output()
-            .expect("Failed to start `auto_hubbard_linux.sh` in calc mode; check if `auto_hubbard_linux.sh` is in current working directory");
-        io::stdout().write_all(&output.stdout)?;
-        io::stderr().write_all(&output.stderr)
+    pub fn invoke(&self) -> Result<(), anyhow::Error> {
+        let hub_args = HubArguments::from(self);
+        let seed = SeedFolder::load(&self.seed_path)?
+            .resolve_pseudopotentials(hub_args.pseudo(), self.pseudo_lib.as_deref())?
+            .with_hubbard_u(hub_args.init_hubbard_u(), hub_args.ligand_p())?;
+        let castep_command = CastepCommand::from_str(&self.castep_command)?;
+        let template = self
+            .job_template
+            .as_ref()
+            .map(|path| {
+                read_to_string(path)
+                    .with_context(|| format!("Failed to read job template {}", path.display()))
+            })
+            .transpose()?;
+        let children = Arc::new(ChildProcesses::default());
+        let scheduler = self
+            .mode
+            .scheduler(castep_command.clone(), template, children.clone());
+        let runner = CalcRunner::setup(
+            &seed,
+            &hub_args,
+            &castep_command,
+            scheduler,
+            self.resume,
+            self.force,
+        )?
+        .with_timeout(
+            self.job_timeout
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+        )
+        .with_recovery(RecoveryPolicy::new(self.max_recoveries));
+        if self.dry_run {
+            return runner.prepare();
+        }
+        cancel_on_sigint(runner.cancel_token(), children)?;
+        runner.run(self.mode.max_jobs(self.max_jobs))
+    }
+}
+
+#[derive(Args)]
+#[command(version, about)]
+pub struct CheckArgs {
+    /// Path to the seed folder including `.cell`, `.param` and other necessary files.
+    pub(crate) seed_path: String,
+    /// Directory of pseudopotential files, searched for the files in `SPECIES_POT`
+    /// which are not in the seed folder.
+    #[arg(long)]
+    pub(crate) pseudo_lib: Option<PathBuf>,
+    /// The Hubbard site to be given to `calc --site`, checked to match a site of the seed.
+    #[arg(long)]
+    pub(crate) site: Option<HubbardSelector>,
+}
+
+impl CheckArgs {
+    /// Print every problem found in the seed, an error if any is severe
+    pub fn invoke(&self) -> Result<(), anyhow::Error> {
+        let findings = check_seed(
+            Path::new(&self.seed_path),
+            self.pseudo_lib.as_deref(),
+            self.site.as_ref(),
+        );
+        findings.iter().for_each(|finding| println!("{finding}"));
+        let count = |severity: Severity| {
+            findings
+                .iter()
+                .filter(|finding| finding.severity == severity)
+                .count()
+        };
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+        println!(
+            "{}: {errors} error(s), {warnings} warning(s)",
+            self.seed_path
+        );
+        if errors > 0 {
+            bail!("The seed in {} is not ready", self.seed_path);
+        }
+        Ok(())
     }
 }
 
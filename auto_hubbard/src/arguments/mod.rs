@@ -1,4 +1,4 @@
 mod cli_interface;
 pub mod program_mode;
 
-pub use cli_interface::{CalcArgs, Cli, JobCommands, ReadArgs};
+pub use cli_interface::{CalcArgs, CheckArgs, Cli, JobCommands, ReadArgs};
@@ -1,19 +1,56 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::Arc};
 
 use clap::ValueEnum;
 
+use crate::pipeline::{CastepCommand, ChildProcesses, Local, Pbs, Scheduler, Slurm, SystemRunner};
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum ProgramMode {
     Serial,
     Parallel,
+    /// Submit every job to PBS with `qsub`
+    Pbs,
+    /// Submit every job to Slurm with `sbatch`
+    Slurm,
     // Read,
 }
 
+impl ProgramMode {
+    /// `serial` is the executor running one job at a time
+    pub fn max_jobs(&self, max_jobs: usize) -> usize {
+        match self {
+            ProgramMode::Serial => 1,
+            _ => max_jobs,
+        }
+    }
+
+    /// Run locally in `serial`/`parallel` mode, otherwise submit to the batch system
+    /// with the job script rendered from `template`.
+    /// The processes started are registered in `children`.
+    pub fn scheduler(
+        &self,
+        castep_command: CastepCommand,
+        template: Option<String>,
+        children: Arc<ChildProcesses>,
+    ) -> Box<dyn Scheduler> {
+        let runner = Box::new(SystemRunner::new(children));
+        match self {
+            ProgramMode::Serial | ProgramMode::Parallel => {
+                Box::new(Local::new(castep_command, runner))
+            }
+            ProgramMode::Pbs => Box::new(Pbs::new(template, &castep_command, runner)),
+            ProgramMode::Slurm => Box::new(Slurm::new(template, &castep_command, runner)),
+        }
+    }
+}
+
 impl Display for ProgramMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ProgramMode::Serial => f.write_str("serial"),
             ProgramMode::Parallel => f.write_str("parallel"),
+            ProgramMode::Pbs => f.write_str("pbs"),
+            ProgramMode::Slurm => f.write_str("slurm"),
             // ProgramMode::Read => f.write_str("read"),
         }
     }
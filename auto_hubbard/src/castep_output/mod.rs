@@ -0,0 +1,202 @@
+//! Extract the data we need from the `.castep` output.
+
+use std::fmt::Display;
+
+mod occupation;
+mod scf;
+
+pub use occupation::{
+    occupation_blocks, ChannelOccupation, ComplexMatrix, Eigenvectors, OccupationBlock,
+};
+pub use scf::{scf_cycles, ScfCycle};
+
+/// Header of the result csv consumed by `hubbard_data`.
+/// "Perturbed Channel" is the channel whose alpha the run shifts, 0 when all of them are shifted.
+pub const RESULT_CSV_HEADER: &str =
+    "Jobname,Channel ID,Spin,Before SCF,1st SCF,Last SCF,Converged,Perturbed Channel";
+
+/// Marker written by `CASTEP` when a run ends normally.
+const FINALISATION_MARKER: &str = "Finalisation time";
+/// Marker written by `CASTEP` when the SCF reaches the `elec_energy_tol`.
+const CONVERGED_MARKER: &str = "Total energy has converged";
+
+/// Check if the `.castep` reports a finished run.
+pub fn is_finished(castep_content: &str) -> bool {
+    castep_content.contains(FINALISATION_MARKER)
+}
+
+/// The occupation `Total` of one channel and spin, at the three stages we need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OccupationRecord {
+    pub channel: u32,
+    pub spin: u32,
+    pub before_scf: f64,
+    pub first_scf: f64,
+    pub last_scf: f64,
+    pub converged: bool,
+}
+
+/// The parsed content of a `.castep` we care about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastepOutput {
+    blocks: Vec<OccupationBlock>,
+    cycles: Vec<ScfCycle>,
+    finished: bool,
+    converged: bool,
+}
+
+impl CastepOutput {
+    pub fn parse(castep_content: &str) -> Self {
+        Self {
+            blocks: occupation_blocks(castep_content),
+            cycles: scf_cycles(castep_content),
+            finished: is_finished(castep_content),
+            converged: castep_content.contains(CONVERGED_MARKER),
+        }
+    }
+
+    pub fn blocks(&self) -> &[OccupationBlock] {
+        &self.blocks
+    }
+
+    pub fn cycles(&self) -> &[ScfCycle] {
+        &self.cycles
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn converged(&self) -> bool {
+        self.converged
+    }
+
+    /// The first block is the occupation before SCF, the second one is after the
+    /// first SCF cycle, and the last one is the final (converged) occupation.
+    pub fn records(&self) -> Vec<OccupationRecord> {
+        let (Some(before), Some(last)) = (self.blocks.first(), self.blocks.last()) else {
+            return Vec::new();
+        };
+        let first = self.blocks.get(1).unwrap_or(before);
+        before
+            .channels
+            .iter()
+            .map(|occ| {
+                let total_of = |block: &OccupationBlock| {
+                    block
+                        .get(occ.channel, occ.spin)
+                        .map_or(f64::NAN, |found| found.total)
+                };
+                OccupationRecord {
+                    channel: occ.channel,
+                    spin: occ.spin,
+                    before_scf: occ.total,
+                    first_scf: total_of(first),
+                    last_scf: total_of(last),
+                    converged: self.converged,
+                }
+            })
+            .collect()
+    }
+}
+
+/// One line in the result csv.
+#[derive(Debug, Clone, Copy)]
+pub struct ResultRow<'a> {
+    job_name: &'a str,
+    record: OccupationRecord,
+    perturbed_channel: u32,
+}
+
+impl<'a> ResultRow<'a> {
+    pub fn new(job_name: &'a str, record: OccupationRecord, perturbed_channel: u32) -> Self {
+        Self {
+            job_name,
+            record,
+            perturbed_channel,
+        }
+    }
+}
+
+impl Display for ResultRow<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{:.16},{:.16},{:.16},{},{}",
+            self.job_name,
+            self.record.channel,
+            self.record.spin,
+            self.record.before_scf,
+            self.record.first_scf,
+            self.record.last_scf,
+            self.record.converged,
+            self.perturbed_channel
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::read_to_string, path::Path};
+
+    use super::CastepOutput;
+
+    #[test]
+    fn read_gdy_castep() {
+        let castep_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .join("CASTEP_project_doc/GDY_111_Fe_U.castep");
+        let output = CastepOutput::parse(&read_to_string(castep_path).unwrap());
+        assert!(output.finished());
+        assert!(output.converged());
+        let records = output.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].before_scf, 4.88454712510949);
+        assert_eq!(records[0].first_scf, 4.88222767868911);
+        assert_eq!(records[0].last_scf, 4.88417863385162);
+        assert_eq!(records[1].spin, 2);
+        assert_eq!(records[1].last_scf, 2.03022846329140);
+        let cycles = output.cycles();
+        assert_eq!(cycles.len(), 78);
+        assert_eq!(cycles[0].energy, -3.63546574E+003);
+        assert_eq!(cycles[0].hubbard_energy, Some(2.152_384_643_092_604_6));
+        let initial = cycles[0].occupations.as_ref().unwrap();
+        assert_eq!(initial.channels[0].total, 4.88454712510949);
+        assert_eq!(initial.channels[0].mz, Some(0.0));
+        let first = cycles[1].occupations.as_ref().unwrap();
+        assert_eq!(first.channels[1].total, 2.03625090967629);
+        assert_eq!(cycles[1].energy_gain, Some(-4.62879811E-002));
+        let last = cycles.last().unwrap();
+        assert_eq!(last.index, 77);
+        assert!(last.occupations.is_some());
+    }
+
+    #[test]
+    fn density_matrix_and_eigenvectors() {
+        let castep_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .join("CASTEP_project_doc/GDY_111_Fe_U.castep");
+        let output = CastepOutput::parse(&read_to_string(castep_path).unwrap());
+        let spin_up = &output.blocks()[0].channels[0];
+        assert_eq!(spin_up.density_matrix.dim(), 5);
+        assert_eq!(spin_up.density_matrix.imag.len(), 5);
+        assert_eq!(
+            spin_up.density_matrix.diagonal(),
+            vec![0.982, 0.992, 0.992, 0.948, 0.970]
+        );
+        assert_eq!(
+            spin_up.eigenvectors.orbitals,
+            vec!["DZZ", "DZY", "DZX", "DXX-YY", "DXY"]
+        );
+        assert_eq!(
+            spin_up.eigenvectors.eigenvalues,
+            vec![0.937, 0.971, 0.992, 0.992, 0.992]
+        );
+        assert_eq!(
+            spin_up.eigenvectors.vectors.real[0],
+            vec![-0.0, -0.0, -0.0, -0.87, 0.50]
+        );
+    }
+}
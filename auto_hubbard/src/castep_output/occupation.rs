@@ -0,0 +1,199 @@
+/// Title line of each occupation block in `.castep`
+pub(super) const BLOCK_TITLE: &str = "LDA+U occupation numbers";
+/// The block opens its table with a `=====` line and closes it with another one.
+const BLOCK_BORDER: &str = "=====";
+/// Ends the eigenvector table of each channel and spin.
+const CHANNEL_END: &str = "->>";
+
+/// A square complex matrix printed by `CASTEP` as interleaved real and imaginary rows.
+/// The dimension is 5 for d orbitals and 7 for f orbitals.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComplexMatrix {
+    pub real: Vec<Vec<f64>>,
+    pub imag: Vec<Vec<f64>>,
+}
+
+impl ComplexMatrix {
+    /// Build from the rows as printed: real row, imaginary row, real row, ...
+    fn from_interleaved_rows(rows: Vec<Vec<f64>>) -> Self {
+        let (real, imag) = rows.into_iter().enumerate().fold(
+            (Vec::new(), Vec::new()),
+            |(mut real, mut imag), (i, row)| {
+                if i % 2 == 0 {
+                    real.push(row)
+                } else {
+                    imag.push(row)
+                }
+                (real, imag)
+            },
+        );
+        Self { real, imag }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.real.len()
+    }
+
+    /// Diagonal of the real part, i.e. the occupation of each orbital.
+    pub fn diagonal(&self) -> Vec<f64> {
+        self.real
+            .iter()
+            .enumerate()
+            .filter_map(|(i, row)| row.get(i).copied())
+            .collect()
+    }
+}
+
+/// The `Matrix of eigenvectors` table:
+/// ```text
+/// occupation :   DZZ       DZY       DZX       DXX-YY    DXY
+///      0.937 :      -0.00     -0.00     -0.00     -0.87      0.50
+///            :       0.00      0.00      0.00      0.00      0.00
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Eigenvectors {
+    /// Orbital labels, e.g.: `DZZ`, `DZY`, `DZX`, `DXX-YY`, `DXY`
+    pub orbitals: Vec<String>,
+    /// Occupation eigenvalue of each eigenvector
+    pub eigenvalues: Vec<f64>,
+    /// One eigenvector per eigenvalue, components in the order of `orbitals`
+    pub vectors: ComplexMatrix,
+}
+
+/// Occupation of one channel and spin in a single `LDA+U occupation numbers` block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelOccupation {
+    pub channel: u32,
+    pub spin: u32,
+    pub total: f64,
+    pub mz: Option<f64>,
+    pub density_matrix: ComplexMatrix,
+    pub eigenvectors: Eigenvectors,
+}
+
+/// One `LDA+U occupation numbers` block, printed once before the SCF and once
+/// per SCF cycle.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OccupationBlock {
+    pub channels: Vec<ChannelOccupation>,
+}
+
+impl OccupationBlock {
+    pub fn get(&self, channel: u32, spin: u32) -> Option<&ChannelOccupation> {
+        self.channels
+            .iter()
+            .find(|occ| occ.channel == channel && occ.spin == spin)
+    }
+}
+
+/// Collect every `LDA+U occupation numbers` block in order of appearance:
+/// ```text
+///      LDA+U occupation numbers
+///      ------------------------
+/// Species   Ion   l  spin               occupancies
+/// ==================================================================
+///  -------------------------------------------
+///  Channel            1  and spin            1  :
+///  ...
+///            1           1 Total:    4.88454712510949       Mz:
+///  ...
+/// ==================================================================
+/// ```
+pub fn occupation_blocks(castep_content: &str) -> Vec<OccupationBlock> {
+    let mut blocks = Vec::new();
+    let mut lines = castep_content.lines();
+    while lines.any(|line| line.trim() == BLOCK_TITLE) {
+        blocks.push(parse_block(&mut lines));
+    }
+    blocks
+}
+
+/// Parse the lines following the block title, consuming up to the closing border.
+pub(super) fn parse_block<'a, I: Iterator<Item = &'a str>>(lines: &mut I) -> OccupationBlock {
+    let mut block = OccupationBlock::default();
+    let mut borders = 0;
+    while let Some(line) = lines.next() {
+        if line.starts_with(BLOCK_BORDER) {
+            borders += 1;
+            if borders == 2 {
+                break;
+            }
+        } else if let Some((channel, spin)) = parse_channel_header(line) {
+            block.channels.push(parse_channel(lines, channel, spin));
+        }
+    }
+    block
+}
+
+/// Parse from the line after ` Channel 1 and spin 1 :` up to ` ->>-----<<-`
+fn parse_channel<'a, I: Iterator<Item = &'a str>>(
+    lines: &mut I,
+    channel: u32,
+    spin: u32,
+) -> ChannelOccupation {
+    let mut matrix_rows = Vec::new();
+    let mut total = f64::NAN;
+    let mut mz = None;
+    let mut eigenvectors = Eigenvectors::default();
+    let mut vector_rows = Vec::new();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        if trimmed.starts_with(CHANNEL_END) {
+            break;
+        } else if let Some((_, _, value)) = parse_total(line) {
+            total = value;
+            // `Mz:` is followed by its value on the next line
+            mz = lines
+                .next()
+                .and_then(|next| next.split_whitespace().next())
+                .and_then(|value| value.parse::<f64>().ok());
+        } else if let Some(labels) = trimmed.strip_prefix("occupation :") {
+            eigenvectors.orbitals = labels.split_whitespace().map(String::from).collect();
+        } else if let Some((eigenvalue, components)) = trimmed.split_once(':') {
+            if let Ok(value) = eigenvalue.trim().parse::<f64>() {
+                eigenvectors.eigenvalues.push(value);
+            }
+            vector_rows.push(parse_row(components).unwrap_or_default());
+        } else if let Some(row) = parse_row(trimmed) {
+            matrix_rows.push(row);
+        }
+    }
+    eigenvectors.vectors = ComplexMatrix::from_interleaved_rows(vector_rows);
+    ChannelOccupation {
+        channel,
+        spin,
+        total,
+        mz,
+        density_matrix: ComplexMatrix::from_interleaved_rows(matrix_rows),
+        eigenvectors,
+    }
+}
+
+/// A line consisting of numbers only.
+fn parse_row(line: &str) -> Option<Vec<f64>> {
+    let row = line
+        .split_whitespace()
+        .map(|value| value.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    (!row.is_empty()).then_some(row)
+}
+
+/// ` Channel            1  and spin            1  :`
+fn parse_channel_header(line: &str) -> Option<(u32, u32)> {
+    let rest = line.trim().strip_prefix("Channel")?;
+    let (channel, spin) = rest.split_once("and spin")?;
+    Some((
+        channel.trim().parse().ok()?,
+        spin.trim().trim_end_matches(':').trim().parse().ok()?,
+    ))
+}
+
+/// `           1           1 Total:    4.88454712510949       Mz:`
+fn parse_total(line: &str) -> Option<(u32, u32, f64)> {
+    let (ids, rest) = line.split_once("Total:")?;
+    let mut ids = ids.split_whitespace();
+    let channel = ids.next()?.parse::<u32>().ok()?;
+    let spin = ids.next()?.parse::<u32>().ok()?;
+    let total = rest.split_whitespace().next()?.parse::<f64>().ok()?;
+    Some((channel, spin, total))
+}
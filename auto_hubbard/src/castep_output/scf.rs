@@ -0,0 +1,96 @@
+use super::occupation::{parse_block, OccupationBlock, BLOCK_TITLE};
+
+/// Suffix of each line in the SCF table.
+const SCF_MARKER: &str = "<-- SCF";
+/// Line of the energy decomposition reporting the Hubbard energy.
+const HUBBARD_ENERGY_PREFIX: &str = "+Hubbard energy";
+
+/// Everything we know about one SCF cycle.
+/// Cycle `0` is the `Initial` line of the SCF table, whose occupation is the
+/// one accepted before the SCF starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScfCycle {
+    pub index: usize,
+    /// Total energy in eV
+    pub energy: f64,
+    /// Fermi energy in eV
+    pub fermi_energy: f64,
+    /// Energy gain per atom in eV, not reported for the initial cycle
+    pub energy_gain: Option<f64>,
+    /// Hubbard energy in eV, from the energy decomposition printed before the SCF line
+    pub hubbard_energy: Option<f64>,
+    /// The occupation matrices computed in this cycle
+    pub occupations: Option<OccupationBlock>,
+}
+
+/// One line in the SCF table:
+/// ```text
+/// Initial  -3.63546574E+003  0.00000000E+000                        19.81  <-- SCF
+///       1  -3.63458626E+003 -2.08120628E+000  -4.62879811E-002      40.23  <-- SCF
+/// ```
+fn parse_scf_line(line: &str) -> Option<(usize, f64, f64, Option<f64>)> {
+    let content = line.strip_suffix(SCF_MARKER)?;
+    let values = content.split_whitespace().collect::<Vec<&str>>();
+    let index = match values.first()? {
+        &"Initial" => 0,
+        index => index.parse::<usize>().ok()?,
+    };
+    let energy = values.get(1)?.parse::<f64>().ok()?;
+    let fermi_energy = values.get(2)?.parse::<f64>().ok()?;
+    let energy_gain = (index > 0)
+        .then(|| values.get(3).and_then(|gain| gain.parse::<f64>().ok()))
+        .flatten();
+    Some((index, energy, fermi_energy, energy_gain))
+}
+
+/// `+Hubbard energy                  =         2.15238464309260458 eV`
+fn parse_hubbard_energy(line: &str) -> Option<f64> {
+    line.strip_prefix(HUBBARD_ENERGY_PREFIX)?
+        .trim()
+        .strip_prefix('=')?
+        .split_whitespace()
+        .next()?
+        .parse::<f64>()
+        .ok()
+}
+
+/// Walk through the `.castep` and assemble the SCF cycles.
+/// The occupation block of cycle `n > 0` is printed after its line in the SCF table,
+/// while the occupation of the initial cycle is printed before the table.
+pub fn scf_cycles(castep_content: &str) -> Vec<ScfCycle> {
+    let mut cycles: Vec<ScfCycle> = Vec::new();
+    let mut pending_block: Option<OccupationBlock> = None;
+    let mut pending_hubbard_energy: Option<f64> = None;
+    let mut lines = castep_content.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == BLOCK_TITLE {
+            let block = parse_block(&mut lines);
+            match cycles.last_mut() {
+                Some(cycle) if cycle.index > 0 && cycle.occupations.is_none() => {
+                    cycle.occupations = Some(block)
+                }
+                // Blocks repeated after the SCF has finished are dropped.
+                Some(_) => (),
+                None => pending_block = Some(block),
+            }
+        } else if let Some(energy) = parse_hubbard_energy(line.trim_start()) {
+            pending_hubbard_energy = Some(energy);
+        } else if let Some((index, energy, fermi_energy, energy_gain)) =
+            parse_scf_line(line.trim_end())
+        {
+            cycles.push(ScfCycle {
+                index,
+                energy,
+                fermi_energy,
+                energy_gain,
+                hubbard_energy: pending_hubbard_energy.take(),
+                occupations: if index == 0 {
+                    pending_block.take()
+                } else {
+                    None
+                },
+            });
+        }
+    }
+    cycles
+}
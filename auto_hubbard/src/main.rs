@@ -1,11 +1,10 @@
-use std::io;
-
 use crate::arguments::ReadArgs;
 use arguments::Cli;
 use clap::Parser;
 use inquire::CustomType;
 
 mod arguments;
+mod castep_output;
 mod errors;
 mod seed_settings;
 mod pipeline {
@@ -13,8 +12,8 @@ mod pipeline {
     //! Things to do:
     //! 1. Receive a folder path, check existence of `.cell` and `.param`
     //! 2. Deserialization
-    //!     1. Deserialize `.cell` with `castep_cell_data::from_str::<CellFile>`
-    //!     2. Deserialize `.param` with `castep_cell_data::from_str::<ParamFile>`
+    //!     1. Parse `.cell` with `str::parse::<CellFile>`, keeping the blocks we do not change
+    //!     2. Parse `.param` with `str::parse::<ParamFile>`, keeping the keywords we do not change
     //! 3. Create `HubbardUCell<Init>` with `HubbardUCell::<Init>::from_cell_file(cell_file: CellFile)`, create
     //!    `HubbardUParam<Init>` with `HubbardUParam::from_param(param_file:ParamFile)`
 
@@ -23,20 +22,83 @@ mod pipeline {
     use castep_cell_data::param::electronic_minimisation::ElecEnergyTol;
     use serde::{Deserialize, Serialize};
 
-    use crate::seed_settings::JobType;
+    use crate::{
+        arguments::CalcArgs,
+        seed_settings::{HubbardSelector, JobType, KpointSampling, ParamOverrides},
+    };
+    mod executor;
+    #[cfg(test)]
+    mod fixtures;
+    mod hubbard_job;
+    mod manifest;
+    mod preflight;
+    mod pseudo;
+    mod reader;
+    mod recovery;
+    mod runner;
+    mod scheduler;
+    mod seed;
     mod sequence;
+    mod watcher;
 
+    pub use executor::{cancel_on_sigint, CancelToken, Executor, JobGraph, DEFAULT_MAX_JOBS};
+    pub use hubbard_job::{HubbardJob, PerturbChain};
+    pub use manifest::{JobRecord, JobState, RunManifest, MANIFEST_FILE};
+    pub use preflight::{check_seed, Finding, Severity};
+    pub use pseudo::{PseudoKind, PseudoLibrary, DEFAULT_OTFG_LIBRARY};
+    pub use reader::ResultReader;
+    pub use recovery::{Adjustment, RecoveryContext, RecoveryPolicy, DEFAULT_MAX_RECOVERIES};
+    pub use runner::CalcRunner;
+    pub use scheduler::{
+        CastepCommand, ChildProcesses, Local, Pbs, Scheduler, Slurm, SystemRunner,
+    };
+    pub use seed::SeedFolder;
     pub use sequence::Sequence;
+    pub use watcher::{CompletionWatcher, FailureReason, JobOutcome};
+
+    /// Default of `HubArguments::init_hubbard_u`
+    pub const INIT_HUBBARD_U: f64 = 1e-8;
+    /// Default of `HubArguments::init_elec_energy_tol`
+    pub const INIT_ELEC_ENERGY_TOL: f64 = 1e-5;
+    /// Default of `HubArguments::grid_scale`
+    pub const GRID_SCALE: f64 = 1.75;
+
+    fn default_grid_scale() -> f64 {
+        GRID_SCALE
+    }
+
+    /// Keep 14 decimals as the original shell workflow did with `printf "%.14f0"`,
+    /// to get rid of floating point noise such as `0.15000000000000002`.
+    pub fn truncate_value(value: f64) -> f64 {
+        format!("{value:.14}")
+            .parse::<f64>()
+            .expect("truncated value should still be `f64`")
+    }
 
-    #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     pub struct HubArguments {
         /// A value very small and close to zero, to trick `CASTEP` into LDA+U even if
         /// U is meant to be zero
-        /// Default: 1e-7
+        /// Default: 1e-8
         init_hubbard_u: f64,
         /// The `elec_energy_tol` for the first run without perturbation
         /// Default: ElecEnergyTol {value: 1e-5, unit:None}
         init_elec_energy_tol: ElecEnergyTol,
+        /// The `elec_energy_tol` for the perturbed runs, `init_elec_energy_tol` / 10 if `None`
+        #[serde(default)]
+        perturbed_elec_energy_tol: Option<ElecEnergyTol>,
+        /// `cut_off_energy` in eV, the seed's if `None`
+        #[serde(default)]
+        cut_off_energy: Option<f64>,
+        /// Default: 1.75
+        #[serde(default = "default_grid_scale")]
+        grid_scale: f64,
+        /// Not written if `None`
+        #[serde(default)]
+        fine_grid_scale: Option<f64>,
+        /// The seed's if `None`
+        #[serde(default)]
+        max_scf_cycles: Option<u32>,
         /// Beginning of the `U` series
         u_start: f64,
         /// Increment of `U`
@@ -51,8 +113,102 @@ mod pipeline {
         alpha_end: f64,
         /// Determine `U` or `Alpha` run
         job_type: JobType,
+        /// The Hubbard sites to set U and alpha on, all sites if `None`
+        site: Option<HubbardSelector>,
+        /// The k-point sampling of every job, the one in the seed if `None`
+        kpoints: Option<KpointSampling>,
+        /// The kind of pseudopotential of every species, the `SPECIES_POT` of the seed if `None`
+        pseudo: Option<PseudoKind>,
+        /// Elements whose p shell also gets a U when the seed has no `HUBBARD_U`
+        #[serde(default)]
+        ligand_p: Vec<String>,
     }
+    impl From<&CalcArgs> for HubArguments {
+        fn from(args: &CalcArgs) -> Self {
+            Self {
+                init_hubbard_u: args.init_hubbard_u,
+                init_elec_energy_tol: ElecEnergyTol {
+                    value: args.init_elec_energy_tol,
+                    unit: None,
+                },
+                perturbed_elec_energy_tol: args
+                    .perturbed_elec_energy_tol
+                    .map(|value| ElecEnergyTol { value, unit: None }),
+                cut_off_energy: args.cut_off_energy,
+                grid_scale: args.grid_scale,
+                fine_grid_scale: args.fine_grid_scale,
+                max_scf_cycles: args.max_scf_cycles,
+                u_start: args.init_input_u,
+                u_step: args.step_u,
+                u_end: args.final_u,
+                alpha_start: args.perturb_init,
+                alpha_step: args.perturb_step,
+                alpha_end: args.perturb_final,
+                job_type: args.jobtype,
+                site: args.site.clone(),
+                kpoints: args.kpoints,
+                pseudo: args.pseudo.clone(),
+                ligand_p: args.ligand_p.clone(),
+            }
+        }
+    }
+
     impl HubArguments {
+        pub fn job_type(&self) -> JobType {
+            self.job_type
+        }
+        pub fn site(&self) -> Option<&HubbardSelector> {
+            self.site.as_ref()
+        }
+        pub fn kpoints(&self) -> Option<&KpointSampling> {
+            self.kpoints.as_ref()
+        }
+        pub fn pseudo(&self) -> Option<&PseudoKind> {
+            self.pseudo.as_ref()
+        }
+        pub fn init_hubbard_u(&self) -> f64 {
+            self.init_hubbard_u
+        }
+        pub fn ligand_p(&self) -> &[String] {
+            &self.ligand_p
+        }
+        /// `.param` settings of the unperturbed jobs
+        pub fn param_overrides(&self) -> ParamOverrides {
+            ParamOverrides {
+                elec_energy_tol: self.init_elec_energy_tol,
+                cut_off_energy: self.cut_off_energy,
+                grid_scale: self.grid_scale,
+                fine_grid_scale: self.fine_grid_scale,
+                max_scf_cycles: self.max_scf_cycles,
+            }
+        }
+        pub fn u_start(&self) -> f64 {
+            self.u_start
+        }
+        pub fn u_step(&self) -> f64 {
+            self.u_step
+        }
+        pub fn u_end(&self) -> f64 {
+            self.u_end
+        }
+        /// Number of perturbation steps for each `U`
+        pub fn perturb_times(&self) -> usize {
+            Sequence::new(self.alpha_start, self.alpha_step, self.alpha_end).count()
+        }
+        /// `[jobtype]_[init_u]_[step_u]_[final_u]_[perturb_init]_[perturb_step]_[perturb_final]_STEPS_[n]`
+        pub fn folder_suffix(&self) -> String {
+            format!(
+                "{}_{}_{}_{}_{}_{}_{}_STEPS_{}",
+                self.job_type,
+                self.u_start,
+                self.u_step,
+                self.u_end,
+                self.alpha_start,
+                self.alpha_step,
+                self.alpha_end,
+                self.perturb_times()
+            )
+        }
         fn u_values(&self) -> Arc<[f64]> {
             Sequence::new(self.u_start, self.u_step, self.u_end)
                 .map(|v| v + self.init_hubbard_u)
@@ -201,19 +357,22 @@ mod pipeline {
     }
 }
 
-fn main() -> Result<(), io::Error> {
+fn main() -> Result<(), anyhow::Error> {
     let mut cli = Cli::parse();
     match &mut cli.command_mut() {
         arguments::JobCommands::Read(args) => {
-            let set = args.set_from_folder_name();
-            if let Err(e) = set {
+            if args.set_from_manifest().is_ok() {
+                return args.invoke();
+            }
+            if let Err(e) = args.set_from_folder_name() {
                 println!("{e}");
                 let new_args = CustomType::<ReadArgs>::new("Please enter the name of the result folder (e.g.: XXX_[jobtype]_[init_input_u]_[step_u]_[final_u]_[perturb_init]_[perturb_step]_[perturb_final]_STEPS_[perturb_times])")
-                    .prompt().unwrap();
-                new_args.invoke()?;
+                    .prompt()?;
+                return new_args.invoke();
             }
             args.invoke()
         }
         arguments::JobCommands::Calc(calc_args) => calc_args.invoke(),
+        arguments::JobCommands::Check(check_args) => check_args.invoke(),
     }
 }
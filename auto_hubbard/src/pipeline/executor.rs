@@ -0,0 +1,332 @@
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{anyhow, bail};
+use signal_hook::{consts::SIGINT, iterator::Signals};
+
+use super::scheduler::ChildProcesses;
+
+/// Default of `--max-jobs`, the number of `U` the original shell workflow ran at once.
+pub const DEFAULT_MAX_JOBS: usize = 32;
+
+/// Set once the run is interrupted, checked by the executor and the waiting jobs.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// On the first `SIGINT`: cancel `token` and terminate our child processes.
+/// A second `SIGINT` exits immediately.
+pub fn cancel_on_sigint(
+    token: CancelToken,
+    children: Arc<ChildProcesses>,
+) -> Result<(), anyhow::Error> {
+    let mut signals = Signals::new([SIGINT])?;
+    thread::spawn(move || {
+        signals.forever().for_each(|_| {
+            if token.is_cancelled() {
+                std::process::exit(130);
+            }
+            println!("Interrupted: stopping the running jobs, press Ctrl-C again to quit now");
+            token.cancel();
+            children.terminate_all();
+        })
+    });
+    Ok(())
+}
+
+/// Jobs and the jobs each of them waits for.
+/// A node can only depend on nodes added before it, so the graph has no cycle.
+#[derive(Debug, Clone, Default)]
+pub struct JobGraph {
+    dependencies: Vec<Vec<usize>>,
+}
+
+impl JobGraph {
+    /// Add a node after its `dependencies` and return its index.
+    pub fn add(&mut self, dependencies: &[usize]) -> usize {
+        let index = self.dependencies.len();
+        assert!(
+            dependencies.iter().all(|&dep| dep < index),
+            "Dependencies must be added first"
+        );
+        self.dependencies.push(dependencies.to_vec());
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.dependencies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dependencies.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Progress {
+    /// Number of unfinished dependencies of each node
+    waiting_on: Vec<usize>,
+    dependents: Vec<Vec<usize>>,
+    ready: VecDeque<usize>,
+    running: usize,
+    finished: usize,
+    failures: Vec<anyhow::Error>,
+}
+
+impl Progress {
+    fn new(graph: &JobGraph) -> Self {
+        let mut dependents = vec![Vec::new(); graph.len()];
+        graph
+            .dependencies
+            .iter()
+            .enumerate()
+            .for_each(|(node, deps)| deps.iter().for_each(|&dep| dependents[dep].push(node)));
+        Self {
+            waiting_on: graph.dependencies.iter().map(Vec::len).collect(),
+            dependents,
+            ready: (0..graph.len())
+                .filter(|&node| graph.dependencies[node].is_empty())
+                .collect(),
+            running: 0,
+            finished: 0,
+            failures: Vec::new(),
+        }
+    }
+
+    /// The dependents become ready before the other waiting nodes, so with one
+    /// job at a time each chain runs to the end before the next one starts.
+    fn finish(&mut self, node: usize) {
+        self.finished += 1;
+        let newly_ready = self.dependents[node]
+            .iter()
+            .filter_map(|&dependent| {
+                self.waiting_on[dependent] -= 1;
+                (self.waiting_on[dependent] == 0).then_some(dependent)
+            })
+            .collect::<Vec<usize>>();
+        newly_ready
+            .into_iter()
+            .rev()
+            .for_each(|dependent| self.ready.push_front(dependent));
+    }
+}
+
+/// Runs the nodes of a `JobGraph` on at most `max_jobs` threads, each node once all
+/// its dependencies succeeded. The dependents of a failed node are skipped, the
+/// others carry on.
+#[derive(Debug, Clone)]
+pub struct Executor {
+    max_jobs: usize,
+    cancel: CancelToken,
+}
+
+impl Executor {
+    pub fn new(max_jobs: usize, cancel: CancelToken) -> Self {
+        Self {
+            max_jobs: max_jobs.max(1),
+            cancel,
+        }
+    }
+
+    pub fn run<F>(&self, graph: &JobGraph, task: F) -> Result<(), anyhow::Error>
+    where
+        F: Fn(usize) -> Result<(), anyhow::Error> + Sync,
+    {
+        let progress = Mutex::new(Progress::new(graph));
+        let wake = Condvar::new();
+        thread::scope(|s| {
+            (0..self.max_jobs.min(graph.len())).for_each(|_| {
+                s.spawn(|| self.worker(&progress, &wake, &task));
+            })
+        });
+        let progress = progress
+            .into_inner()
+            .map_err(|_| anyhow!("A job thread panicked"))?;
+        if self.cancel.is_cancelled() {
+            bail!(
+                "Interrupted after {} of {} jobs",
+                progress.finished,
+                graph.len()
+            );
+        }
+        if !progress.failures.is_empty() {
+            let skipped = graph.len() - progress.finished - progress.failures.len();
+            let reasons = progress
+                .failures
+                .iter()
+                .map(|e| format!("  {e}"))
+                .collect::<Vec<String>>()
+                .join("\n");
+            bail!(
+                "{} jobs failed, {skipped} jobs depending on them were skipped:\n{reasons}",
+                progress.failures.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn worker<F>(&self, progress: &Mutex<Progress>, wake: &Condvar, task: &F)
+    where
+        F: Fn(usize) -> Result<(), anyhow::Error> + Sync,
+    {
+        loop {
+            let Ok(mut state) = progress.lock() else {
+                return;
+            };
+            let node = loop {
+                if self.cancel.is_cancelled() || (state.ready.is_empty() && state.running == 0) {
+                    wake.notify_all();
+                    return;
+                }
+                if let Some(node) = state.ready.pop_front() {
+                    state.running += 1;
+                    break node;
+                }
+                state = match wake.wait(state) {
+                    Ok(state) => state,
+                    Err(_) => return,
+                };
+            };
+            drop(state);
+            // A panicking job fails like any other, so the others still see it finish
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| task(node))).unwrap_or_else(|payload| {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    Err(anyhow!("job {node} panicked: {message}"))
+                });
+            let Ok(mut state) = progress.lock() else {
+                return;
+            };
+            state.running -= 1;
+            match result {
+                Ok(_) => state.finish(node),
+                Err(e) => state.failures.push(e),
+            }
+            wake.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use anyhow::bail;
+
+    use super::{CancelToken, Executor, JobGraph};
+
+    /// Two chains: 0 -> (1, 2) and 3 -> (4, 5)
+    fn chains() -> JobGraph {
+        let mut graph = JobGraph::default();
+        (0..2).for_each(|_| {
+            let init = graph.add(&[]);
+            graph.add(&[init]);
+            graph.add(&[init]);
+        });
+        graph
+    }
+
+    #[test]
+    fn serial_runs_chain_by_chain() {
+        let order = Mutex::new(Vec::new());
+        Executor::new(1, CancelToken::default())
+            .run(&chains(), |node| {
+                order.lock().unwrap().push(node);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(order.into_inner().unwrap(), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn parallel_respects_limit_and_dependencies() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let done = Mutex::new(Vec::new());
+        Executor::new(2, CancelToken::default())
+            .run(&chains(), |node| {
+                if node % 3 != 0 {
+                    assert!(done.lock().unwrap().contains(&(node - node % 3)));
+                }
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                done.lock().unwrap().push(node);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(done.into_inner().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn failure_skips_dependents_only() {
+        let ran = Mutex::new(Vec::new());
+        let result = Executor::new(4, CancelToken::default()).run(&chains(), |node| {
+            ran.lock().unwrap().push(node);
+            if node == 0 {
+                bail!("job 0 failed");
+            }
+            Ok(())
+        });
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("1 jobs failed, 2 jobs depending on them were skipped"));
+        let mut ran = ran.into_inner().unwrap();
+        ran.sort();
+        assert_eq!(ran, vec![0, 3, 4, 5]);
+    }
+
+    #[test]
+    fn panic_fails_the_job() {
+        let result = Executor::new(2, CancelToken::default()).run(&chains(), |node| {
+            if node == 3 {
+                panic!("broken job");
+            }
+            Ok(())
+        });
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("1 jobs failed, 2 jobs depending on them were skipped"));
+        assert!(message.contains("job 3 panicked: broken job"), "{message}");
+    }
+
+    #[test]
+    fn cancel_stops_new_jobs() {
+        let token = CancelToken::default();
+        let count = AtomicUsize::new(0);
+        let result = Executor::new(1, token.clone()).run(&chains(), |_| {
+            count.fetch_add(1, Ordering::SeqCst);
+            token.cancel();
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
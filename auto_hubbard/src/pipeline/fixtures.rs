@@ -0,0 +1,49 @@
+//! Setup shared by the pipeline tests, on the seed in `sh/test`
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clap::Parser;
+
+use crate::arguments::{Cli, JobCommands};
+
+use super::{HubArguments, SeedFolder};
+
+/// `sh/test`, holding `GDY_111_Fe_U.cell` and `GDY_111_Fe_U.param`
+pub fn test_seed_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .join("sh/test")
+}
+
+pub fn test_seed() -> SeedFolder {
+    SeedFolder::load(test_seed_path()).unwrap()
+}
+
+/// A new empty folder in the temp dir, named after `name`, the process and a counter,
+/// so that neither the tests running in parallel nor two runs of the suite share one
+pub fn test_dir(name: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "{name}_{}_{}",
+        process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The arguments of `auto_hubbard calc sh/test [args]`, e.g.: `&["u", "--dry-run"]`
+pub fn calc_args(args: &[&str]) -> HubArguments {
+    let cli = Cli::parse_from(["auto_hubbard", "calc", "sh/test"].iter().chain(args));
+    let JobCommands::Calc(calc_args) = cli.command else {
+        unreachable!()
+    };
+    HubArguments::from(&calc_args)
+}
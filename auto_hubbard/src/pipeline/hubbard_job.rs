@@ -0,0 +1,379 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::seed_settings::{CellFile, HubbardUCell, ParamFile};
+
+use super::{
+    seed::{copy_aux_files, SeedFolder, ARCHIVE_EXTENSION},
+    truncate_value, HubArguments, HubUSetup, Sequence,
+};
+
+/// A single `CASTEP` job: where it lives and the inputs it runs with.
+#[derive(Debug, Clone)]
+pub struct HubbardJob {
+    /// Relative to the result folder, e.g.: `U_0_u` or `U_0_u/U_0_u_1`
+    dir: PathBuf,
+    seed_name: String,
+    u_value: f64,
+    alpha_value: f64,
+    cell: CellFile,
+    param: ParamFile,
+    /// Pseudopotential files to copy into the job folder
+    potentials: Vec<PathBuf>,
+}
+
+impl HubbardJob {
+    pub fn new(
+        dir: PathBuf,
+        seed_name: &str,
+        u_value: f64,
+        alpha_value: f64,
+        cell: CellFile,
+        param: ParamFile,
+    ) -> Self {
+        Self {
+            dir,
+            seed_name: seed_name.to_string(),
+            u_value,
+            alpha_value,
+            cell,
+            param,
+            potentials: Vec::new(),
+        }
+    }
+
+    /// The same job copying the pseudopotential files `potentials`
+    pub fn with_potentials(self, potentials: &[PathBuf]) -> Self {
+        Self {
+            potentials: potentials.to_vec(),
+            ..self
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn seed_name(&self) -> &str {
+        &self.seed_name
+    }
+
+    pub fn u_value(&self) -> f64 {
+        self.u_value
+    }
+
+    pub fn alpha_value(&self) -> f64 {
+        self.alpha_value
+    }
+
+    pub fn cell(&self) -> &CellFile {
+        &self.cell
+    }
+
+    pub fn param(&self) -> &ParamFile {
+        &self.param
+    }
+
+    /// The name recorded in the result csv, e.g.: `./U_0_u/U_0_u_1/GDY_111_Fe_U`
+    pub fn job_name(&self) -> String {
+        format!("./{}/{}", self.dir.display(), self.seed_name)
+    }
+
+    /// Path to the `.castep` output of this job.
+    pub fn castep_path(&self, result_root: &Path) -> PathBuf {
+        result_root
+            .join(&self.dir)
+            .join(format!("{}.castep", self.seed_name))
+    }
+
+    /// Create the job folder, copy the auxiliary files from `source_dir`
+    /// and the pseudopotentials, and write the `.cell` and `.param`.
+    pub fn write_inputs(&self, result_root: &Path, source_dir: &Path) -> Result<(), anyhow::Error> {
+        self.write_aux_files(result_root, source_dir)?;
+        let dest = result_root.join(&self.dir);
+        let cell_path = dest.join(format!("{}.cell", self.seed_name));
+        fs::write(&cell_path, self.cell.to_cell_file())
+            .with_context(|| format!("Failed to write {}", cell_path.display()))?;
+        let param_path = dest.join(format!("{}.param", self.seed_name));
+        fs::write(&param_path, self.param.to_param_file())
+            .with_context(|| format!("Failed to write {}", param_path.display()))
+    }
+
+    /// Create the job folder, copy the auxiliary files from `source_dir`
+    /// and the pseudopotentials, leaving the `.cell` and `.param` as they are.
+    pub fn write_aux_files(
+        &self,
+        result_root: &Path,
+        source_dir: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let dest = result_root.join(&self.dir);
+        copy_aux_files(source_dir, &dest)?;
+        self.potentials.iter().try_for_each(|path| {
+            let file_name = path.file_name().expect("Files always have a name");
+            fs::copy(path, dest.join(file_name))
+                .map(|_| ())
+                .with_context(|| format!("Failed to copy {}", path.display()))
+        })
+    }
+
+    /// Whether the `.cell` and `.param` of this job have been written, e.g. by `calc --dry-run`
+    pub fn has_inputs(&self, result_root: &Path) -> bool {
+        let dest = result_root.join(&self.dir);
+        ["cell", "param"]
+            .iter()
+            .all(|ext| dest.join(format!("{}.{ext}", self.seed_name)).exists())
+    }
+
+    /// The same job run with another `.param`
+    pub fn with_param(&self, param: ParamFile) -> Self {
+        Self {
+            param,
+            ..self.clone()
+        }
+    }
+
+    /// Move the `.castep` and `.err` of an earlier attempt out of the way,
+    /// since `CASTEP` appends to an existing `.castep`.
+    /// They are kept as `[file].attempt_[n]`.
+    pub fn archive_outputs(&self, result_root: &Path, attempt: u32) -> Result<(), anyhow::Error> {
+        let dest = result_root.join(&self.dir);
+        let prefix = format!("{}.", self.seed_name);
+        fs::read_dir(&dest)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+                    && path
+                        .extension()
+                        .is_some_and(|ext| ext == "castep" || ext == "err")
+            })
+            .try_for_each(|path| {
+                let mut archived = path.clone().into_os_string();
+                archived.push(format!(".{ARCHIVE_EXTENSION}{attempt}"));
+                fs::rename(&path, &archived)
+                    .with_context(|| format!("Failed to archive {}", path.display()))
+            })
+    }
+
+    /// The `.check` written by `CASTEP` in the job folder
+    pub fn check_path(&self, result_root: &Path) -> PathBuf {
+        result_root
+            .join(&self.dir)
+            .join(format!("{}.check", self.seed_name))
+    }
+
+    /// Rewrite the inputs to continue from the `.check` left by an earlier attempt.
+    /// The auxiliary files are not copied again, since they would replace that `.check`.
+    pub fn write_continuation_inputs(&self, result_root: &Path) -> Result<(), anyhow::Error> {
+        let dest = result_root.join(&self.dir);
+        let cell_path = dest.join(format!("{}.cell", self.seed_name));
+        fs::write(&cell_path, self.cell.to_cell_file())
+            .with_context(|| format!("Failed to write {}", cell_path.display()))?;
+        let param_path = dest.join(format!("{}.param", self.seed_name));
+        fs::write(&param_path, self.param.continued().to_param_file())
+            .with_context(|| format!("Failed to write {}", param_path.display()))
+    }
+}
+
+/// The unperturbed job of one input `U` followed by its perturbation steps.
+/// Every perturbation step continues from the `.check` of the unperturbed job.
+#[derive(Debug, Clone)]
+pub struct PerturbChain {
+    u_input: f64,
+    init_job: HubbardJob,
+    perturbed_jobs: Vec<HubbardJob>,
+}
+
+impl PerturbChain {
+    pub fn u_input(&self) -> f64 {
+        self.u_input
+    }
+
+    pub fn init_job(&self) -> &HubbardJob {
+        &self.init_job
+    }
+
+    pub fn perturbed_jobs(&self) -> &[HubbardJob] {
+        &self.perturbed_jobs
+    }
+}
+
+/// One line per job with its folder, U, alpha, `elec_energy_tol`, `continuation`
+/// and the job it continues from.
+pub fn plan_table(chains: &[PerturbChain]) -> String {
+    let header = [
+        "job",
+        "U",
+        "alpha",
+        "elec_energy_tol",
+        "continuation",
+        "depends on",
+    ]
+    .map(String::from);
+    let row = |job: &HubbardJob, dependency: Option<&HubbardJob>| {
+        [
+            job.dir().display().to_string(),
+            job.u_value().to_string(),
+            job.alpha_value().to_string(),
+            format!("{:e}", job.param().elec_energy_tol().value),
+            job.param().continuation().unwrap_or("-").to_string(),
+            dependency.map_or("-".to_string(), |dependency| {
+                dependency.dir().display().to_string()
+            }),
+        ]
+    };
+    let rows = std::iter::once(header)
+        .chain(chains.iter().flat_map(|chain| {
+            std::iter::once(row(chain.init_job(), None)).chain(
+                chain
+                    .perturbed_jobs()
+                    .iter()
+                    .map(|job| row(job, Some(chain.init_job()))),
+            )
+        }))
+        .collect::<Vec<[String; 6]>>();
+    let widths = (0..6)
+        .map(|i| rows.iter().map(|row| row[i].len()).max().unwrap_or(0))
+        .collect::<Vec<usize>>();
+    rows.iter()
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect::<Vec<String>>()
+                .join("  ");
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+impl HubArguments {
+    /// Build the jobs of every input `U`:
+    /// `U_[u]_[jobtype]` and `U_[u]_[jobtype]/U_[u]_[jobtype]_[step]`
+    pub fn perturb_chains(&self, seed: &SeedFolder) -> Result<Vec<PerturbChain>, anyhow::Error> {
+        let param_before = seed.param().param_before_perturb(&self.param_overrides());
+        let param_after = param_before.param_after_perturb(self.perturbed_elec_energy_tol);
+        let seed_cell = match self.kpoints() {
+            Some(sampling) => {
+                HubbardUCell::from_cell_file(seed.cell().cell.with_kpoint_sampling(sampling))
+            }
+            None => seed.cell().clone(),
+        };
+        Sequence::new(self.u_start, self.u_step, self.u_end)
+            .map(|u_input| -> Result<PerturbChain, anyhow::Error> {
+                let (u_value, alpha_value): (f64, f64) =
+                    HubUSetup::init(self.init_hubbard_u, self.job_type)
+                        .set_u(u_input)
+                        .map(|v| truncate_value(*v))
+                        .into();
+                let cell_before = seed_cell.cell_before(u_value, alpha_value, self.site())?;
+                let init_dir = PathBuf::from(format!("U_{u_input}_{}", self.job_type));
+                let init_job = HubbardJob::new(
+                    init_dir.clone(),
+                    seed.seed_name(),
+                    u_value,
+                    alpha_value,
+                    cell_before.cell.clone(),
+                    param_before.param.clone(),
+                )
+                .with_potentials(seed.potentials());
+                let perturbed_jobs =
+                    Sequence::new(self.alpha_start, self.alpha_step, self.alpha_end)
+                        .enumerate()
+                        .map(|(i, delta_alpha)| -> Result<HubbardJob, anyhow::Error> {
+                            let perturbed_alpha = truncate_value(alpha_value + delta_alpha);
+                            let perturbed_cell =
+                                cell_before.update_alpha(perturbed_alpha, self.site())?;
+                            Ok(HubbardJob::new(
+                                init_dir.join(format!("U_{u_input}_{}_{}", self.job_type, i + 1)),
+                                seed.seed_name(),
+                                u_value,
+                                perturbed_alpha,
+                                perturbed_cell.cell,
+                                param_after.param.clone(),
+                            )
+                            .with_potentials(seed.potentials()))
+                        })
+                        .collect::<Result<Vec<HubbardJob>, _>>()?;
+                Ok(PerturbChain {
+                    u_input,
+                    init_job,
+                    perturbed_jobs,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use crate::pipeline::{
+        fixtures::{calc_args, test_dir, test_seed, test_seed_path},
+        SeedFolder, INIT_HUBBARD_U,
+    };
+
+    use super::plan_table;
+
+    #[test]
+    fn chains_from_test_seed() {
+        let seed = test_seed();
+        let hub_args = calc_args(&["u"]);
+        let chains = hub_args.perturb_chains(&seed).unwrap();
+        assert_eq!(chains.len(), 7);
+        let chain = &chains[1];
+        assert_eq!(chain.init_job().dir(), Path::new("U_2_u"));
+        assert_eq!(chain.init_job().u_value(), 2.0 + INIT_HUBBARD_U);
+        assert_eq!(chain.perturbed_jobs().len(), 5);
+        let last = chain.perturbed_jobs().last().unwrap();
+        assert_eq!(last.job_name(), "./U_2_u/U_2_u_5/GDY_111_Fe_U");
+        assert_eq!(last.alpha_value(), 0.25000001);
+    }
+
+    #[test]
+    fn plan_of_test_seed() {
+        let seed = test_seed();
+        let chains = calc_args(&["u"]).perturb_chains(&seed).unwrap();
+        let plan = plan_table(&chains);
+        let lines = plan.lines().collect::<Vec<&str>>();
+        // Header, then 7 U with 1 + 5 jobs each
+        assert_eq!(lines.len(), 1 + 7 * 6);
+        assert!(lines[0].starts_with("job"));
+        let init = lines[7].split_whitespace().collect::<Vec<&str>>();
+        assert_eq!(
+            init,
+            ["U_2_u", "2.00000001", "0.00000001", "1e-5", "-", "-"]
+        );
+        let perturbed = lines[8].split_whitespace().collect::<Vec<&str>>();
+        assert_eq!(
+            perturbed[..3],
+            ["U_2_u/U_2_u_1", "2.00000001", "0.05000001"]
+        );
+        assert!((perturbed[3].parse::<f64>().unwrap() - 1e-6).abs() < 1e-20);
+        assert_eq!(perturbed[4..], ["default", "U_2_u"]);
+    }
+
+    #[test]
+    fn prepared_inputs_are_kept() {
+        let seed_path = test_seed_path();
+        let seed = SeedFolder::load(&seed_path).unwrap();
+        let chains = calc_args(&["u"]).perturb_chains(&seed).unwrap();
+        let job = chains[0].init_job();
+        let root = test_dir("auto_hubbard_prepared_inputs");
+        assert!(!job.has_inputs(&root));
+        job.write_inputs(&root, &seed_path).unwrap();
+        assert!(job.has_inputs(&root));
+        let cell_path = root.join(job.dir()).join("GDY_111_Fe_U.cell");
+        fs::write(&cell_path, "edited").unwrap();
+        job.write_aux_files(&root, &seed_path).unwrap();
+        assert_eq!(fs::read_to_string(&cell_path).unwrap(), "edited");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
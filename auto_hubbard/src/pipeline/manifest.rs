@@ -0,0 +1,307 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{HubArguments, PerturbChain};
+
+/// File name of the manifest inside the result folder, shared with `hubbard_data`
+/// which reads the perturbation value back from it
+pub use hubbard_data_args::MANIFEST_FILE;
+
+/// Seconds since the unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Hex encoded sha256 of the seed `.cell` and `.param` contents
+pub fn seed_hash(cell: &str, param: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(cell.as_bytes());
+    hasher.update(param.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Pending,
+    /// Waiting in the batch queue
+    Submitted,
+    Running,
+    Finished,
+    Failed,
+}
+
+impl Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobState::Pending => f.write_str("pending"),
+            JobState::Submitted => f.write_str("submitted"),
+            JobState::Running => f.write_str("running"),
+            JobState::Finished => f.write_str("finished"),
+            JobState::Failed => f.write_str("failed"),
+        }
+    }
+}
+
+/// Status of one job in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobRecord {
+    /// Same as `HubbardJob::job_name`
+    pub name: String,
+    pub u: f64,
+    pub alpha: f64,
+    pub state: JobState,
+    /// Unix time of the last state change
+    pub updated: u64,
+    /// Number of times the job has been started
+    pub attempts: u32,
+    /// Id given by the batch system on the last submission
+    pub scheduler_id: Option<String>,
+    /// Unix time
+    pub submitted: Option<u64>,
+    /// Unix time
+    pub started: Option<u64>,
+    /// Unix time
+    pub ended: Option<u64>,
+    /// The `.param` adjustments of each recovery, see `RecoveryPolicy`
+    #[serde(default)]
+    pub recoveries: Vec<String>,
+}
+
+/// Everything needed to read back a run, written to `run.toml` in the result folder.
+/// Replaces the parameters encoded in the result folder name, which can not be
+/// parsed back when the seed name holds underscores or negative numbers.
+/// `hubbard_data_args::RunManifest` reads a part of it, keep the two in step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunManifest {
+    seed_name: String,
+    /// See `seed_hash`
+    seed_hash: String,
+    castep_command: String,
+    /// Unix time
+    created: u64,
+    /// Unix time
+    updated: u64,
+    hub_arguments: HubArguments,
+    jobs: Vec<JobRecord>,
+}
+
+impl RunManifest {
+    pub fn new(
+        seed_name: &str,
+        seed_hash: &str,
+        castep_command: &str,
+        hub_arguments: &HubArguments,
+        chains: &[PerturbChain],
+    ) -> Self {
+        let now = timestamp();
+        let jobs = chains
+            .iter()
+            .flat_map(|chain| std::iter::once(chain.init_job()).chain(chain.perturbed_jobs()))
+            .map(|job| JobRecord {
+                name: job.job_name(),
+                u: job.u_value(),
+                alpha: job.alpha_value(),
+                state: JobState::Pending,
+                updated: now,
+                attempts: 0,
+                scheduler_id: None,
+                submitted: None,
+                started: None,
+                ended: None,
+                recoveries: Vec::new(),
+            })
+            .collect();
+        Self {
+            seed_name: seed_name.to_string(),
+            seed_hash: seed_hash.to_string(),
+            castep_command: castep_command.to_string(),
+            created: now,
+            updated: now,
+            hub_arguments: hub_arguments.clone(),
+            jobs,
+        }
+    }
+
+    pub fn path<P: AsRef<Path>>(result_root: P) -> PathBuf {
+        result_root.as_ref().join(MANIFEST_FILE)
+    }
+
+    pub fn load<P: AsRef<Path>>(result_root: P) -> Result<Self, anyhow::Error> {
+        let path = Self::path(result_root);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, result_root: P) -> Result<(), anyhow::Error> {
+        let path = Self::path(result_root);
+        fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn seed_name(&self) -> &str {
+        &self.seed_name
+    }
+
+    pub fn seed_hash(&self) -> &str {
+        &self.seed_hash
+    }
+
+    pub fn castep_command(&self) -> &str {
+        &self.castep_command
+    }
+
+    pub fn hub_arguments(&self) -> &HubArguments {
+        &self.hub_arguments
+    }
+
+    pub fn jobs(&self) -> &[JobRecord] {
+        &self.jobs
+    }
+
+    pub fn job(&self, job_name: &str) -> Option<&JobRecord> {
+        self.jobs.iter().find(|record| record.name == job_name)
+    }
+
+    /// Whether any job has left `Pending`, so a new run would throw its results away
+    pub fn has_started(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|record| record.state != JobState::Pending)
+    }
+
+    /// Whether the manifest was written by `calc --dry-run` for the same seed, command
+    /// and arguments: no job has been started yet, so a new run can take over the tree.
+    pub fn is_prepared(
+        &self,
+        seed_hash: &str,
+        castep_command: &str,
+        hub_arguments: &HubArguments,
+    ) -> bool {
+        self.seed_hash == seed_hash
+            && self.castep_command == castep_command
+            && self.hub_arguments == *hub_arguments
+            && !self.has_started()
+    }
+
+    /// Record the new state of the job named `job_name`, and when it happened
+    pub fn set_state(&mut self, job_name: &str, state: JobState) {
+        let now = timestamp();
+        if let Some(record) = self.jobs.iter_mut().find(|record| record.name == job_name) {
+            match state {
+                JobState::Pending => (),
+                JobState::Submitted => {
+                    record.attempts += 1;
+                    record.submitted = Some(now);
+                    record.started = None;
+                    record.ended = None;
+                }
+                JobState::Running => {
+                    // A batch job has been counted on submission
+                    if record.state != JobState::Submitted {
+                        record.attempts += 1;
+                    }
+                    record.started = Some(now);
+                    record.ended = None;
+                }
+                JobState::Finished | JobState::Failed => record.ended = Some(now),
+            }
+            record.state = state;
+            record.updated = now;
+        }
+        self.updated = now;
+    }
+
+    pub fn add_recovery(&mut self, job_name: &str, adjustments: String) {
+        if let Some(record) = self.jobs.iter_mut().find(|record| record.name == job_name) {
+            record.recoveries.push(adjustments);
+        }
+    }
+
+    pub fn set_scheduler_id(&mut self, job_name: &str, id: Option<String>) {
+        if let Some(record) = self.jobs.iter_mut().find(|record| record.name == job_name) {
+            record.scheduler_id = id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pipeline::fixtures::{calc_args, test_dir, test_seed};
+
+    use super::{JobState, RunManifest};
+
+    #[test]
+    fn manifest_round_trip() {
+        let seed = test_seed();
+        let hub_args = calc_args(&["u", "--init-input-u", "-2", "--final-u", "2"]);
+        let chains = hub_args.perturb_chains(&seed).unwrap();
+        let mut manifest = RunManifest::new(
+            seed.seed_name(),
+            seed.hash(),
+            "castep.serial",
+            &hub_args,
+            &chains,
+        );
+        let first = chains[0].init_job().job_name();
+        manifest.set_state(&first, JobState::Submitted);
+        manifest.set_state(&first, JobState::Running);
+        manifest.set_state(&first, JobState::Finished);
+        assert_eq!(manifest.job(&first).unwrap().attempts, 1);
+        let dir = test_dir("auto_hubbard_manifest_test");
+        manifest.save(&dir).unwrap();
+        let loaded = RunManifest::load(&dir).unwrap();
+        assert_eq!(loaded.seed_hash(), seed.hash());
+        // A negative `U` can not be parsed back from the folder name
+        assert_eq!(loaded.hub_arguments().u_start(), -2.0);
+        assert_eq!(loaded.hub_arguments().perturb_times(), 5);
+        assert_eq!(loaded.jobs(), manifest.jobs());
+        assert_eq!(loaded.jobs()[0].state, JobState::Finished);
+        // The part read by `hubbard_data`
+        let analysis = hubbard_data_args::RunManifest::load(&dir).unwrap();
+        assert_eq!(analysis.job_type(), "u");
+        assert_eq!(analysis.perturb_step(), 0.05);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prepared_manifest() {
+        let seed = test_seed();
+        let hub_args = calc_args(&["u", "--dry-run"]);
+        let chains = hub_args.perturb_chains(&seed).unwrap();
+        let mut manifest = RunManifest::new(
+            seed.seed_name(),
+            seed.hash(),
+            "castep.serial",
+            &hub_args,
+            &chains,
+        );
+        assert!(manifest.is_prepared(seed.hash(), "castep.serial", &hub_args));
+        assert!(!manifest.is_prepared("changed", "castep.serial", &hub_args));
+        assert!(!manifest.is_prepared(seed.hash(), "castep.mpi", &hub_args));
+        let other_args = calc_args(&["u", "--kpoints", "2x2x1"]);
+        assert!(!manifest.is_prepared(seed.hash(), "castep.serial", &other_args));
+        let first = chains[0].init_job().job_name();
+        manifest.set_state(&first, JobState::Submitted);
+        assert!(manifest.has_started());
+        assert!(!manifest.is_prepared(seed.hash(), "castep.serial", &hub_args));
+    }
+}
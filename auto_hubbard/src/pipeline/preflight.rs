@@ -0,0 +1,513 @@
+use std::{collections::BTreeMap, fmt::Display, fs::read_to_string, path::Path, str::FromStr};
+
+use castep_cell_data::cell::species::AtomHubbardU;
+use castep_periodic_table::element::ElementSymbol;
+
+use crate::seed_settings::{
+    element_of, CellFile, HubbardBlock, HubbardItem, HubbardSelector, ParamFile,
+};
+
+use super::{
+    pseudo::{is_potential_file, locate_potentials, PseudoLibrary},
+    seed::find_by_extension,
+    INIT_HUBBARD_U,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Worth knowing, nothing to fix
+    Info,
+    /// The run starts, but probably not as intended
+    Warning,
+    /// The run fails or gives meaningless results
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => f.write_str("info"),
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// One problem found in a seed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:>7}: {}", self.severity, self.message)
+    }
+}
+
+/// Look for the mistakes in a seed folder which would only show after the first job:
+/// unreadable `.cell`/`.param`, settings unfit for the linear response,
+/// species disagreeing between the blocks, missing pseudopotential files,
+/// and a `site` matching nothing.
+pub fn check_seed(
+    path: &Path,
+    pseudo_lib: Option<&Path>,
+    site: Option<&HubbardSelector>,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let cell_path = match find_by_extension(path, "cell") {
+        Ok(cell_path) => cell_path,
+        Err(e) => return vec![Finding::new(Severity::Error, e.to_string())],
+    };
+    let seed_name = cell_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let param_path = path.join(format!("{seed_name}.param"));
+    match read_to_string(&param_path) {
+        Ok(content) => match content.parse::<ParamFile>() {
+            Ok(param) => findings.extend(check_param(&param, path, &seed_name)),
+            Err(e) => findings.push(Finding::new(
+                Severity::Error,
+                format!("{}: {e}", param_path.display()),
+            )),
+        },
+        Err(e) => findings.push(Finding::new(
+            Severity::Error,
+            format!("Failed to read {}: {e}", param_path.display()),
+        )),
+    }
+    match read_to_string(&cell_path).map(|content| content.parse::<CellFile>()) {
+        Ok(Ok(cell)) => {
+            let library = match pseudo_lib.map(PseudoLibrary::new).transpose() {
+                Ok(library) => library,
+                Err(e) => {
+                    findings.push(Finding::new(Severity::Error, e.to_string()));
+                    None
+                }
+            };
+            findings.extend(check_cell(&cell, path, library.as_ref(), site));
+        }
+        Ok(Err(e)) => findings.push(Finding::new(
+            Severity::Error,
+            format!("{}: {e}", cell_path.display()),
+        )),
+        Err(e) => findings.push(Finding::new(
+            Severity::Error,
+            format!("Failed to read {}: {e}", cell_path.display()),
+        )),
+    }
+    findings
+}
+
+fn check_param(param: &ParamFile, seed_dir: &Path, seed_name: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let is_true = |value: &str| matches!(value.to_lowercase().as_str(), "true" | "t" | "1");
+    if !param.keyword_value("spin_polarized").is_some_and(is_true) {
+        findings.push(Finding::new(
+            Severity::Error,
+            "`spin_polarized` is not true, the occupation response needs both spins",
+        ));
+    }
+    if let Some(task) = param
+        .keyword_value("task")
+        .filter(|task| !task.eq_ignore_ascii_case("singlepoint"))
+    {
+        findings.push(Finding::new(
+            Severity::Warning,
+            format!("`task : {task}`, the linear response expects a `SinglePoint`"),
+        ));
+    }
+    if let Some(continuation) = param.continuation() {
+        let check = match continuation.eq_ignore_ascii_case("default") {
+            true => seed_dir.join(format!("{seed_name}.check")),
+            false => seed_dir.join(continuation),
+        };
+        findings.push(match check.exists() {
+            true => Finding::new(
+                Severity::Info,
+                format!(
+                    "`continuation : {continuation}` is dropped, the unperturbed jobs start from scratch"
+                ),
+            ),
+            false => Finding::new(
+                Severity::Warning,
+                format!(
+                    "`continuation : {continuation}` refers to {}, which does not exist",
+                    check.display()
+                ),
+            ),
+        });
+    }
+    findings
+}
+
+/// Number of atoms of each species in the positions block, `CASTEP` numbering the ions per species
+fn species_counts(cell: &CellFile) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    cell.geometry()
+        .atoms()
+        .iter()
+        .for_each(|atom| *counts.entry(atom.species.clone()).or_insert(0) += 1);
+    counts
+}
+
+/// Sites of a Hubbard block naming a species or an ion absent from the positions
+fn check_hubbard_sites(
+    block_name: &str,
+    lines: &[AtomHubbardU],
+    counts: &BTreeMap<String, usize>,
+) -> Vec<Finding> {
+    lines
+        .iter()
+        .filter_map(|atom| {
+            let species = atom.species.to_string();
+            if ElementSymbol::from_str(&element_of(&species)).is_err() {
+                return Some(Finding::new(
+                    Severity::Error,
+                    format!("{block_name} lists `{species}`, which is not an element or a label of one"),
+                ));
+            }
+            match (counts.get(&species), atom.ion_number) {
+                (None, _) => Some(Finding::new(
+                    Severity::Error,
+                    format!("{block_name} lists {species}, which is not in the positions"),
+                )),
+                (Some(count), Some(ion)) if ion as usize > *count => Some(Finding::new(
+                    Severity::Error,
+                    format!(
+                        "{block_name} lists {species} {ion}, but there are {count} {species} in the positions"
+                    ),
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Entries of a species block naming species absent from the positions
+fn check_species_block(name: &str, species: &[String], positions: &[String]) -> Vec<Finding> {
+    species
+        .iter()
+        .filter(|species| !positions.contains(species))
+        .map(|species| {
+            Finding::new(
+                Severity::Warning,
+                format!("{name} lists {species}, which is not in the positions"),
+            )
+        })
+        .collect()
+}
+
+fn check_cell(
+    cell: &CellFile,
+    seed_dir: &Path,
+    library: Option<&PseudoLibrary>,
+    site: Option<&HubbardSelector>,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let positions =
+        cell.geometry()
+            .atoms()
+            .iter()
+            .fold(Vec::<String>::new(), |mut positions, atom| {
+                if !positions.contains(&atom.species) {
+                    positions.push(atom.species.clone());
+                }
+                positions
+            });
+    if positions.is_empty() {
+        findings.push(Finding::new(
+            Severity::Error,
+            "There is no atom in the positions",
+        ));
+    }
+    let counts = species_counts(cell);
+    // Species blocks
+    let potentials = cell.species_potentials();
+    let pot_species = potentials
+        .iter()
+        .map(|(species, _)| species.clone())
+        .collect::<Vec<String>>();
+    findings.extend(check_species_block("SPECIES_POT", &pot_species, &positions));
+    positions
+        .iter()
+        .filter(|species| !pot_species.contains(species))
+        .for_each(|species| {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!("{species} has no SPECIES_POT entry, `CASTEP` picks its default potential"),
+            ))
+        });
+    ["SPECIES_MASS", "SPECIES_LCAO_STATES"]
+        .iter()
+        .for_each(|name| {
+            if let Some(species) = cell.block_species(name) {
+                findings.extend(check_species_block(name, &species, &positions));
+            }
+        });
+    // Pseudopotential files
+    potentials
+        .iter()
+        .filter(|(_, potential)| is_potential_file(potential))
+        .for_each(|entry| {
+            if let Err(e) = locate_potentials(&[entry.clone()], seed_dir, library) {
+                findings.push(Finding::new(Severity::Error, e.to_string()));
+            }
+        });
+    // Hubbard blocks
+    let generated = match cell.hubbard_u() {
+        Some(_) => None,
+        None => match cell.with_generated_hubbard_u(INIT_HUBBARD_U, &[]) {
+            Ok(generated) => Some(generated),
+            Err(e) => {
+                findings.push(Finding::new(Severity::Error, e.to_string()));
+                return findings;
+            }
+        },
+    };
+    let cell = generated.as_ref().unwrap_or(cell);
+    let hubbard_u = cell
+        .hubbard_u()
+        .expect("Generated above for a seed without `HUBBARD_U`");
+    if generated.is_some() {
+        let sites = HubbardBlock::try_from(hubbard_u)
+            .map(|block| {
+                block
+                    .settings()
+                    .iter()
+                    .map(describe_site)
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        findings.push(Finding::new(
+            Severity::Info,
+            format!(
+                "HUBBARD_U is missing, `calc` writes one for {}",
+                sites.join(", ")
+            ),
+        ));
+    }
+    if hubbard_u.atom_u_values.is_empty() {
+        findings.push(Finding::new(Severity::Error, "HUBBARD_U has no site"));
+    }
+    let u_findings = check_hubbard_sites("HUBBARD_U", &hubbard_u.atom_u_values, &counts);
+    let u_valid = u_findings.is_empty();
+    findings.extend(u_findings);
+    // The species are checked above, so the blocks are only read when they are valid
+    let u_block = HubbardBlock::try_from(hubbard_u).ok().filter(|_| u_valid);
+    if let (Some(site), Some(u_block)) = (site, &u_block) {
+        findings.extend(check_site(u_block, site));
+    }
+    if let Some(alpha) = cell.hubbard_alpha() {
+        let alpha_findings = check_hubbard_sites("HUBBARD_ALPHA", &alpha.atom_u_values, &counts);
+        let alpha_valid = alpha_findings.is_empty();
+        findings.extend(alpha_findings);
+        let alpha_block = HubbardBlock::try_from(alpha).ok().filter(|_| alpha_valid);
+        if let (Some(u_block), Some(alpha_block)) = (&u_block, &alpha_block) {
+            findings.extend(check_seed_alpha(u_block, alpha_block));
+        }
+    }
+    findings
+}
+
+/// A `HUBBARD_ALPHA` already in the seed is kept on the sites outside `--site`,
+/// and its orbitals are perturbed instead of those of `HUBBARD_U`.
+fn check_seed_alpha(hubbard_u: &HubbardBlock, hubbard_alpha: &HubbardBlock) -> Vec<Finding> {
+    let mut findings = vec![Finding::new(
+        Severity::Info,
+        "HUBBARD_ALPHA is already in the seed, its orbitals are the ones perturbed",
+    )];
+    hubbard_alpha
+        .settings()
+        .iter()
+        .filter(|item| item.hub_value() != 0.0)
+        .for_each(|item| {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "HUBBARD_ALPHA of {} is {}, not zero: kept on the sites outside `--site`",
+                    describe_site(item),
+                    item.hub_value()
+                ),
+            ))
+        });
+    hubbard_alpha
+        .settings()
+        .iter()
+        .filter(|alpha| {
+            !hubbard_u.settings().iter().any(|u| {
+                u.species() == alpha.species()
+                    && u.atom_id() == alpha.atom_id()
+                    && u.orbital() == alpha.orbital()
+            })
+        })
+        .for_each(|item| {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "HUBBARD_ALPHA sets {}, which has no U in HUBBARD_U",
+                    describe_site(item)
+                ),
+            ))
+        });
+    findings
+}
+
+/// e.g.: `Fe 1 d`, the notation of `--site` apart from the separators
+fn describe_site(item: &HubbardItem) -> String {
+    let species = item.species();
+    let orbital = item.orbital();
+    match item.atom_id() {
+        Some(id) => format!("{species} {id} {orbital}"),
+        None => format!("{species} {orbital}"),
+    }
+}
+
+/// `--site` matching no Hubbard site of the seed
+fn check_site(block: &HubbardBlock, selector: &HubbardSelector) -> Option<Finding> {
+    (!block
+        .settings()
+        .iter()
+        .any(|item| item.is_selected_by(Some(selector))))
+    .then(|| {
+        Finding::new(
+            Severity::Error,
+            format!("`--site {selector}` matches no site of HUBBARD_U"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pipeline::fixtures::{test_dir, test_seed_path};
+
+    use super::{check_seed, Finding, Severity};
+
+    #[test]
+    fn test_seed_passes() {
+        let findings = check_seed(&test_seed_path(), None, None);
+        assert!(
+            findings
+                .iter()
+                .all(|finding| finding.severity < Severity::Error),
+            "{findings:?}"
+        );
+    }
+
+    #[test]
+    fn broken_seed() {
+        let dir = test_dir("auto_hubbard_preflight");
+        fs::write(
+            dir.join("NiO.cell"),
+            "%BLOCK LATTICE_CART\n4.17 0 0\n0 4.17 0\n0 0 4.17\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nNi 0 0 0\nO 0.5 0.5 0.5\n%ENDBLOCK POSITIONS_FRAC\n\
+            %BLOCK SPECIES_POT\nNi Ni_00PBE.usp\nO C19\n%ENDBLOCK SPECIES_POT\n\
+            %BLOCK SPECIES_MASS\nNi 58.69\nO 16.0\nFe 55.85\n%ENDBLOCK SPECIES_MASS\n\
+            %BLOCK HUBBARD_U\neV\nNi 1 d: 6.0\nCo 1 d: 5.0\nNi 2 d: 6.0\n%ENDBLOCK HUBBARD_U\n\
+            %BLOCK HUBBARD_ALPHA\neV\nNi 1 d: 0.3\n%ENDBLOCK HUBBARD_ALPHA\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("NiO.param"),
+            "task : SinglePoint\nspin_polarized : false\n",
+        )
+        .unwrap();
+        let findings = check_seed(&dir, None, None);
+        let errors = messages_of(&findings, Severity::Error);
+        assert_eq!(errors.len(), 4, "{errors:?}");
+        assert!(errors[0].contains("spin_polarized"));
+        assert!(errors[1].contains("Ni_00PBE.usp"));
+        assert!(errors[2].contains("Co, which is not in the positions"));
+        assert!(errors[3].contains("Ni 2, but there are 1 Ni"));
+        assert_eq!(
+            messages_of(&findings, Severity::Warning),
+            ["SPECIES_MASS lists Fe, which is not in the positions"]
+        );
+        // The same seed fixed, apart from the alpha left in it
+        fs::write(dir.join("Ni_00PBE.usp"), "").unwrap();
+        fs::write(
+            dir.join("NiO.param"),
+            "task : SinglePoint\nspin_polarized : true\n",
+        )
+        .unwrap();
+        let cell = fs::read_to_string(dir.join("NiO.cell"))
+            .unwrap()
+            .replace("Co 1 d: 5.0\nNi 2 d: 6.0\n", "")
+            .replace("Fe 55.85\n", "");
+        fs::write(dir.join("NiO.cell"), cell).unwrap();
+        let findings = check_seed(&dir, None, None);
+        assert!(findings
+            .iter()
+            .all(|finding| finding.severity < Severity::Error));
+        let co = "Co".parse().unwrap();
+        assert_eq!(
+            messages_of(&check_seed(&dir, None, Some(&co)), Severity::Error),
+            ["`--site Co` matches no site of HUBBARD_U"]
+        );
+        assert_eq!(
+            messages_of(&findings, Severity::Warning),
+            ["HUBBARD_ALPHA of Ni 1 d is 0.3, not zero: kept on the sites outside `--site`"]
+        );
+        // Without HUBBARD_U, the one `calc` generates is checked instead
+        let cell = fs::read_to_string(dir.join("NiO.cell")).unwrap().replace(
+            "%BLOCK HUBBARD_U\neV\nNi 1 d: 6.0\n%ENDBLOCK HUBBARD_U\n",
+            "",
+        );
+        fs::write(dir.join("NiO.cell"), cell).unwrap();
+        let findings = check_seed(&dir, None, None);
+        assert!(messages_of(&findings, Severity::Error).is_empty());
+        assert!(messages_of(&findings, Severity::Info)
+            .contains(&"HUBBARD_U is missing, `calc` writes one for Ni 1 d"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn labelled_species_seed() {
+        let dir = test_dir("auto_hubbard_preflight_labels");
+        fs::write(
+            dir.join("Fe2.cell"),
+            "%BLOCK LATTICE_CART\n2.87 0 0\n0 2.87 0\n0 0 2.87\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nFe1 0 0 0\nFe:up 0.5 0.5 0.5\n%ENDBLOCK POSITIONS_FRAC\n\
+            %BLOCK HUBBARD_U\neV\nFe1 1 d: 4.0\nFe:up 1 d: 3.0\n%ENDBLOCK HUBBARD_U\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Fe2.param"),
+            "task : SinglePoint\nspin_polarized : true\n",
+        )
+        .unwrap();
+        let site = "Fe1:1:d".parse().unwrap();
+        let findings = check_seed(&dir, None, Some(&site));
+        assert!(
+            messages_of(&findings, Severity::Error).is_empty(),
+            "{findings:?}"
+        );
+        let absent = "Fe1:2".parse().unwrap();
+        assert_eq!(
+            messages_of(&check_seed(&dir, None, Some(&absent)), Severity::Error),
+            ["`--site Fe1:2` matches no site of HUBBARD_U"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn messages_of(findings: &[Finding], severity: Severity) -> Vec<&str> {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .map(|finding| finding.message.as_str())
+            .collect()
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::seed_settings::element_of;
+
+/// Default on-the-fly library of `CASTEP`
+pub const DEFAULT_OTFG_LIBRARY: &str = "C19";
+/// Extensions of norm-conserving pseudopotential files
+const NORM_CONSERVING_EXTENSIONS: [&str; 2] = ["recpot", "ncp"];
+/// Extensions of ultrasoft pseudopotential files
+const ULTRASOFT_EXTENSIONS: [&str; 3] = ["usp", "uspcc", "uspso"];
+
+/// The kind of pseudopotential written for every species in `SPECIES_POT`.
+/// Written as `nc`, `us`, or `otfg[:library_or_string]`, e.g.: `otfg:C19`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PseudoKind {
+    /// A `.recpot`/`.ncp` file from the library
+    NormConserving,
+    /// A `.usp`/`.uspcc`/`.uspso` file from the library
+    Ultrasoft,
+    /// Generated by `CASTEP` on the fly, from a library name such as `C19`
+    /// or a full generation string such as `3|1.8|7|8|9|40U:41:32:42`
+    OnTheFly(String),
+}
+
+impl PseudoKind {
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            PseudoKind::NormConserving => &NORM_CONSERVING_EXTENSIONS,
+            PseudoKind::Ultrasoft => &ULTRASOFT_EXTENSIONS,
+            PseudoKind::OnTheFly(_) => &[],
+        }
+    }
+}
+
+impl Display for PseudoKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PseudoKind::NormConserving => f.write_str("nc"),
+            PseudoKind::Ultrasoft => f.write_str("us"),
+            PseudoKind::OnTheFly(otfg) => write!(f, "otfg:{otfg}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PseudoKindParsingError(String);
+
+impl Display for PseudoKindParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid pseudopotential kind `{}`, expected `nc`, `us` or `otfg[:library_or_string]`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for PseudoKindParsingError {}
+
+impl FromStr for PseudoKind {
+    type Err = PseudoKindParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, otfg) = s.split_once(':').unwrap_or((s, ""));
+        match (kind.trim().to_lowercase().as_str(), otfg.trim()) {
+            ("nc" | "ncp" | "norm-conserving", "") => Ok(Self::NormConserving),
+            ("us" | "usp" | "ultrasoft", "") => Ok(Self::Ultrasoft),
+            ("otfg", "") => Ok(Self::OnTheFly(DEFAULT_OTFG_LIBRARY.to_string())),
+            ("otfg", otfg) => Ok(Self::OnTheFly(otfg.to_string())),
+            _ => Err(PseudoKindParsingError(s.to_string())),
+        }
+    }
+}
+
+/// Whether a `SPECIES_POT` entry names a file, rather than an on-the-fly string or library
+pub fn is_potential_file(potential: &str) -> bool {
+    !potential.contains('|')
+        && Path::new(potential).extension().is_some_and(|ext| {
+            ext.to_string_lossy()
+                .chars()
+                .all(|c| c.is_ascii_alphabetic())
+        })
+}
+
+/// A directory of pseudopotential files named after their element,
+/// e.g.: `Fe_00PBE.uspcc`, `Fe_00.recpot`
+#[derive(Debug, Clone)]
+pub struct PseudoLibrary {
+    dir: PathBuf,
+}
+
+impl PseudoLibrary {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, anyhow::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        if !dir.is_dir() {
+            bail!(
+                "Pseudopotential library {} is not a directory",
+                dir.display()
+            );
+        }
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The file of `kind` for `species`, the first by name if there are several
+    pub fn find(&self, species: &str, kind: &PseudoKind) -> Result<PathBuf, anyhow::Error> {
+        let element = element_of(species);
+        let mut candidates = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read directory {}", self.dir.display()))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let stem = name.split(['_', '.']).next().unwrap_or_default();
+                path.is_file()
+                    && stem.eq_ignore_ascii_case(&element)
+                    && path.extension().is_some_and(|ext| {
+                        kind.extensions()
+                            .iter()
+                            .any(|expected| ext.eq_ignore_ascii_case(expected))
+                    })
+            })
+            .collect::<Vec<PathBuf>>();
+        candidates.sort();
+        candidates.into_iter().next().ok_or_else(|| {
+            anyhow!(
+                "No {} pseudopotential of {element} ({}) in {}",
+                kind,
+                kind.extensions().join("/"),
+                self.dir.display()
+            )
+        })
+    }
+
+    /// The `SPECIES_POT` entry of `species` for `kind`
+    pub fn potential(&self, species: &str, kind: &PseudoKind) -> Result<String, anyhow::Error> {
+        match kind {
+            PseudoKind::OnTheFly(otfg) => Ok(otfg.clone()),
+            _ => Ok(self
+                .find(species, kind)?
+                .file_name()
+                .expect("Files always have a name")
+                .to_string_lossy()
+                .to_string()),
+        }
+    }
+}
+
+/// Locate every pseudopotential file named in `SPECIES_POT`, in `seed_dir` first
+/// and then in `library`. On-the-fly entries need no file.
+pub fn locate_potentials(
+    potentials: &[(String, String)],
+    seed_dir: &Path,
+    library: Option<&PseudoLibrary>,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut files = potentials
+        .iter()
+        .filter(|(_, potential)| is_potential_file(potential))
+        .map(|(species, potential)| {
+            let dirs = std::iter::once(seed_dir).chain(library.map(PseudoLibrary::dir));
+            dirs.clone()
+                .map(|dir| dir.join(potential))
+                .find(|path| path.is_file())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Pseudopotential {potential} of {species} not found in {}",
+                        dirs.map(|dir| dir.display().to_string())
+                            .collect::<Vec<String>>()
+                            .join(" or ")
+                    )
+                })
+        })
+        .collect::<Result<Vec<PathBuf>, anyhow::Error>>()?;
+    files.dedup();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pipeline::fixtures::test_dir;
+
+    use super::{is_potential_file, locate_potentials, PseudoKind, PseudoLibrary};
+
+    #[test]
+    fn parse_kind() {
+        assert_eq!("us".parse::<PseudoKind>().unwrap(), PseudoKind::Ultrasoft);
+        assert_eq!(
+            "NC".parse::<PseudoKind>().unwrap(),
+            PseudoKind::NormConserving
+        );
+        assert_eq!(
+            "otfg".parse::<PseudoKind>().unwrap(),
+            PseudoKind::OnTheFly("C19".into())
+        );
+        let string = "otfg:3|1.8|7|8|9|40U:41:32:42";
+        let kind = string.parse::<PseudoKind>().unwrap();
+        assert_eq!(
+            kind,
+            PseudoKind::OnTheFly("3|1.8|7|8|9|40U:41:32:42".into())
+        );
+        assert_eq!(kind.to_string(), string);
+        assert!("paw".parse::<PseudoKind>().is_err());
+        assert!(is_potential_file("Fe_00PBE.uspcc"));
+        assert!(!is_potential_file("3|1.8|7|8|9|40U:41:32:42"));
+        assert!(!is_potential_file("C19"));
+    }
+
+    #[test]
+    fn library_lookup() {
+        let dir = test_dir("auto_hubbard_pseudo_library");
+        [
+            "Fe_00PBE.uspcc",
+            "Fe_00.recpot",
+            "C_00PBE.usp",
+            "Co_00PBE.usp",
+        ]
+        .iter()
+        .for_each(|name| fs::write(dir.join(name), "").unwrap());
+        let library = PseudoLibrary::new(&dir).unwrap();
+        assert_eq!(
+            library.potential("Fe1", &PseudoKind::Ultrasoft).unwrap(),
+            "Fe_00PBE.uspcc"
+        );
+        assert_eq!(
+            library
+                .potential("Fe", &PseudoKind::NormConserving)
+                .unwrap(),
+            "Fe_00.recpot"
+        );
+        assert_eq!(
+            library.potential("C", &PseudoKind::Ultrasoft).unwrap(),
+            "C_00PBE.usp"
+        );
+        assert!(library.find("C", &PseudoKind::NormConserving).is_err());
+        assert_eq!(
+            library
+                .potential("C", &PseudoKind::OnTheFly("C19".into()))
+                .unwrap(),
+            "C19"
+        );
+        let potentials = [
+            ("Fe".to_string(), "Fe_00PBE.uspcc".to_string()),
+            ("C".to_string(), "C19".to_string()),
+        ];
+        let seed_dir = test_dir("auto_hubbard_pseudo_seed");
+        assert_eq!(
+            locate_potentials(&potentials, &seed_dir, Some(&library)).unwrap(),
+            vec![dir.join("Fe_00PBE.uspcc")]
+        );
+        assert!(locate_potentials(&potentials, &seed_dir, None).is_err());
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(seed_dir).unwrap();
+    }
+}
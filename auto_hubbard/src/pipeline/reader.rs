@@ -0,0 +1,120 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    castep_output::{CastepOutput, ResultRow, RESULT_CSV_HEADER},
+    seed_settings::{CellFile, HubbardSelector, JobType},
+};
+
+use super::{seed::find_by_extension, Sequence};
+
+/// Reads the `.castep` outputs of an existing result folder,
+/// which has the tree `U_[u]_[jobtype]/U_[u]_[jobtype]_[step]`.
+#[derive(Debug, Clone)]
+pub struct ResultReader {
+    result_root: PathBuf,
+    job_type: JobType,
+    u_values: Sequence<f64>,
+    perturb_times: usize,
+    /// The `--site` of the run, to record its channel in the result csv
+    site: Option<HubbardSelector>,
+}
+
+impl ResultReader {
+    pub fn new<P: AsRef<Path>>(
+        result_root: P,
+        job_type: JobType,
+        u_values: Sequence<f64>,
+        perturb_times: usize,
+    ) -> Self {
+        Self {
+            result_root: result_root.as_ref().to_path_buf(),
+            job_type,
+            u_values,
+            perturb_times,
+            site: None,
+        }
+    }
+
+    pub fn with_site(self, site: Option<HubbardSelector>) -> Self {
+        Self { site, ..self }
+    }
+
+    /// Channel of the site perturbed by the job in `dir`, from its `.cell`, 0 for all channels
+    fn perturbed_channel(&self, dir: &Path) -> u32 {
+        self.site
+            .as_ref()
+            .and_then(|site| {
+                let cell = fs::read_to_string(find_by_extension(dir, "cell").ok()?).ok()?;
+                cell.parse::<CellFile>().ok()?.channel_of(site)
+            })
+            .unwrap_or(0)
+    }
+
+    /// `result_[jobtype]_post_read.csv`
+    pub fn post_read_path(&self) -> PathBuf {
+        self.result_root
+            .join(format!("result_{}_post_read.csv", self.job_type))
+    }
+
+    /// Relative folders of all jobs to be read, in the order of the result csv.
+    fn job_dirs(&self) -> Vec<PathBuf> {
+        self.u_values
+            .flat_map(|u| {
+                let init_dir = PathBuf::from(format!("U_{u}_{}", self.job_type));
+                let perturbed = (1..=self.perturb_times)
+                    .map(|step| init_dir.join(format!("U_{u}_{}_{step}", self.job_type)))
+                    .collect::<Vec<PathBuf>>();
+                std::iter::once(init_dir).chain(perturbed)
+            })
+            .collect()
+    }
+
+    /// Read every job and write the rows into `result_[jobtype]_post_read.csv`
+    pub fn read(&self) -> Result<PathBuf, anyhow::Error> {
+        let post_read_path = self.post_read_path();
+        let mut post_read = File::create(&post_read_path)?;
+        writeln!(post_read, "{RESULT_CSV_HEADER}")?;
+        for job_dir in self.job_dirs() {
+            let dir = self.result_root.join(&job_dir);
+            if !dir.is_dir() {
+                println!("Directory: {} does not exist, skip", dir.display());
+                continue;
+            }
+            let castep_path = match find_by_extension(&dir, "castep") {
+                Ok(path) => path,
+                Err(e) => {
+                    println!("{e}, skip");
+                    continue;
+                }
+            };
+            let content = fs::read_to_string(&castep_path)
+                .with_context(|| format!("Failed to read {}", castep_path.display()))?;
+            let job_name = format!(
+                "./{}/{}",
+                job_dir.display(),
+                castep_path
+                    .file_stem()
+                    .expect("Found by extension")
+                    .to_string_lossy()
+            );
+            let perturbed_channel = self.perturbed_channel(&dir);
+            CastepOutput::parse(&content)
+                .records()
+                .into_iter()
+                .try_for_each(|record| {
+                    writeln!(
+                        post_read,
+                        "{}",
+                        ResultRow::new(&job_name, record, perturbed_channel)
+                    )
+                })?;
+        }
+        Ok(post_read_path)
+    }
+}
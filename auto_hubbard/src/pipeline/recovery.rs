@@ -0,0 +1,173 @@
+use std::fmt::Display;
+
+use super::watcher::{FailureReason, JobOutcome};
+
+/// Default of `--max-recoveries`
+pub const DEFAULT_MAX_RECOVERIES: u32 = 2;
+
+/// One change to the `.param` before running a job again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Adjustment {
+    /// Multiply `max_scf_cycles`
+    MoreScfCycles(u32),
+    /// Multiply `mix_charge_amp` and `mix_spin_amp`
+    DampMixing(f64),
+    /// `mixing_scheme : pulay` instead of the `CASTEP` default Broyden
+    SwitchToPulay,
+    /// `metals_method : edft` instead of density mixing
+    SwitchToEdft,
+    /// Multiply `elec_energy_tol`
+    LoosenEnergyTol(f64),
+}
+
+impl Display for Adjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Adjustment::MoreScfCycles(factor) => write!(f, "max_scf_cycles x{factor}"),
+            Adjustment::DampMixing(factor) => write!(f, "mix_charge_amp, mix_spin_amp x{factor}"),
+            Adjustment::SwitchToPulay => f.write_str("mixing_scheme -> pulay"),
+            Adjustment::SwitchToEdft => f.write_str("metals_method dm -> edft"),
+            Adjustment::LoosenEnergyTol(factor) => write!(f, "elec_energy_tol x{factor}"),
+        }
+    }
+}
+
+/// What the recovery plan needs to know of the job to run again
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecoveryContext {
+    /// `metals_method : dm`, the `CASTEP` default
+    pub density_mixing: bool,
+    /// `mixing_scheme : pulay` already
+    pub pulay_mixing: bool,
+    /// A perturbation step, whose occupations are only meaningful at the tolerance asked for
+    pub perturbed: bool,
+}
+
+/// Decides how to run an unconverged or failed job again, in place of the
+/// "another script for non-converged cases" of the shell workflow.
+#[derive(Debug, Clone, Copy)]
+pub struct RecoveryPolicy {
+    max_recoveries: u32,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_RECOVERIES)
+    }
+}
+
+impl RecoveryPolicy {
+    pub fn new(max_recoveries: u32) -> Self {
+        Self { max_recoveries }
+    }
+
+    /// The adjustments for the `recovery`-th (from 1) rerun of a job which ended
+    /// with `outcome`, `None` to give up.
+    /// Unconverged: more cycles with gentler mixing, then Pulay instead of Broyden mixing,
+    /// then EDFT (or gentler mixing again without density mixing), then a looser tolerance.
+    /// A perturbation step never gets a looser tolerance: it stays unconverged, and is
+    /// flagged so in the result csv.
+    /// An error during the SCF: gentler mixing, then Pulay mixing, then EDFT.
+    /// Other outcomes are not about the `.param` and are not recovered.
+    pub fn plan(
+        &self,
+        outcome: &JobOutcome,
+        recovery: u32,
+        context: RecoveryContext,
+    ) -> Option<Vec<Adjustment>> {
+        if recovery == 0 || recovery > self.max_recoveries {
+            return None;
+        }
+        let RecoveryContext {
+            density_mixing,
+            pulay_mixing,
+            perturbed,
+        } = context;
+        let gentler = || vec![Adjustment::MoreScfCycles(2), Adjustment::DampMixing(0.5)];
+        match (outcome, recovery) {
+            (JobOutcome::Unconverged { .. }, 1) => Some(gentler()),
+            (JobOutcome::Unconverged { .. }, 2) if density_mixing && !pulay_mixing => {
+                Some(vec![Adjustment::SwitchToPulay])
+            }
+            (JobOutcome::Unconverged { .. }, 2 | 3) if density_mixing => {
+                Some(vec![Adjustment::SwitchToEdft])
+            }
+            (JobOutcome::Unconverged { .. }, 2 | 3) => Some(gentler()),
+            (JobOutcome::Unconverged { .. }, _) if perturbed => None,
+            (JobOutcome::Unconverged { .. }, _) => Some(vec![Adjustment::LoosenEnergyTol(10.0)]),
+            (
+                JobOutcome::Failed(FailureReason::ErrorBanner(_) | FailureReason::ErrFile { .. }),
+                1,
+            ) => Some(vec![Adjustment::DampMixing(0.5)]),
+            (
+                JobOutcome::Failed(FailureReason::ErrorBanner(_) | FailureReason::ErrFile { .. }),
+                2,
+            ) if density_mixing && !pulay_mixing => Some(vec![Adjustment::SwitchToPulay]),
+            (
+                JobOutcome::Failed(FailureReason::ErrorBanner(_) | FailureReason::ErrFile { .. }),
+                2 | 3,
+            ) if density_mixing => Some(vec![Adjustment::SwitchToEdft]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::watcher::{FailureReason, JobOutcome};
+
+    use super::{Adjustment, RecoveryContext, RecoveryPolicy};
+
+    #[test]
+    fn recovery_ladder() {
+        let policy = RecoveryPolicy::new(4);
+        let unconverged = JobOutcome::Unconverged { scf_cycles: 100 };
+        let broyden = RecoveryContext {
+            density_mixing: true,
+            pulay_mixing: false,
+            perturbed: false,
+        };
+        let pulay = RecoveryContext {
+            pulay_mixing: true,
+            ..broyden
+        };
+        let edft = RecoveryContext {
+            density_mixing: false,
+            ..pulay
+        };
+        assert_eq!(
+            policy.plan(&unconverged, 1, broyden),
+            Some(vec![
+                Adjustment::MoreScfCycles(2),
+                Adjustment::DampMixing(0.5)
+            ])
+        );
+        assert_eq!(
+            policy.plan(&unconverged, 2, broyden),
+            Some(vec![Adjustment::SwitchToPulay])
+        );
+        assert_eq!(
+            policy.plan(&unconverged, 3, pulay),
+            Some(vec![Adjustment::SwitchToEdft])
+        );
+        assert_eq!(
+            policy.plan(&unconverged, 4, edft),
+            Some(vec![Adjustment::LoosenEnergyTol(10.0)])
+        );
+        assert_eq!(policy.plan(&unconverged, 5, edft), None);
+        // A seed already on Pulay mixing goes on to EDFT
+        assert_eq!(
+            policy.plan(&unconverged, 2, pulay),
+            Some(vec![Adjustment::SwitchToEdft])
+        );
+        // A perturbation step is left unconverged rather than loosened
+        let perturbed = RecoveryContext {
+            perturbed: true,
+            ..edft
+        };
+        assert_eq!(policy.plan(&unconverged, 4, perturbed), None);
+        let crashed = JobOutcome::Failed(FailureReason::Exit("killed".to_string()));
+        assert_eq!(policy.plan(&crashed, 1, broyden), None);
+        assert_eq!(RecoveryPolicy::new(0).plan(&unconverged, 1, broyden), None);
+    }
+}
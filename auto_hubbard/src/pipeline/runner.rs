@@ -0,0 +1,517 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::{
+    castep_output::{is_finished, CastepOutput, ResultRow, RESULT_CSV_HEADER},
+    seed_settings::JobType,
+};
+
+use super::{
+    executor::{CancelToken, Executor, JobGraph},
+    hubbard_job::{plan_table, HubbardJob, PerturbChain},
+    manifest::{JobRecord, JobState, RunManifest},
+    recovery::{Adjustment, RecoveryContext, RecoveryPolicy},
+    scheduler::{
+        status_with_retries, CastepCommand, JobId, JobSpec, JobStatus, Scheduler, STATUS_RETRIES,
+    },
+    seed::{copy_aux_files, SeedFolder},
+    watcher::{inspect, CompletionWatcher, FailureReason, JobOutcome},
+    HubArguments,
+};
+
+/// Runs all the perturbation chains of a calculation inside the result folder.
+#[derive(Debug)]
+pub struct CalcRunner {
+    result_root: PathBuf,
+    job_type: JobType,
+    scheduler: Box<dyn Scheduler>,
+    chains: Vec<PerturbChain>,
+    /// Channel of the `--site` recorded in the result csv, 0 for all channels
+    perturbed_channel: u32,
+    /// Saved to `run.toml` on every job state change
+    manifest: Mutex<RunManifest>,
+    /// Continue from the job states in an existing `run.toml`
+    resume: bool,
+    /// Start from the inputs written by `--dry-run` instead of writing them again
+    prepared: bool,
+    cancel: CancelToken,
+    watcher: CompletionWatcher,
+    recovery: RecoveryPolicy,
+}
+
+impl CalcRunner {
+    /// Create the result folder, copy the seed files into it, build the jobs
+    /// and write the `run.toml` manifest.
+    /// With `resume`, the job states are taken from the existing `run.toml` instead.
+    /// A `run.toml` left by `--dry-run` for the same seed, command and arguments is taken over
+    /// with its inputs. One with started jobs is only replaced with `force`.
+    pub fn setup(
+        seed: &SeedFolder,
+        hub_args: &HubArguments,
+        castep_command: &CastepCommand,
+        scheduler: Box<dyn Scheduler>,
+        resume: bool,
+        force: bool,
+    ) -> Result<Self, anyhow::Error> {
+        let result_root = seed.result_folder(hub_args);
+        let chains = hub_args.perturb_chains(seed)?;
+        // Only `--resume` depends on the old manifest, a new run replaces a broken one
+        let existing = match RunManifest::path(&result_root)
+            .exists()
+            .then(|| RunManifest::load(&result_root))
+            .transpose()
+        {
+            Ok(existing) => existing,
+            Err(e) if resume => return Err(e),
+            Err(_) => None,
+        };
+        let mut prepared = false;
+        let manifest = match existing {
+            Some(manifest) if resume => {
+                if manifest.seed_hash() != seed.hash() {
+                    bail!(
+                        "The seed in {} has changed since the run started, start a new run instead of `--resume`",
+                        seed.path().display()
+                    );
+                }
+                println!("Resume in: {}", result_root.display());
+                manifest
+            }
+            Some(manifest)
+                if manifest.is_prepared(seed.hash(), &castep_command.to_string(), hub_args) =>
+            {
+                println!("Start the prepared jobs in: {}", result_root.display());
+                prepared = true;
+                manifest
+            }
+            Some(manifest) if manifest.has_started() && !force => bail!(
+                "{} holds jobs which have already started, continue them with `--resume` or start over with `--force`",
+                result_root.display()
+            ),
+            _ => {
+                println!("New directory: {}", result_root.display());
+                copy_aux_files(seed.path(), &result_root)?;
+                RunManifest::new(
+                    seed.seed_name(),
+                    seed.hash(),
+                    &castep_command.to_string(),
+                    hub_args,
+                    &chains,
+                )
+            }
+        };
+        manifest.save(&result_root)?;
+        let perturbed_channel = hub_args
+            .site()
+            .and_then(|site| seed.cell().cell.channel_of(site))
+            .unwrap_or(0);
+        Ok(Self {
+            result_root,
+            job_type: hub_args.job_type,
+            scheduler,
+            chains,
+            perturbed_channel,
+            manifest: Mutex::new(manifest),
+            resume,
+            prepared,
+            cancel: CancelToken::default(),
+            watcher: CompletionWatcher::default(),
+            recovery: RecoveryPolicy::default(),
+        })
+    }
+
+    pub fn with_recovery(self, recovery: RecoveryPolicy) -> Self {
+        Self { recovery, ..self }
+    }
+
+    /// Give up on jobs without an outcome after `timeout`
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            watcher: CompletionWatcher::new(timeout),
+            ..self
+        }
+    }
+
+    pub fn result_root(&self) -> &Path {
+        &self.result_root
+    }
+
+    /// Stops the jobs once cancelled, e.g. by `SIGINT`
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Write the inputs of every job and print the plan without submitting anything.
+    /// The jobs are started later by running again without `--dry-run`.
+    pub fn prepare(&self) -> Result<(), anyhow::Error> {
+        self.chains.iter().try_for_each(|chain| {
+            chain
+                .init_job()
+                .write_inputs(&self.result_root, &self.result_root)?;
+            let init_dir = self.result_root.join(chain.init_job().dir());
+            chain
+                .perturbed_jobs()
+                .iter()
+                .try_for_each(|job| job.write_inputs(&self.result_root, &init_dir))
+        })?;
+        print!("{}", plan_table(&self.chains));
+        println!(
+            "Dry run: inputs written in {}, nothing submitted",
+            self.result_root.display()
+        );
+        Ok(())
+    }
+
+    /// Run every job with at most `max_jobs` at once: in each chain the perturbation
+    /// steps start after the unperturbed job, from its `.check`.
+    /// Then gather the results of each complete `U` into `result_[jobtype]_final.csv`
+    pub fn run(&self, max_jobs: usize) -> Result<(), anyhow::Error> {
+        let mut graph = JobGraph::default();
+        // Each job with the folder its auxiliary files come from, and whether it is a perturbation step
+        let mut jobs: Vec<(&HubbardJob, PathBuf, bool)> = Vec::new();
+        self.chains.iter().for_each(|chain| {
+            let init = graph.add(&[]);
+            jobs.push((chain.init_job(), self.result_root.clone(), false));
+            let init_dir = self.result_root.join(chain.init_job().dir());
+            chain.perturbed_jobs().iter().for_each(|job| {
+                graph.add(&[init]);
+                jobs.push((job, init_dir.clone(), true));
+            });
+        });
+        let outcome = Executor::new(max_jobs, self.cancel.clone()).run(&graph, |node| {
+            let (job, source_dir, perturbed) = &jobs[node];
+            self.run_job(job, source_dir, *perturbed)
+        });
+        let complete = self
+            .chains
+            .iter()
+            .filter(|chain| {
+                std::iter::once(chain.init_job())
+                    .chain(chain.perturbed_jobs())
+                    .all(|job| self.is_finished(job))
+            })
+            .collect::<Vec<&PerturbChain>>();
+        complete
+            .iter()
+            .try_for_each(|chain| self.write_chain_result(chain))?;
+        self.gather_results(&complete)?;
+        outcome
+    }
+
+    fn local_result_path(&self, chain: &PerturbChain) -> PathBuf {
+        self.result_root
+            .join(chain.init_job().dir())
+            .join(format!("result_{}.csv", self.job_type))
+    }
+
+    fn final_result_path(&self) -> PathBuf {
+        self.result_root
+            .join(format!("result_{}_final.csv", self.job_type))
+    }
+
+    /// Write the rows of the unperturbed job, then the perturbation steps in order.
+    fn write_chain_result(&self, chain: &PerturbChain) -> Result<(), anyhow::Error> {
+        let local_result = self.local_result_path(chain);
+        File::create(&local_result)?;
+        std::iter::once(chain.init_job())
+            .chain(chain.perturbed_jobs())
+            .try_for_each(|job| self.write_result(job, &local_result))
+    }
+
+    fn lock_manifest(&self) -> Result<std::sync::MutexGuard<'_, RunManifest>, anyhow::Error> {
+        self.manifest
+            .lock()
+            .map_err(|_| anyhow!("The run manifest is poisoned"))
+    }
+
+    fn record_state(&self, job: &HubbardJob, state: JobState) -> Result<(), anyhow::Error> {
+        let mut manifest = self.lock_manifest()?;
+        manifest.set_state(&job.job_name(), state);
+        manifest.save(&self.result_root)
+    }
+
+    fn record_submission(&self, job: &HubbardJob, id: &JobId) -> Result<(), anyhow::Error> {
+        let mut manifest = self.lock_manifest()?;
+        manifest.set_scheduler_id(&job.job_name(), Some(id.to_string()));
+        manifest.set_state(&job.job_name(), JobState::Submitted);
+        manifest.save(&self.result_root)
+    }
+
+    fn record_recovery(
+        &self,
+        job: &HubbardJob,
+        adjustments: &[Adjustment],
+    ) -> Result<(), anyhow::Error> {
+        let description = adjustments
+            .iter()
+            .map(Adjustment::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut manifest = self.lock_manifest()?;
+        manifest.add_recovery(&job.job_name(), description);
+        manifest.save(&self.result_root)
+    }
+
+    /// Run the job, rerun it with an adjusted `.param` as long as the recovery
+    /// policy has something to try, and record its state in the manifest.
+    /// An unconverged job still counts as finished, its results flagged in the csv.
+    fn run_job(
+        &self,
+        job: &HubbardJob,
+        source_dir: &Path,
+        perturbed: bool,
+    ) -> Result<(), anyhow::Error> {
+        let mut outcome = self.start_job(job, source_dir);
+        let mut current = job.clone();
+        let mut recoveries = self
+            .last_record(job)?
+            .map_or(0, |record| record.recoveries.len() as u32);
+        while let Ok(last) = &outcome {
+            let context = RecoveryContext {
+                density_mixing: current.param().uses_density_mixing(),
+                pulay_mixing: current.param().uses_pulay_mixing(),
+                perturbed,
+            };
+            let Some(adjustments) = self.recovery.plan(last, recoveries + 1, context) else {
+                break;
+            };
+            recoveries += 1;
+            println!(
+                "Recover {} ({last}) with {}",
+                job.job_name(),
+                adjustments
+                    .iter()
+                    .map(Adjustment::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            self.record_recovery(job, &adjustments)?;
+            current = current.with_param(current.param().adjusted(&adjustments));
+            outcome = self.rerun_job(&current, source_dir);
+        }
+        let state = match &outcome {
+            Ok(outcome) if outcome.has_results() => JobState::Finished,
+            _ => JobState::Failed,
+        };
+        self.record_state(job, state)?;
+        match outcome? {
+            JobOutcome::Finished => {
+                println!("{} completed!", job.job_name());
+                Ok(())
+            }
+            unconverged @ JobOutcome::Unconverged { .. } => {
+                println!("Warning: {} completed, {unconverged}", job.job_name());
+                Ok(())
+            }
+            outcome => bail!("{} {outcome}", job.job_name()),
+        }
+    }
+
+    fn is_finished(&self, job: &HubbardJob) -> bool {
+        fs::read_to_string(job.castep_path(&self.result_root))
+            .is_ok_and(|content| is_finished(&content))
+    }
+
+    fn last_record(&self, job: &HubbardJob) -> Result<Option<JobRecord>, anyhow::Error> {
+        Ok(self.lock_manifest()?.job(&job.job_name()).cloned())
+    }
+
+    /// Skip the job if the `.castep` shows it has been done.
+    /// When resuming, keep waiting for a batch job still in the queue, even one recorded
+    /// as failed, and retry a failed or interrupted job from its own `.check`.
+    fn start_job(&self, job: &HubbardJob, source_dir: &Path) -> Result<JobOutcome, anyhow::Error> {
+        let job_dir = self.result_root.join(job.dir());
+        let max_scf_cycles = Some(job.param().max_scf_cycles());
+        if let Some(done) = inspect(&job_dir, job.seed_name(), max_scf_cycles)
+            .filter(|outcome| self.is_finished(job) && outcome.has_results())
+        {
+            println!("{} has been completed! Skip now", job.job_name());
+            return Ok(done);
+        }
+        let last_record = self.last_record(job)?.filter(|record| {
+            self.resume
+                && matches!(
+                    record.state,
+                    JobState::Submitted | JobState::Running | JobState::Failed
+                )
+        });
+        // A failed job may only have lost track of its batch job, which still writes in the folder
+        if let Some(id) = last_record
+            .as_ref()
+            .and_then(|record| record.scheduler_id.as_deref())
+            .map(JobId::new)
+        {
+            if self.scheduler.tracks_completion() && self.poll_status(&id)? != JobStatus::Finished {
+                println!("{} is still in the queue as {id}", job.job_name());
+                return self.wait_job(job, &id);
+            }
+        }
+        match last_record {
+            Some(record) => {
+                job.archive_outputs(&self.result_root, record.attempts)?;
+                self.rerun_inputs(job, source_dir)?;
+            }
+            // The prepared `.cell` and `.param` may have been edited since the dry run
+            None if self.prepared && job.has_inputs(&self.result_root) => {
+                job.write_aux_files(&self.result_root, source_dir)?
+            }
+            None => job.write_inputs(&self.result_root, source_dir)?,
+        }
+        self.submit_job(job)
+    }
+
+    /// Rewrite the inputs of a job which has already run, continuing from its `.check` if any.
+    fn rerun_inputs(&self, job: &HubbardJob, source_dir: &Path) -> Result<(), anyhow::Error> {
+        if job.check_path(&self.result_root).exists() {
+            println!("Retry {} from its `.check`", job.job_name());
+            job.write_continuation_inputs(&self.result_root)
+        } else {
+            job.write_inputs(&self.result_root, source_dir)
+        }
+    }
+
+    /// Run a job again after an unsuccessful attempt, keeping the previous outputs aside.
+    fn rerun_job(&self, job: &HubbardJob, source_dir: &Path) -> Result<JobOutcome, anyhow::Error> {
+        let attempts = self.last_record(job)?.map_or(0, |record| record.attempts);
+        job.archive_outputs(&self.result_root, attempts)?;
+        self.rerun_inputs(job, source_dir)?;
+        self.submit_job(job)
+    }
+
+    /// Hand the job with its inputs written to the scheduler and wait for its outcome.
+    fn submit_job(&self, job: &HubbardJob) -> Result<JobOutcome, anyhow::Error> {
+        let job_dir = self.result_root.join(job.dir());
+        let max_scf_cycles = Some(job.param().max_scf_cycles());
+        println!(
+            "Start {}: U = {}, alpha = {}",
+            job.job_name(),
+            job.u_value(),
+            job.alpha_value()
+        );
+        let job_name = job_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| job.job_name());
+        // A local job runs while `submit` blocks
+        if !self.scheduler.tracks_completion() {
+            self.record_state(job, JobState::Running)?;
+        }
+        let submitted = self.scheduler.submit(&JobSpec {
+            dir: &job_dir,
+            seed_name: job.seed_name(),
+            job_name: &job_name,
+            log: &self.result_root.join(format!("log_{}.txt", self.job_type)),
+        });
+        let id = match submitted {
+            Ok(id) => id,
+            // The castep command exited abnormally, the folder may tell why
+            Err(e) if !self.scheduler.tracks_completion() => {
+                return Ok(inspect(&job_dir, job.seed_name(), max_scf_cycles)
+                    .filter(|outcome| !outcome.has_results())
+                    .unwrap_or(JobOutcome::Failed(FailureReason::Exit(e.to_string()))));
+            }
+            Err(e) => return Err(e),
+        };
+        if self.scheduler.tracks_completion() {
+            self.record_submission(job, &id)?;
+        }
+        self.wait_job(job, &id)
+    }
+
+    /// The status of a submitted job, retried on failure. Once out of retries the job
+    /// is left alone: it may still be in the queue, and `--resume` looks for it again.
+    fn poll_status(&self, id: &JobId) -> Result<JobStatus, anyhow::Error> {
+        status_with_retries(
+            self.scheduler.as_ref(),
+            id,
+            self.scheduler.poll_interval(),
+            STATUS_RETRIES,
+        )
+        .map_err(|e| {
+            e.context(format!(
+                "Lost track of job {id}, which may still be in the queue: `--resume` waits for it"
+            ))
+        })
+    }
+
+    /// Poll the scheduler until the job leaves the queue, then tell how it ended.
+    /// A local command may only submit the job somewhere else, so the folder is
+    /// watched until the `.castep` shows an outcome.
+    fn wait_job(&self, job: &HubbardJob, id: &JobId) -> Result<JobOutcome, anyhow::Error> {
+        let started = Instant::now();
+        let job_dir = self.result_root.join(job.dir());
+        let max_scf_cycles = Some(job.param().max_scf_cycles());
+        let mut running = false;
+        loop {
+            if self.cancel.is_cancelled() {
+                self.scheduler.cancel(id)?;
+                return Ok(JobOutcome::Cancelled);
+            }
+            if let Some(timed_out) = self.watcher.timed_out(started) {
+                self.scheduler.cancel(id)?;
+                return Ok(timed_out);
+            }
+            match self.poll_status(id)? {
+                JobStatus::Finished => break,
+                JobStatus::Running if !running => {
+                    running = true;
+                    self.record_state(job, JobState::Running)?;
+                }
+                _ => (),
+            }
+            thread::sleep(self.scheduler.poll_interval());
+        }
+        if self.scheduler.tracks_completion() {
+            return Ok(
+                inspect(&job_dir, job.seed_name(), max_scf_cycles).unwrap_or_else(|| {
+                    JobOutcome::Failed(FailureReason::Exit(format!(
+                        "job {id} left the queue without finishing the `.castep`"
+                    )))
+                }),
+            );
+        }
+        self.watcher.wait(
+            &job_dir,
+            job.seed_name(),
+            max_scf_cycles,
+            started,
+            &self.cancel,
+        )
+    }
+
+    fn write_result(&self, job: &HubbardJob, result_path: &Path) -> Result<(), anyhow::Error> {
+        let content = fs::read_to_string(job.castep_path(&self.result_root))?;
+        let job_name = job.job_name();
+        let mut file = OpenOptions::new().append(true).open(result_path)?;
+        CastepOutput::parse(&content)
+            .records()
+            .into_iter()
+            .try_for_each(|record| {
+                writeln!(
+                    file,
+                    "{}",
+                    ResultRow::new(&job_name, record, self.perturbed_channel)
+                )
+            })?;
+        Ok(())
+    }
+
+    fn gather_results(&self, chains: &[&PerturbChain]) -> Result<(), anyhow::Error> {
+        let mut final_result = File::create(self.final_result_path())?;
+        writeln!(final_result, "{RESULT_CSV_HEADER}")?;
+        chains.iter().try_for_each(|chain| {
+            let local = fs::read_to_string(self.local_result_path(chain))?;
+            final_result.write_all(local.as_bytes())
+        })?;
+        println!("Result: {}", self.final_result_path().display());
+        Ok(())
+    }
+}
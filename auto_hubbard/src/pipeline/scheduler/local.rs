@@ -0,0 +1,88 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+use super::{append_log, CommandRunner, JobId, JobSpec, JobStatus, Scheduler};
+
+/// The command to start `CASTEP` inside a job folder.
+/// The seed name is appended as the last argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CastepCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl FromStr for CastepCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut components = s.split_whitespace().map(String::from);
+        let program = components
+            .next()
+            .ok_or_else(|| anyhow!("Empty castep command"))?;
+        Ok(Self {
+            program,
+            args: components.collect(),
+        })
+    }
+}
+
+impl Display for CastepCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.program)?;
+        self.args.iter().try_for_each(|arg| write!(f, " {arg}"))
+    }
+}
+
+impl CastepCommand {
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// Arguments with the seed name appended
+    pub fn args_with_seed(&self, seed_name: &str) -> Vec<String> {
+        let mut args = self.args.clone();
+        args.push(seed_name.to_string());
+        args
+    }
+}
+
+/// Run the castep command on this machine and return once it exits.
+#[derive(Debug)]
+pub struct Local {
+    castep_command: CastepCommand,
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Local {
+    pub fn new(castep_command: CastepCommand, runner: Box<dyn CommandRunner>) -> Self {
+        Self {
+            castep_command,
+            runner,
+        }
+    }
+}
+
+impl Scheduler for Local {
+    fn submit(&self, job: &JobSpec) -> Result<JobId, anyhow::Error> {
+        let output = self.runner.run(
+            self.castep_command.program(),
+            &self.castep_command.args_with_seed(job.seed_name),
+            job.dir,
+        )?;
+        append_log(job.log, &output)?;
+        if !output.success {
+            bail!("Castep command failed for {}", job.job_name);
+        }
+        Ok(JobId::new(job.job_name))
+    }
+
+    /// The command has returned when `submit` does
+    fn status(&self, _id: &JobId) -> Result<JobStatus, anyhow::Error> {
+        Ok(JobStatus::Finished)
+    }
+
+    fn tracks_completion(&self) -> bool {
+        false
+    }
+}
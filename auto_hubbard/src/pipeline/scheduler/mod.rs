@@ -0,0 +1,330 @@
+//! Where the `CASTEP` jobs run: directly on this machine, or submitted to a
+//! PBS or Slurm batch system.
+
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Display},
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+
+mod local;
+mod pbs;
+mod slurm;
+
+pub use local::{CastepCommand, Local};
+pub use pbs::Pbs;
+pub use slurm::Slurm;
+
+/// Replaced by the folder name of the job, e.g.: `U_2_u_3`
+pub const JOB_NAME_PLACEHOLDER: &str = "{job_name}";
+/// Replaced by the seed name, e.g.: `GDY_111_Fe_U`
+pub const SEED_NAME_PLACEHOLDER: &str = "{seed_name}";
+/// Replaced by the castep command, e.g.: `mpirun castep.mpi`
+pub const CASTEP_COMMAND_PLACEHOLDER: &str = "{castep_command}";
+
+/// What a finished external command left behind.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs external programs (`castep`, `qsub`, `qstat`, `sbatch`, `squeue`).
+/// Replaced by a mock in tests so the schedulers can be checked without a batch system.
+pub trait CommandRunner: Debug + Send + Sync {
+    fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        cwd: &Path,
+    ) -> Result<CommandOutput, anyhow::Error>;
+}
+
+/// Ids of the processes started by this program, so an interruption
+/// terminates them and nothing else.
+#[derive(Debug, Default)]
+pub struct ChildProcesses(Mutex<HashSet<u32>>);
+
+impl ChildProcesses {
+    fn insert(&self, pid: u32) {
+        if let Ok(mut pids) = self.0.lock() {
+            pids.insert(pid);
+        }
+    }
+
+    fn remove(&self, pid: u32) {
+        if let Ok(mut pids) = self.0.lock() {
+            pids.remove(&pid);
+        }
+    }
+
+    /// Send `SIGTERM` to every process still running
+    pub fn terminate_all(&self) {
+        if let Ok(pids) = self.0.lock() {
+            pids.iter().for_each(|&pid| {
+                // SAFETY: `kill` has no memory effects. `SystemRunner::run` removes the pid
+                // before reaping the child, so a pid in the set can not have been reused.
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                }
+            });
+        }
+    }
+}
+
+/// Runs the programs with `std::process::Command`
+#[derive(Debug, Clone, Default)]
+pub struct SystemRunner {
+    children: Arc<ChildProcesses>,
+}
+
+impl SystemRunner {
+    pub fn new(children: Arc<ChildProcesses>) -> Self {
+        Self { children }
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        cwd: &Path,
+    ) -> Result<CommandOutput, anyhow::Error> {
+        let mut child = Command::new(program)
+            .args(args)
+            .current_dir(cwd)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start `{program}`"))?;
+        let pid = child.id();
+        self.children.insert(pid);
+        // Both pipes are read to the end, so the child never blocks on a full one
+        let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+        let stderr_reader = thread::spawn(move || {
+            let mut stderr = Vec::new();
+            stderr_pipe.read_to_end(&mut stderr).map(|_| stderr)
+        });
+        let mut stdout = Vec::new();
+        let stdout_read = child
+            .stdout
+            .take()
+            .expect("stdout is piped")
+            .read_to_end(&mut stdout);
+        let stderr = stderr_reader
+            .join()
+            .map_err(|_| anyhow!("Reading the stderr of `{program}` panicked"));
+        // The exited child stays a zombie holding its pid until it is reaped,
+        // so the pid is removed in between and `terminate_all` never hits a reused one.
+        wait_for_exit(pid);
+        self.children.remove(pid);
+        let status = child
+            .wait()
+            .with_context(|| format!("Failed to wait for `{program}`"))?;
+        stdout_read.with_context(|| format!("Failed to read the stdout of `{program}`"))?;
+        let stderr =
+            stderr?.with_context(|| format!("Failed to read the stderr of `{program}`"))?;
+        Ok(CommandOutput {
+            success: status.success(),
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+        })
+    }
+}
+
+/// Block until the child `pid` has exited, without reaping it
+fn wait_for_exit(pid: u32) {
+    // SAFETY: `siginfo_t` is plain data that `waitid` fills in, and `WNOWAIT` leaves
+    // the child to be reaped by `Child::wait`.
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        while libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+        ) == -1
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+        {}
+    }
+}
+
+/// A job to hand over to the scheduler
+#[derive(Debug, Clone, Copy)]
+pub struct JobSpec<'a> {
+    /// Absolute path of the job folder, holding the `.cell` and `.param`
+    pub dir: &'a Path,
+    pub seed_name: &'a str,
+    /// Short name shown in the queue
+    pub job_name: &'a str,
+    /// Where the output of the castep command or of the submission goes
+    pub log: &'a Path,
+}
+
+/// The id given by the scheduler on submission
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JobId(String);
+
+impl JobId {
+    pub fn new(id: &str) -> Self {
+        Self(id.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// The job has left the queue, whether it succeeded or not
+    Finished,
+}
+
+pub trait Scheduler: Debug + Send + Sync {
+    /// Start the job and return the id to poll its status with.
+    fn submit(&self, job: &JobSpec) -> Result<JobId, anyhow::Error>;
+    fn status(&self, id: &JobId) -> Result<JobStatus, anyhow::Error>;
+    /// Time between two status queries
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+    /// Remove a submitted job from the queue.
+    /// A local job is stopped with the other child processes instead.
+    fn cancel(&self, _id: &JobId) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    /// Whether `Finished` means the `.castep` is complete.
+    /// A local castep command may itself submit to a queue, so `Local` can not tell.
+    fn tracks_completion(&self) -> bool {
+        true
+    }
+}
+
+/// Retries of a failed status query before the job is given up, see `status_with_retries`
+pub const STATUS_RETRIES: u32 = 4;
+
+/// Query the status of `id`, retrying up to `retries` times with a delay doubling from `delay`,
+/// since `qstat` and `squeue` fail now and then while the batch server is busy.
+pub fn status_with_retries(
+    scheduler: &dyn Scheduler,
+    id: &JobId,
+    delay: Duration,
+    retries: u32,
+) -> Result<JobStatus, anyhow::Error> {
+    let mut delay = delay;
+    let mut failed = 0;
+    loop {
+        match scheduler.status(id) {
+            Err(e) if failed < retries => {
+                eprintln!("Failed to query the status of job {id}, retry in {delay:?}: {e:#}");
+                thread::sleep(delay);
+                delay *= 2;
+                failed += 1;
+            }
+            status => return status,
+        }
+    }
+}
+
+/// Fill the placeholders of a job script template
+pub fn render_template(template: &str, job: &JobSpec, castep_command: &str) -> String {
+    template
+        .replace(JOB_NAME_PLACEHOLDER, job.job_name)
+        .replace(SEED_NAME_PLACEHOLDER, job.seed_name)
+        .replace(CASTEP_COMMAND_PLACEHOLDER, castep_command)
+}
+
+/// Render the job script into the job folder as `[seed_name].[extension]`, returning its file name.
+fn write_job_script(
+    job: &JobSpec,
+    template: &str,
+    castep_command: &str,
+    extension: &str,
+) -> Result<String, anyhow::Error> {
+    let script_name = format!("{}.{extension}", job.seed_name);
+    fs::write(
+        job.dir.join(&script_name),
+        render_template(template, job, castep_command),
+    )
+    .with_context(|| format!("Failed to write job script for {}", job.job_name))?;
+    Ok(script_name)
+}
+
+/// Append the output of a command to the job log
+fn append_log(log: &Path, output: &CommandOutput) -> Result<(), anyhow::Error> {
+    let mut log = OpenOptions::new().create(true).append(true).open(log)?;
+    log.write_all(output.stdout.as_bytes())?;
+    log.write_all(output.stderr.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{CommandOutput, CommandRunner};
+
+    /// Replies with the queued outputs in order and records the calls.
+    #[derive(Debug, Default)]
+    pub(super) struct MockRunner {
+        pub(super) outputs: Mutex<Vec<CommandOutput>>,
+        pub(super) calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockRunner {
+        /// `(success, output)` of each call in turn, the output going to stderr on failure
+        pub(super) fn new(outputs: Vec<(bool, &str)>) -> Self {
+            Self {
+                outputs: Mutex::new(
+                    outputs
+                        .into_iter()
+                        .rev()
+                        .map(|(success, output)| CommandOutput {
+                            success,
+                            stdout: if success { output } else { "" }.to_string(),
+                            stderr: if success { "" } else { output }.to_string(),
+                        })
+                        .collect(),
+                ),
+                calls: Arc::default(),
+            }
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn run(
+            &self,
+            program: &str,
+            args: &[String],
+            _cwd: &Path,
+        ) -> Result<CommandOutput, anyhow::Error> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{program} {}", args.join(" ")));
+            Ok(self.outputs.lock().unwrap().pop().unwrap_or_default())
+        }
+    }
+}
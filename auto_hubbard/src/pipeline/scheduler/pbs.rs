@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+
+use super::{
+    append_log, write_job_script, CastepCommand, CommandRunner, JobId, JobSpec, JobStatus,
+    Scheduler,
+};
+
+/// Used when no `--job-template` is given
+pub const DEFAULT_PBS_TEMPLATE: &str = "#!/bin/bash
+#PBS -N {job_name}
+#PBS -j oe
+cd $PBS_O_WORKDIR
+{castep_command} {seed_name}
+";
+
+/// Submit with `qsub` and poll with `qstat`
+#[derive(Debug)]
+pub struct Pbs {
+    template: String,
+    castep_command: String,
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Pbs {
+    pub fn new(
+        template: Option<String>,
+        castep_command: &CastepCommand,
+        runner: Box<dyn CommandRunner>,
+    ) -> Self {
+        Self {
+            template: template.unwrap_or_else(|| DEFAULT_PBS_TEMPLATE.to_string()),
+            castep_command: castep_command.to_string(),
+            runner,
+        }
+    }
+}
+
+/// The job state column of `qstat <id>`:
+/// ```text
+/// Job id            Name             User              Time Use S Queue
+/// ----------------  ---------------- ----------------  -------- - -----
+/// 1234.server       U_2_u_3          user              00:00:01 R workq
+/// ```
+fn parse_qstat(stdout: &str, id: &JobId) -> JobStatus {
+    let state = stdout
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .find(|fields| {
+            fields.first().is_some_and(|job_id| {
+                id.as_str().starts_with(job_id.trim_end_matches('*'))
+                    || job_id.starts_with(id.as_str())
+            })
+        })
+        .and_then(|fields| fields.get(4).copied());
+    match state {
+        Some("Q" | "H" | "W" | "T" | "S") => JobStatus::Queued,
+        Some("R" | "E" | "B") => JobStatus::Running,
+        _ => JobStatus::Finished,
+    }
+}
+
+/// `qstat: Unknown Job Id 1234.server` (Torque, OpenPBS), or for a job kept in the history of
+/// PBS Pro: `qstat: 1234.server Job has finished, use -x or -H to obtain historical job information`
+fn is_unknown_job(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("unknown job id") || stderr.contains("job has finished")
+}
+
+impl Scheduler for Pbs {
+    fn submit(&self, job: &JobSpec) -> Result<JobId, anyhow::Error> {
+        let script = write_job_script(job, &self.template, &self.castep_command, "pbs")?;
+        let output = self.runner.run("qsub", &[script], job.dir)?;
+        append_log(job.log, &output)?;
+        if !output.success {
+            bail!("qsub failed for {}: {}", job.job_name, output.stderr.trim());
+        }
+        output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(JobId::new)
+            .ok_or_else(|| anyhow!("qsub returned no job id for {}", job.job_name))
+    }
+
+    /// `qstat` fails once the job is no longer known to the server,
+    /// any other failure (server down, timeout) is an error
+    fn status(&self, id: &JobId) -> Result<JobStatus, anyhow::Error> {
+        let output = self
+            .runner
+            .run("qstat", &[id.to_string()], std::path::Path::new("."))?;
+        if !output.success {
+            if is_unknown_job(&output.stderr) {
+                return Ok(JobStatus::Finished);
+            }
+            bail!("qstat failed for {id}: {}", output.stderr.trim());
+        }
+        Ok(parse_qstat(&output.stdout, id))
+    }
+
+    fn cancel(&self, id: &JobId) -> Result<(), anyhow::Error> {
+        let output = self
+            .runner
+            .run("qdel", &[id.to_string()], std::path::Path::new("."))?;
+        if !output.success {
+            bail!("qdel failed for {id}: {}", output.stderr.trim());
+        }
+        Ok(())
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use crate::pipeline::fixtures::test_dir;
+
+    use super::super::{
+        status_with_retries, test::MockRunner, CastepCommand, JobId, JobSpec, JobStatus, Scheduler,
+    };
+
+    use super::Pbs;
+
+    #[test]
+    fn pbs_submit_and_poll() {
+        let dir = test_dir("auto_hubbard_pbs_test");
+        let runner = MockRunner::new(vec![
+            (true, "1234.server\n"),
+            (
+                true,
+                "Job id  Name  User  Time Use S Queue\n------ ---- ---- ---- - -----\n1234.server  U_2_u_3  user  00:00:01 R workq\n",
+            ),
+            (false, "qstat: Unknown Job Id 1234.server\n"),
+            (false, "Connection refused\nqstat: cannot connect to server server (errno=111)\n"),
+        ]);
+        let calls = runner.calls.clone();
+        let castep: CastepCommand = "mpirun castep.mpi".parse().unwrap();
+        let pbs = Pbs::new(None, &castep, Box::new(runner));
+        let job = JobSpec {
+            dir: &dir,
+            seed_name: "GDY_111_Fe_U",
+            job_name: "U_2_u_3",
+            log: &dir.join("log_u.txt"),
+        };
+        let id = pbs.submit(&job).unwrap();
+        assert_eq!(id, JobId::new("1234.server"));
+        let script = fs::read_to_string(dir.join("GDY_111_Fe_U.pbs")).unwrap();
+        assert!(script.contains("#PBS -N U_2_u_3"));
+        assert!(script.contains("mpirun castep.mpi GDY_111_Fe_U"));
+        assert_eq!(pbs.status(&id).unwrap(), JobStatus::Running);
+        assert_eq!(pbs.status(&id).unwrap(), JobStatus::Finished);
+        // The job is not known to have finished while the server is down
+        assert!(pbs.status(&id).is_err());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "qsub GDY_111_Fe_U.pbs",
+                "qstat 1234.server",
+                "qstat 1234.server",
+                "qstat 1234.server"
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn retry_failed_status() {
+        let runner = MockRunner::new(vec![
+            (false, "qstat: cannot connect to server server (errno=111)\n"),
+            (false, "qstat: cannot connect to server server (errno=111)\n"),
+            (
+                true,
+                "Job id  Name  User  Time Use S Queue\n------ ---- ---- ---- - -----\n1234.server  U_2_u_3  user  00:00:01 Q workq\n",
+            ),
+            (false, "qstat: cannot connect to server server (errno=111)\n"),
+        ]);
+        let calls = runner.calls.clone();
+        let castep: CastepCommand = "mpirun castep.mpi".parse().unwrap();
+        let pbs = Pbs::new(None, &castep, Box::new(runner));
+        let id = JobId::new("1234.server");
+        assert_eq!(
+            status_with_retries(&pbs, &id, Duration::ZERO, 2).unwrap(),
+            JobStatus::Queued
+        );
+        assert_eq!(calls.lock().unwrap().len(), 3);
+        // Out of retries, the job is given up rather than taken as finished
+        assert!(status_with_retries(&pbs, &id, Duration::ZERO, 0).is_err());
+    }
+}
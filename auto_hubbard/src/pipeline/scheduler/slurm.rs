@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+
+use super::{
+    append_log, write_job_script, CastepCommand, CommandRunner, JobId, JobSpec, JobStatus,
+    Scheduler,
+};
+
+/// Used when no `--job-template` is given
+pub const DEFAULT_SLURM_TEMPLATE: &str = "#!/bin/bash
+#SBATCH --job-name={job_name}
+#SBATCH --output={job_name}.out
+{castep_command} {seed_name}
+";
+
+/// Submit with `sbatch` and poll with `squeue`
+#[derive(Debug)]
+pub struct Slurm {
+    template: String,
+    castep_command: String,
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Slurm {
+    pub fn new(
+        template: Option<String>,
+        castep_command: &CastepCommand,
+        runner: Box<dyn CommandRunner>,
+    ) -> Self {
+        Self {
+            template: template.unwrap_or_else(|| DEFAULT_SLURM_TEMPLATE.to_string()),
+            castep_command: castep_command.to_string(),
+            runner,
+        }
+    }
+}
+
+/// The `%T` field of `squeue`, empty once the job has left the queue
+fn parse_squeue(stdout: &str) -> JobStatus {
+    match stdout.trim() {
+        "PENDING" | "CONFIGURING" | "REQUEUED" | "SUSPENDED" => JobStatus::Queued,
+        "RUNNING" | "COMPLETING" => JobStatus::Running,
+        _ => JobStatus::Finished,
+    }
+}
+
+impl Scheduler for Slurm {
+    fn submit(&self, job: &JobSpec) -> Result<JobId, anyhow::Error> {
+        let script = write_job_script(job, &self.template, &self.castep_command, "slurm")?;
+        let output = self
+            .runner
+            .run("sbatch", &["--parsable".to_string(), script], job.dir)?;
+        append_log(job.log, &output)?;
+        if !output.success {
+            bail!(
+                "sbatch failed for {}: {}",
+                job.job_name,
+                output.stderr.trim()
+            );
+        }
+        // `--parsable` prints `jobid[;cluster]`
+        output
+            .stdout
+            .trim()
+            .split(';')
+            .next()
+            .filter(|id| !id.is_empty())
+            .map(JobId::new)
+            .ok_or_else(|| anyhow!("sbatch returned no job id for {}", job.job_name))
+    }
+
+    /// `squeue` fails on a job id purged from the controller,
+    /// any other failure (controller down, timeout) is an error
+    fn status(&self, id: &JobId) -> Result<JobStatus, anyhow::Error> {
+        let args = ["-h", "-j", id.as_str(), "-o", "%T"].map(String::from);
+        let output = self
+            .runner
+            .run("squeue", &args, std::path::Path::new("."))?;
+        if !output.success {
+            if output
+                .stderr
+                .to_lowercase()
+                .contains("invalid job id specified")
+            {
+                return Ok(JobStatus::Finished);
+            }
+            bail!("squeue failed for {id}: {}", output.stderr.trim());
+        }
+        Ok(parse_squeue(&output.stdout))
+    }
+
+    fn cancel(&self, id: &JobId) -> Result<(), anyhow::Error> {
+        let output = self
+            .runner
+            .run("scancel", &[id.to_string()], std::path::Path::new("."))?;
+        if !output.success {
+            bail!("scancel failed for {id}: {}", output.stderr.trim());
+        }
+        Ok(())
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pipeline::fixtures::test_dir;
+
+    use super::super::{test::MockRunner, CastepCommand, JobId, JobSpec, JobStatus, Scheduler};
+
+    use super::Slurm;
+
+    #[test]
+    fn slurm_submit_and_poll() {
+        let dir = test_dir("auto_hubbard_slurm_test");
+        let runner = MockRunner::new(vec![
+            (true, "5678;cluster\n"),
+            (true, "PENDING\n"),
+            (true, ""),
+            (false, "slurm_load_jobs error: Invalid job id specified\n"),
+            (
+                false,
+                "slurm_load_jobs error: Unable to contact slurm controller (connect failure)\n",
+            ),
+        ]);
+        let calls = runner.calls.clone();
+        let castep: CastepCommand = "srun castep.mpi".parse().unwrap();
+        let template = "#!/bin/bash\n#SBATCH -J {job_name}\n{castep_command} {seed_name}\n";
+        let slurm = Slurm::new(Some(template.to_string()), &castep, Box::new(runner));
+        let job = JobSpec {
+            dir: &dir,
+            seed_name: "GDY_111_Fe_U",
+            job_name: "U_2_u_3",
+            log: &dir.join("log_u.txt"),
+        };
+        let id = slurm.submit(&job).unwrap();
+        assert_eq!(id, JobId::new("5678"));
+        assert_eq!(
+            fs::read_to_string(dir.join("GDY_111_Fe_U.slurm")).unwrap(),
+            "#!/bin/bash\n#SBATCH -J U_2_u_3\nsrun castep.mpi GDY_111_Fe_U\n"
+        );
+        assert_eq!(slurm.status(&id).unwrap(), JobStatus::Queued);
+        assert_eq!(slurm.status(&id).unwrap(), JobStatus::Finished);
+        assert_eq!(slurm.status(&id).unwrap(), JobStatus::Finished);
+        assert!(slurm.status(&id).is_err());
+        assert_eq!(
+            calls.lock().unwrap()[0],
+            "sbatch --parsable GDY_111_Fe_U.slurm"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
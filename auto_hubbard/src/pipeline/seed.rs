@@ -0,0 +1,243 @@
+use std::{
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context};
+use castep_periodic_table::element::ElementSymbol;
+
+use crate::seed_settings::{CellFile, HubbardUCell, HubbardUParam, Init, ParamFile};
+
+use super::{
+    manifest::{seed_hash, MANIFEST_FILE},
+    pseudo::{locate_potentials, PseudoKind, PseudoLibrary},
+    HubArguments,
+};
+
+/// File extensions which are never copied into the job folders.
+/// Same filter as the `find ... -not -name` in the original shell workflow,
+/// plus `.cell` and `.param` which are always regenerated,
+/// the pseudopotentials, of which only those in `SPECIES_POT` are copied,
+/// and `.err`, which would fail the job it is copied into.
+const EXCLUDED_EXTENSIONS: [&str; 13] = [
+    "castep", "txt", "csv", "xsd", "xms", "cell", "param", "usp", "uspcc", "uspso", "recpot",
+    "ncp", "err",
+];
+
+/// Extension prefix of the outputs of earlier attempts, `[file].attempt_[n]`,
+/// see `HubbardJob::archive_outputs`. Never copied either.
+pub const ARCHIVE_EXTENSION: &str = "attempt_";
+
+/// The user provided seed folder, holding exactly one `.cell` and one `.param`.
+#[derive(Debug, Clone)]
+pub struct SeedFolder {
+    path: PathBuf,
+    seed_name: String,
+    cell: HubbardUCell<Init>,
+    param: HubbardUParam<Init>,
+    /// sha256 of the `.cell` and `.param` contents
+    hash: String,
+    /// The pseudopotential files named in `SPECIES_POT`, set by `resolve_pseudopotentials`
+    potentials: Vec<PathBuf>,
+}
+
+impl SeedFolder {
+    /// Look for the `.cell` and `.param` in `path` and deserialize them.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        let cell_path = find_by_extension(&path, "cell")?;
+        let seed_name = cell_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .ok_or_else(|| anyhow!("Invalid `.cell` file name: {}", cell_path.display()))?;
+        let param_path = path.join(format!("{seed_name}.param"));
+        let cell_content = read_to_string(&cell_path)?;
+        let param_content = read_to_string(&param_path)
+            .with_context(|| format!("Missing {}", param_path.display()))?;
+        let cell = cell_content
+            .parse::<CellFile>()
+            .map(HubbardUCell::from_cell_file)
+            .map_err(|e| anyhow!("Failed to parse {}: {e}", cell_path.display()))?;
+        let param = param_content
+            .parse::<ParamFile>()
+            .map(HubbardUParam::from_param)
+            .map_err(|e| anyhow!("Failed to parse {}: {e}", param_path.display()))?;
+        Ok(Self {
+            path,
+            seed_name,
+            cell,
+            param,
+            hash: seed_hash(&cell_content, &param_content),
+            potentials: Vec::new(),
+        })
+    }
+
+    /// Switch every species of `SPECIES_POT` to a potential of `kind` if given,
+    /// then check the pseudopotential files exist in the seed folder or the library.
+    pub fn resolve_pseudopotentials(
+        self,
+        kind: Option<&PseudoKind>,
+        library: Option<&Path>,
+    ) -> Result<Self, anyhow::Error> {
+        let library = library.map(PseudoLibrary::new).transpose()?;
+        let mut cell = self.cell.cell.clone();
+        if let Some(kind) = kind {
+            let potentials = cell
+                .species_potentials()
+                .into_iter()
+                .map(|(species, _)| {
+                    let potential = match (&library, kind) {
+                        (_, PseudoKind::OnTheFly(otfg)) => otfg.clone(),
+                        (Some(library), _) => library.potential(&species, kind)?,
+                        (None, _) => return Err(anyhow!("`--pseudo {kind}` needs `--pseudo-lib`")),
+                    };
+                    Ok((species, potential))
+                })
+                .collect::<Result<Vec<(String, String)>, anyhow::Error>>()?;
+            cell = cell.with_species_potentials(&potentials);
+        }
+        let potentials =
+            locate_potentials(&cell.species_potentials(), &self.path, library.as_ref())?;
+        Ok(Self {
+            cell: HubbardUCell::from_cell_file(cell),
+            potentials,
+            ..self
+        })
+    }
+
+    /// Generate `HUBBARD_U` with `u_value` if the seed has none, see
+    /// `CellFile::with_generated_hubbard_u`.
+    pub fn with_hubbard_u(self, u_value: f64, ligands: &[String]) -> Result<Self, anyhow::Error> {
+        let ligands = ligands
+            .iter()
+            .map(|ligand| {
+                ElementSymbol::from_str(ligand)
+                    .map_err(|_| anyhow!("`--ligand-p {ligand}` is not an element"))
+            })
+            .collect::<Result<Vec<ElementSymbol>, anyhow::Error>>()?;
+        let cell = self
+            .cell
+            .cell
+            .with_generated_hubbard_u(u_value, &ligands)
+            .map_err(|e| anyhow!("{}: {e}", self.path.display()))?;
+        Ok(Self {
+            cell: HubbardUCell::from_cell_file(cell),
+            ..self
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn seed_name(&self) -> &str {
+        &self.seed_name
+    }
+
+    pub fn cell(&self) -> &HubbardUCell<Init> {
+        &self.cell
+    }
+
+    pub fn param(&self) -> &HubbardUParam<Init> {
+        &self.param
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn potentials(&self) -> &[PathBuf] {
+        &self.potentials
+    }
+
+    /// The folder holding all jobs of this run, created inside the seed folder:
+    /// `[seed][_site]_[jobtype]_[init_u]_[step_u]_[final_u]_[perturb_init]_[perturb_step]_[perturb_final]_STEPS_[n]`
+    pub fn result_folder(&self, args: &HubArguments) -> PathBuf {
+        let folder_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.seed_name.clone());
+        // Runs of different sites go to their own folders, to be combined in the analysis
+        let site = args
+            .site()
+            .map(|site| format!("_{}", site.folder_tag()))
+            .unwrap_or_default();
+        self.path
+            .join(format!("{folder_name}{site}_{}", args.folder_suffix()))
+    }
+}
+
+/// Find the first file with the given extension directly under `dir`.
+pub fn find_by_extension(dir: &Path, extension: &str) -> Result<PathBuf, anyhow::Error> {
+    fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.is_file() && path.extension().is_some_and(|ext| ext == extension))
+        .ok_or_else(|| anyhow!("No `.{extension}` file found in {}", dir.display()))
+}
+
+/// Copy the auxiliary files (`.check`, job scripts, etc.)
+/// directly under `src` into `dest`, leaving out the `run.toml` manifest
+/// and the archived outputs of earlier attempts.
+pub fn copy_aux_files(src: &Path, dest: &Path) -> Result<(), anyhow::Error> {
+    fs::create_dir_all(dest)?;
+    fs::read_dir(src)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path.file_name().is_some_and(|name| name != MANIFEST_FILE)
+                && !path.extension().is_some_and(|ext| {
+                    EXCLUDED_EXTENSIONS
+                        .iter()
+                        .any(|excluded| ext.eq_ignore_ascii_case(excluded))
+                        || ext.to_string_lossy().starts_with(ARCHIVE_EXTENSION)
+                })
+        })
+        .try_for_each(|path| {
+            let file_name = path.file_name().expect("Files always have a name");
+            fs::copy(&path, dest.join(file_name))
+                .map(|_| ())
+                .with_context(|| format!("Failed to copy {}", path.display()))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pipeline::fixtures::test_dir;
+
+    use super::{copy_aux_files, MANIFEST_FILE};
+
+    #[test]
+    fn aux_files_of_init_job() {
+        let dir = test_dir("auto_hubbard_aux_files");
+        let (init, perturbed) = (dir.join("U_2_u"), dir.join("U_2_u/U_2_u_1"));
+        fs::create_dir_all(&init).unwrap();
+        [
+            "NiO.check",
+            "NiO.castep",
+            "NiO.0001.err",
+            "NiO.castep.attempt_1",
+            "NiO.0001.err.attempt_1",
+            "NiO.cell",
+            MANIFEST_FILE,
+        ]
+        .iter()
+        .for_each(|name| fs::write(init.join(name), "content").unwrap());
+        copy_aux_files(&init, &perturbed).unwrap();
+        let mut copied = fs::read_dir(&perturbed)
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        copied.sort();
+        assert_eq!(copied, ["NiO.check"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
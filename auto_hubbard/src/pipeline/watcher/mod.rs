@@ -0,0 +1,82 @@
+//! Wait for a job to end and tell how it ended, woken by file events in its folder
+//! instead of re-reading the `.castep` every second.
+
+use std::{
+    path::Path,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use notify::{RecursiveMode, Watcher};
+
+use super::CancelToken;
+
+mod outcome;
+
+pub use outcome::{castep_outcome, inspect, FailureReason, JobOutcome};
+
+/// Look at the folder at least this often, since file events can be missed
+/// on network file systems.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompletionWatcher {
+    /// Give up on a job after this long, never by default
+    timeout: Option<Duration>,
+}
+
+impl CompletionWatcher {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self { timeout }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Whether `started` is longer ago than the timeout
+    pub fn timed_out(&self, started: Instant) -> Option<JobOutcome> {
+        self.timeout
+            .filter(|timeout| started.elapsed() >= *timeout)
+            .map(JobOutcome::TimedOut)
+    }
+
+    /// Block until `inspect` gives an outcome for the job in `job_dir`,
+    /// the timeout counting from `started`.
+    pub fn wait(
+        &self,
+        job_dir: &Path,
+        seed_name: &str,
+        max_scf_cycles: Option<usize>,
+        started: Instant,
+        cancel: &CancelToken,
+    ) -> Result<JobOutcome, anyhow::Error> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver is gone once we have returned
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(job_dir, RecursiveMode::NonRecursive)?;
+        loop {
+            if let Some(outcome) = inspect(job_dir, seed_name, max_scf_cycles) {
+                return Ok(outcome);
+            }
+            if cancel.is_cancelled() {
+                return Ok(JobOutcome::Cancelled);
+            }
+            if let Some(timed_out) = self.timed_out(started) {
+                return Ok(timed_out);
+            }
+            match events.recv_timeout(RESCAN_INTERVAL) {
+                Ok(event) => {
+                    event.map_err(|e| anyhow!("Failed to watch {}: {e}", job_dir.display()))?;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("Stopped watching {}", job_dir.display()))
+                }
+            }
+        }
+    }
+}
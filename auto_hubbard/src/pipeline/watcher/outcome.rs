@@ -0,0 +1,191 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::castep_output::CastepOutput;
+
+/// Lines of the `.castep` starting with these report a fatal error
+const ERROR_BANNERS: [&str; 2] = ["Error ", "*** Error"];
+
+/// How a job ended, as far as its folder and the scheduler tell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobOutcome {
+    /// `Finalisation time` after a converged SCF
+    Finished,
+    /// The SCF stopped after `scf_cycles` cycles without reaching `elec_energy_tol`
+    Unconverged {
+        scf_cycles: usize,
+    },
+    Failed(FailureReason),
+    /// No outcome within the timeout
+    TimedOut(Duration),
+    /// The run was interrupted
+    Cancelled,
+}
+
+impl JobOutcome {
+    /// Whether the `.castep` holds results to be written
+    pub fn has_results(&self) -> bool {
+        matches!(self, JobOutcome::Finished | JobOutcome::Unconverged { .. })
+    }
+}
+
+impl Display for JobOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobOutcome::Finished => f.write_str("finished"),
+            JobOutcome::Unconverged { scf_cycles } => {
+                write!(f, "SCF not converged after {scf_cycles} cycles")
+            }
+            JobOutcome::Failed(reason) => write!(f, "failed: {reason}"),
+            JobOutcome::TimedOut(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+            JobOutcome::Cancelled => f.write_str("cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureReason {
+    /// `[seed].[node].err` written by `CASTEP`
+    ErrFile { path: PathBuf, message: String },
+    /// An error banner in the `.castep`
+    ErrorBanner(String),
+    /// The castep command exited abnormally, or the batch job left the queue
+    /// without a finished `.castep`
+    Exit(String),
+}
+
+impl Display for FailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureReason::ErrFile { path, message } => {
+                write!(f, "{}: {message}", path.display())
+            }
+            FailureReason::ErrorBanner(line) => f.write_str(line),
+            FailureReason::Exit(message) => f.write_str(message),
+        }
+    }
+}
+
+/// The first non-empty `.err` of the seed in `job_dir`
+fn err_file(job_dir: &Path, seed_name: &str) -> Option<FailureReason> {
+    let prefix = format!("{seed_name}.");
+    fs::read_dir(job_dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "err")
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .find_map(|path| {
+            let content = fs::read_to_string(&path).ok()?;
+            let message = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .take(3)
+                .collect::<Vec<&str>>()
+                .join(" ");
+            (!message.is_empty()).then_some(FailureReason::ErrFile { path, message })
+        })
+}
+
+/// Classify the `.castep` content
+pub fn castep_outcome(castep_content: &str, max_scf_cycles: Option<usize>) -> Option<JobOutcome> {
+    if let Some(banner) = castep_content
+        .lines()
+        .map(str::trim)
+        .find(|line| ERROR_BANNERS.iter().any(|banner| line.starts_with(banner)))
+    {
+        return Some(JobOutcome::Failed(FailureReason::ErrorBanner(
+            banner.to_string(),
+        )));
+    }
+    let output = CastepOutput::parse(castep_content);
+    let scf_cycles = output.cycles().last().map_or(0, |cycle| cycle.index);
+    let out_of_cycles = max_scf_cycles.is_some_and(|max| scf_cycles >= max);
+    match (output.finished(), output.converged()) {
+        (true, true) => Some(JobOutcome::Finished),
+        (true, false) => Some(JobOutcome::Unconverged { scf_cycles }),
+        // The SCF is over, `CASTEP` is only writing the summary
+        (false, false) if out_of_cycles => Some(JobOutcome::Unconverged { scf_cycles }),
+        (false, _) => None,
+    }
+}
+
+/// Classify what the job left in `job_dir`, `None` while it is still going.
+pub fn inspect(
+    job_dir: &Path,
+    seed_name: &str,
+    max_scf_cycles: Option<usize>,
+) -> Option<JobOutcome> {
+    if let Some(reason) = err_file(job_dir, seed_name) {
+        return Some(JobOutcome::Failed(reason));
+    }
+    let castep = fs::read_to_string(job_dir.join(format!("{seed_name}.castep"))).ok()?;
+    castep_outcome(&castep, max_scf_cycles)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pipeline::fixtures::test_dir;
+
+    use super::{castep_outcome, inspect, FailureReason, JobOutcome};
+
+    const SCF: &str =
+        "Initial  -3.63546574E+003  0.00000000E+000                        19.81  <-- SCF
+      1  -3.63458626E+003 -2.08120628E+000  -4.62879811E-002      40.23  <-- SCF
+      2  -3.63458627E+003 -2.08120628E+000  -4.62879811E-007      60.23  <-- SCF
+";
+
+    #[test]
+    fn classify_castep() {
+        assert_eq!(castep_outcome(SCF, Some(100)), None);
+        assert_eq!(
+            castep_outcome(SCF, Some(2)),
+            Some(JobOutcome::Unconverged { scf_cycles: 2 })
+        );
+        let converged = format!("{SCF}Total energy has converged\nFinalisation time = 1 s\n");
+        assert_eq!(
+            castep_outcome(&converged, Some(2)),
+            Some(JobOutcome::Finished)
+        );
+        let unconverged = format!("{SCF}Finalisation time = 1 s\n");
+        assert_eq!(
+            castep_outcome(&unconverged, None),
+            Some(JobOutcome::Unconverged { scf_cycles: 2 })
+        );
+        assert!(matches!(
+            castep_outcome(&format!("{SCF} Error in allocating memory\n"), None),
+            Some(JobOutcome::Failed(FailureReason::ErrorBanner(_)))
+        ));
+    }
+
+    #[test]
+    fn err_file_fails_job() {
+        let dir = test_dir("auto_hubbard_outcome_test");
+        fs::write(dir.join("GDY_111_Fe_U.castep"), SCF).unwrap();
+        assert_eq!(inspect(&dir, "GDY_111_Fe_U", None), None);
+        fs::write(
+            dir.join("GDY_111_Fe_U.0001.err"),
+            "\n Unable to open pseudopotential\n",
+        )
+        .unwrap();
+        assert_eq!(
+            inspect(&dir, "GDY_111_Fe_U", None),
+            Some(JobOutcome::Failed(FailureReason::ErrFile {
+                path: dir.join("GDY_111_Fe_U.0001.err"),
+                message: "Unable to open pseudopotential".to_string()
+            }))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,39 +1,300 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, str::FromStr};
 
 use castep_cell_data::{
     cell::{
-        bz_sampling_kpoints::KpointsList,
-        constraints::{CellConstraints, FixCOM, IonicConstraints},
-        external_fields::ExternalEfield,
-        lattice_param::LatticeCart,
-        positions::PositionsFrac,
+        bz_sampling_kpoints::{KpointsList, KpointsMpGrid, KpointsMpOffset, KpointsMpSpacing},
         species::{
-            AtomHubbardU, HubbardAlpha, HubbardU, QuantizationAxis, SpeciesLcaoStates, SpeciesMass,
-            SpeciesPot,
+            HubbardAlpha, HubbardU, Species, SpeciesLcaoStates, SpeciesMass, SpeciesPot,
+            SpeciesPotEntry,
         },
     },
-    ToCellFileDerive,
+    from_str, ToCellFile, ToCellFileDerive,
 };
+use castep_periodic_table::element::ElementSymbol;
 use serde::{Deserialize, Serialize};
 
-use super::{BeforePerturb, Init, JobType, Perturbed, Stage};
+use super::{
+    geometry::Geometry,
+    hubbard::{hubbard_u_lines, split_species_lines, HubbardBlock, HubbardSpeciesError, Orbital},
+    keyword_file::{Entry, KeywordFile},
+    BeforePerturb, HubbardSelector, Init, JobType, KpointSampling, Perturbed, Stage,
+};
 
+/// The blocks and keywords we read or change, all optional.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToCellFileDerive)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub struct CellFile {
-    lattice_cart: LatticeCart,
-    positions_frac: PositionsFrac,
-    kpoints_list: KpointsList,
-    cell_constraints: CellConstraints,
-    fix_com: FixCOM,
-    ionic_constraints: IonicConstraints,
-    external_efield: ExternalEfield,
-    species_mass: SpeciesMass,
-    species_pot: SpeciesPot,
-    species_lcao_states: SpeciesLcaoStates,
-    hubbard_u: HubbardU,
+struct TypedCell {
+    /// Any of the `CASTEP` k-point forms, or none of them for the Gamma point only
+    kpoints_list: Option<KpointsList>,
+    kpoints_mp_grid: Option<KpointsMpGrid>,
+    kpoints_mp_spacing: Option<KpointsMpSpacing>,
+    kpoints_mp_offset: Option<KpointsMpOffset>,
+    species_mass: Option<SpeciesMass>,
+    species_pot: Option<SpeciesPot>,
+    species_lcao_states: Option<SpeciesLcaoStates>,
+    /// Written by `with_generated_hubbard_u` if the seed has none
+    hubbard_u: Option<HubbardU>,
     hubbard_alpha: Option<HubbardAlpha>,
-    quantization_axis: QuantizationAxis,
+}
+
+/// Reads a generated `HUBBARD_U` block
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct GeneratedHubbardU {
+    hubbard_u: HubbardU,
+}
+
+/// Blocks and keywords of `TypedCell`, which are written from it
+const TYPED_BLOCKS: [&str; 9] = [
+    "kpoints_list",
+    "kpoints_mp_grid",
+    "kpoints_mp_spacing",
+    "kpoints_mp_offset",
+    "species_mass",
+    "species_pot",
+    "species_lcao_states",
+    "hubbard_u",
+    "hubbard_alpha",
+];
+
+/// The singular `KPOINT_*` aliases of `CASTEP`
+const KPOINT_ALIASES: [(&str, &str); 4] = [
+    ("kpoint_list", "kpoints_list"),
+    ("kpoint_mp_grid", "kpoints_mp_grid"),
+    ("kpoint_mp_spacing", "kpoints_mp_spacing"),
+    ("kpoint_mp_offset", "kpoints_mp_offset"),
+];
+
+/// A `.cell` with the blocks we change typed, and all the others
+/// (`SYMMETRY_OPS`, `IONIC_CONSTRAINTS`, `SPECIES_Q`, ...) written back verbatim in their original order.
+/// The lattice and positions are also written back as given, in any of their forms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellFile {
+    typed: TypedCell,
+    geometry: Geometry,
+    keywords: KeywordFile,
+}
+
+#[derive(Debug)]
+pub struct CellFileParsingError(String);
+
+impl std::fmt::Display for CellFileParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid `.cell`: {}", self.0)
+    }
+}
+
+impl std::error::Error for CellFileParsingError {}
+
+impl FromStr for CellFile {
+    type Err = CellFileParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keywords = KeywordFile::parse(s);
+        KPOINT_ALIASES
+            .iter()
+            .for_each(|(alias, name)| keywords.rename(alias, name));
+        // Only the typed blocks go through `castep_cell_data`
+        let typed_content = keywords
+            .entries()
+            .iter()
+            .filter(|entry| {
+                entry
+                    .name()
+                    .is_some_and(|name| TYPED_BLOCKS.contains(&name))
+            })
+            .flat_map(Entry::lines)
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let typed = from_str::<TypedCell>(&typed_content)
+            .map_err(|e| CellFileParsingError(e.to_string()))?;
+        let geometry =
+            Geometry::from_keywords(&keywords).map_err(|e| CellFileParsingError(e.to_string()))?;
+        Ok(Self {
+            typed,
+            geometry,
+            keywords,
+        })
+    }
+}
+
+impl CellFile {
+    /// The `.cell` content, the typed blocks at their original place
+    pub fn to_cell_file(&self) -> String {
+        self.keywords
+            .merge(&self.typed.to_cell_file(), &TYPED_BLOCKS)
+    }
+
+    pub fn hubbard_u(&self) -> Option<&HubbardU> {
+        self.typed.hubbard_u.as_ref()
+    }
+
+    /// Write a `HUBBARD_U` for a seed without one: `u_value` on the d shell of the transition
+    /// metals and the f shell of the lanthanides and actinides in the positions, plus the p shell
+    /// of the `ligands`, e.g. O and N. A seed with `HUBBARD_U` is kept as it is.
+    pub fn with_generated_hubbard_u(
+        &self,
+        u_value: f64,
+        ligands: &[ElementSymbol],
+    ) -> Result<Self, CellFileParsingError> {
+        if self.typed.hubbard_u.is_some() {
+            return Ok(self.clone());
+        }
+        let lines = hubbard_u_lines(self.geometry.atoms(), u_value, ligands);
+        if lines.is_empty() {
+            return Err(CellFileParsingError(
+                "No `HUBBARD_U`, and no transition metal, lanthanide or actinide in the positions to write one for"
+                    .to_string(),
+            ));
+        }
+        let block = format!(
+            "%BLOCK HUBBARD_U\neV\n{}\n%ENDBLOCK HUBBARD_U\n",
+            lines.join("\n")
+        );
+        let generated = from_str::<GeneratedHubbardU>(&block)
+            .map_err(|e| CellFileParsingError(e.to_string()))?;
+        Ok(self.with_typed(TypedCell {
+            hubbard_u: Some(generated.hubbard_u),
+            ..self.typed.clone()
+        }))
+    }
+
+    pub fn hubbard_alpha(&self) -> Option<&HubbardAlpha> {
+        self.typed.hubbard_alpha.as_ref()
+    }
+
+    /// The LDA+U channels of `HUBBARD_U` as `(species, ion, orbital)`, in the order `CASTEP`
+    /// numbers them from 1: by species in the order they first appear in the positions,
+    /// then by ion, then by orbital.
+    pub fn hubbard_channels(&self) -> Vec<(String, u32, Orbital)> {
+        let Some(hubbard_u) = self.hubbard_u() else {
+            return Vec::new();
+        };
+        let atoms = self.geometry.atoms();
+        let mut species = Vec::<&str>::new();
+        atoms.iter().for_each(|atom| {
+            if !species.contains(&atom.species.as_str()) {
+                species.push(&atom.species)
+            }
+        });
+        species
+            .into_iter()
+            .flat_map(|label| {
+                let count = atoms.iter().filter(|atom| atom.species == label).count() as u32;
+                (1..=count).flat_map(move |ion| {
+                    let mut orbitals = hubbard_u
+                        .atom_u_values
+                        .iter()
+                        .filter(|line| {
+                            line.species.to_string().eq_ignore_ascii_case(label)
+                                && line.ion_number.is_none_or(|number| number == ion)
+                        })
+                        .flat_map(|line| line.orbitals.iter().map(Orbital::from))
+                        .collect::<Vec<Orbital>>();
+                    orbitals.sort();
+                    orbitals.dedup();
+                    orbitals
+                        .into_iter()
+                        .map(move |orbital| (label.to_string(), ion, orbital))
+                })
+            })
+            .collect()
+    }
+
+    /// Number of ions of `species` in the positions
+    fn ion_count(&self, species: &str) -> u32 {
+        self.geometry
+            .atoms()
+            .iter()
+            .filter(|atom| atom.species.eq_ignore_ascii_case(species))
+            .count() as u32
+    }
+
+    /// Number of the channel `selector` picks, `None` if it picks several channels or none
+    pub fn channel_of(&self, selector: &HubbardSelector) -> Option<u32> {
+        let mut picked = self.hubbard_channels().into_iter().enumerate().filter(
+            |(_, (species, ion, orbital))| {
+                selector.matches_atom(species, Some(*ion))
+                    && selector.matches_orbital(orbital.label())
+            },
+        );
+        match (picked.next(), picked.next()) {
+            (Some((index, _)), None) => Some(index as u32 + 1),
+            _ => None,
+        }
+    }
+
+    /// `(species, potential)` of each line of `SPECIES_POT`,
+    /// the potential being a file name or an on-the-fly string
+    pub fn species_potentials(&self) -> Vec<(String, String)> {
+        self.typed
+            .species_pot
+            .iter()
+            .flat_map(|species_pot| species_pot.entries.iter())
+            .map(|entry| (entry.species.to_string(), entry.filename.clone()))
+            .collect()
+    }
+
+    /// Species at the start of each line of a species block such as `SPECIES_MASS`,
+    /// `None` without the block
+    pub fn block_species(&self, name: &str) -> Option<Vec<String>> {
+        self.keywords.block(name).map(|lines| {
+            lines
+                .iter()
+                .filter_map(|line| {
+                    let mut words = line.split_whitespace();
+                    words.next().filter(|_| words.next().is_some())
+                })
+                .map(str::to_string)
+                .collect()
+        })
+    }
+
+    fn with_typed(&self, typed: TypedCell) -> Self {
+        Self {
+            typed,
+            geometry: self.geometry.clone(),
+            keywords: self.keywords.clone(),
+        }
+    }
+
+    /// Replace the potential of each species in `SPECIES_POT`
+    pub fn with_species_potentials(&self, potentials: &[(String, String)]) -> Self {
+        let entries = potentials
+            .iter()
+            .map(|(species, potential)| SpeciesPotEntry {
+                species: species
+                    .parse::<Species>()
+                    .expect("Species read from `SPECIES_POT` should parse again"),
+                filename: potential.clone(),
+            })
+            .collect();
+        self.with_typed(TypedCell {
+            species_pot: Some(SpeciesPot { entries }),
+            ..self.typed.clone()
+        })
+    }
+
+    /// Lattice and atoms, whichever blocks the seed uses
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    /// Rows of `LATTICE_CART`, converted from `LATTICE_ABC` if needed
+    pub fn lattice_vectors(&self) -> [[f64; 3]; 3] {
+        self.geometry.lattice_vectors()
+    }
+
+    /// Replace the k-points of the seed by the Monkhorst-Pack grid of `sampling`
+    pub fn with_kpoint_sampling(&self, sampling: &KpointSampling) -> Self {
+        self.with_typed(TypedCell {
+            kpoints_list: None,
+            kpoints_mp_grid: Some(KpointsMpGrid(sampling.mp_grid(&self.lattice_vectors()))),
+            kpoints_mp_spacing: None,
+            kpoints_mp_offset: None,
+            ..self.typed.clone()
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -49,100 +310,95 @@ impl HubbardUCell<Init> {
             stage: PhantomData,
         }
     }
-    pub fn cell_before(&self, u_value: f64, alpha_value: f64) -> HubbardUCell<BeforePerturb> {
+    /// Set U and alpha on the sites picked by `selector`, or on every Hubbard site without one.
+    /// Fails on a Hubbard block species that is not an element or a label of one.
+    pub fn cell_before(
+        &self,
+        u_value: f64,
+        alpha_value: f64,
+        selector: Option<&HubbardSelector>,
+    ) -> Result<HubbardUCell<BeforePerturb>, HubbardSpeciesError> {
         // Determine the u and alpha values based on job types
-        let mut new_cell = self.cell.clone();
+        let mut new_cell = self.cell.typed.clone();
         // Set hubbard u
-        // Currently, modify all presented u values to the provided value, regardless of orbitals
-        // Since `HUBBARD_U` block is guaranteed to exist in our provided `.cell` files,
-        new_cell
+        // Unselected sites keep their u values in the seed
+        // `HUBBARD_U` is written by `CellFile::with_generated_hubbard_u` for the seeds without one
+        let hubbard_u = new_cell
             .hubbard_u
-            .atom_u_values
-            .iter_mut()
-            .for_each(|atom_hubbard_u| {
-                atom_hubbard_u
-                    .orbitals
-                    .iter_mut()
-                    .for_each(|orbital| orbital.set_u_value(u_value));
-            });
+            .as_mut()
+            .expect("`HUBBARD_U` should have been generated for a seed without it");
+        // A species-wide line is split per ion, for `selector` to pick one ion of it
+        let ion_count = |species: &str| self.cell.ion_count(species);
+        hubbard_u.atom_u_values =
+            split_species_lines(&hubbard_u.atom_u_values, selector, ion_count);
+        let mut u_block = HubbardBlock::try_from(&*hubbard_u)?;
+        u_block.set_selected(u_value, selector);
+        hubbard_u.atom_u_values = u_block.atom_u_values();
+        let hubbard_u = hubbard_u.clone();
         // Set hubbard alpha
-        // Currently, modify all alpha value
         // Since `HUBBARD_ALPHA` block is not generated by default,
         match new_cell.hubbard_alpha.as_mut() {
             // Modify the mut
             Some(hub_alpha) => {
-                *hub_alpha = update_hubbard_alpha_values(hub_alpha, alpha_value);
+                hub_alpha.atom_u_values =
+                    split_species_lines(&hub_alpha.atom_u_values, selector, ion_count);
+                *hub_alpha = update_hubbard_alpha_values(hub_alpha, alpha_value, selector)?;
             }
             None => {
                 // copy settings from `HubbardU`
                 // to inherit the specified orbitals and species
-                let HubbardU {
-                    unit,
-                    atom_u_values,
-                } = new_cell.hubbard_u.clone();
-                let atom_u_values = atom_u_values
-                    .into_iter()
-                    .map(|mut atom| {
-                        atom.orbitals
-                            .iter_mut()
-                            .for_each(|orbital| orbital.set_u_value(alpha_value));
-                        atom
-                    })
-                    .collect::<Vec<AtomHubbardU>>();
+                let mut alpha_block = HubbardBlock::try_from(&hubbard_u)?;
+                // Unselected sites are not perturbed
+                if selector.is_some() {
+                    alpha_block.set_selected(0.0, None);
+                }
+                alpha_block.set_selected(alpha_value, selector);
                 new_cell.hubbard_alpha = Some(HubbardAlpha {
-                    unit,
-                    atom_u_values,
+                    unit: hubbard_u.unit,
+                    atom_u_values: alpha_block.atom_u_values(),
                 })
             }
         };
-        HubbardUCell {
-            cell: new_cell,
+        Ok(HubbardUCell {
+            cell: self.cell.with_typed(new_cell),
             stage: PhantomData,
-        }
+        })
     }
 }
 
-/// Update hubbard alpha value
-fn update_hubbard_alpha_values(alpha: &HubbardAlpha, new_alpha_value: f64) -> HubbardAlpha {
-    let atom_u_values = alpha
-        .atom_u_values
-        .iter()
-        .map(|atom_hubbard_u| {
-            let orbitals = atom_hubbard_u
-                .orbitals
-                .iter()
-                .map(|orbital| {
-                    let mut new_orbital = orbital.clone();
-                    new_orbital.set_u_value(new_alpha_value);
-                    new_orbital
-                })
-                .collect();
-            AtomHubbardU {
-                species: atom_hubbard_u.species.clone(),
-                orbitals,
-                ion_number: atom_hubbard_u.ion_number,
-            }
-        })
-        .collect();
-    HubbardAlpha {
+/// Update hubbard alpha value of the selected sites
+fn update_hubbard_alpha_values(
+    alpha: &HubbardAlpha,
+    new_alpha_value: f64,
+    selector: Option<&HubbardSelector>,
+) -> Result<HubbardAlpha, HubbardSpeciesError> {
+    let mut block = HubbardBlock::try_from(alpha)?;
+    block.set_selected(new_alpha_value, selector);
+    Ok(HubbardAlpha {
         unit: alpha.unit,
-        atom_u_values,
-    }
+        atom_u_values: block.atom_u_values(),
+    })
 }
 
 impl HubbardUCell<BeforePerturb> {
-    pub fn update_alpha(&self, new_alpha_value: f64) -> HubbardUCell<Perturbed> {
-        HubbardUCell {
-            cell: CellFile {
-                hubbard_alpha: self
-                    .cell
-                    .hubbard_alpha
-                    .as_ref()
-                    .map(|hub_alpha| update_hubbard_alpha_values(hub_alpha, new_alpha_value)),
-                ..self.cell.clone()
-            },
+    pub fn update_alpha(
+        &self,
+        new_alpha_value: f64,
+        selector: Option<&HubbardSelector>,
+    ) -> Result<HubbardUCell<Perturbed>, HubbardSpeciesError> {
+        let typed = &self.cell.typed;
+        let hubbard_alpha = typed
+            .hubbard_alpha
+            .as_ref()
+            .map(|hub_alpha| update_hubbard_alpha_values(hub_alpha, new_alpha_value, selector))
+            .transpose()?;
+        Ok(HubbardUCell {
+            cell: self.cell.with_typed(TypedCell {
+                hubbard_alpha,
+                ..typed.clone()
+            }),
             stage: PhantomData,
-        }
+        })
     }
 }
 
@@ -150,34 +406,338 @@ impl HubbardUCell<BeforePerturb> {
 mod test {
     use std::{fs::read_to_string, path::Path};
 
-    use castep_cell_data::from_str;
+    use castep_cell_data::cell::bz_sampling_kpoints::KpointsMpGrid;
+    use castep_periodic_table::element::ElementSymbol;
 
-    use crate::seed_settings::{cell_setup::HubbardUCell, CellFile};
+    use crate::seed_settings::{
+        cell_setup::HubbardUCell, hubbard::Orbital, CellFile, HubbardBlock, HubbardSelector,
+    };
 
     #[test]
     fn hubbard_init() {
-        let cwd = env!("CARGO_MANIFEST_DIR");
-        let cell_path = Path::new(cwd)
+        let alpha_increment = 0.05;
+        let hubbard_u_before = HubbardUCell::from_cell_file(test_cell())
+            .cell_before(1e-7, 5.0, None)
+            .unwrap();
+        (1..5).for_each(|perturb_step| {
+            let perturbed_cell = hubbard_u_before
+                .update_alpha(perturb_step as f64 * alpha_increment, None)
+                .unwrap();
+            dbg!(perturbed_cell.cell.typed.hubbard_u);
+            dbg!(perturbed_cell.cell.typed.hubbard_alpha);
+        });
+    }
+
+    #[test]
+    fn selected_sites_only() {
+        let cell = test_cell();
+        let init = HubbardUCell::from_cell_file(cell.clone());
+        let absent: HubbardSelector = "Ni".parse().unwrap();
+        let untouched = init.cell_before(2.0, 1e-8, Some(&absent)).unwrap();
+        assert_eq!(untouched.cell.typed.hubbard_u, cell.typed.hubbard_u);
+        let fe_d: HubbardSelector = "Fe:1:d".parse().unwrap();
+        let before = init.cell_before(2.0, 1e-8, Some(&fe_d)).unwrap();
+        assert_ne!(before.cell.typed.hubbard_u, cell.typed.hubbard_u);
+        assert_eq!(
+            before.cell.typed.hubbard_u,
+            init.cell_before(2.0, 1e-8, None)
+                .unwrap()
+                .cell
+                .typed
+                .hubbard_u
+        );
+        let perturbed = before.update_alpha(0.05, Some(&absent)).unwrap();
+        assert_eq!(
+            perturbed.cell.typed.hubbard_alpha,
+            before.cell.typed.hubbard_alpha
+        );
+    }
+
+    #[test]
+    fn rewrite_species_pot() {
+        let cell = test_cell();
+        let potentials = cell.species_potentials();
+        assert_eq!(
+            potentials,
+            vec![
+                ("C".to_string(), "C_00PBE.usp".to_string()),
+                ("Fe".to_string(), "Fe_00PBE.uspcc".to_string())
+            ]
+        );
+        let otfg = potentials
+            .iter()
+            .map(|(species, _)| (species.clone(), "C19".to_string()))
+            .collect::<Vec<(String, String)>>();
+        let written = cell.with_species_potentials(&otfg).to_cell_file();
+        assert!(written.contains("C19"));
+        assert!(!written.contains("Fe_00PBE.uspcc"));
+    }
+
+    #[test]
+    fn kpoint_forms() {
+        let content = test_seed();
+        let cell = content.parse::<CellFile>().unwrap();
+        assert!(cell.typed.kpoints_list.is_some());
+        let gridded = cell.with_kpoint_sampling(&"2x2x1".parse().unwrap());
+        assert!(gridded.typed.kpoints_list.is_none());
+        assert_eq!(
+            gridded.typed.kpoints_mp_grid,
+            Some(KpointsMpGrid([2, 2, 1]))
+        );
+        let written = gridded.to_cell_file();
+        assert!(written.contains("KPOINTS_MP_GRID"));
+        assert!(!written.contains("KPOINTS_LIST"));
+        // Seeds with a grid, a spacing or no k-points at all (Gamma only)
+        let without_list = strip_block(&content, "KPOINTS_LIST");
+        for kpoints in [
+            "KPOINTS_MP_GRID : 4 4 1\n",
+            "KPOINT_MP_SPACING : 0.05\n",
+            "",
+        ] {
+            let seed = format!("{without_list}\n{kpoints}");
+            let cell = seed.parse::<CellFile>().unwrap();
+            assert!(cell.typed.kpoints_list.is_none());
+            assert_eq!(
+                cell.typed.kpoints_mp_grid.is_some(),
+                kpoints.contains("GRID"),
+                "{kpoints}"
+            );
+            assert_eq!(
+                cell.typed.kpoints_mp_spacing.is_some(),
+                kpoints.contains("SPACING")
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_blocks_round_trip() {
+        let cell = test_cell();
+        let perturbed = HubbardUCell::from_cell_file(cell)
+            .cell_before(1e-8, 1e-8, None)
+            .unwrap()
+            .update_alpha(0.05, None)
+            .unwrap();
+        let written = perturbed.cell.to_cell_file();
+        let blocks = written
+            .lines()
+            .filter_map(|line| line.strip_prefix("%BLOCK "))
+            .map(str::trim)
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            blocks[..4],
+            [
+                "LATTICE_CART",
+                "POSITIONS_FRAC",
+                "KPOINTS_LIST",
+                "CELL_CONSTRAINTS"
+            ]
+        );
+        assert_eq!(blocks.last(), Some(&"HUBBARD_ALPHA"));
+        assert!(written.contains("FIX_COM : false"));
+        assert!(written.contains("QUANTIZATION_AXIS :    0.0000    0.0000    1.0000"));
+        // A hand-written seed without the Materials Studio blocks
+        let seed = "%BLOCK LATTICE_CART\n5 0 0\n0 5 0\n0 0 5\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nNi 0 0 0\nO 0.5 0.5 0.5\n%ENDBLOCK POSITIONS_FRAC\n\
+            %BLOCK SYMMETRY_OPS\n1 0 0\n0 1 0\n0 0 1\n0 0 0\n%ENDBLOCK SYMMETRY_OPS\n\
+            %BLOCK HUBBARD_U\neV\nNi 1 d: 6.0\n%ENDBLOCK HUBBARD_U\n\
+            SPECIES_Q : O 1.0\nKPOINT_MP_GRID : 4 4 4\n";
+        let cell = seed.parse::<CellFile>().unwrap();
+        assert!(cell.species_potentials().is_empty());
+        assert_eq!(cell.typed.kpoints_mp_grid, Some(KpointsMpGrid([4, 4, 4])));
+        let written = HubbardUCell::from_cell_file(cell)
+            .cell_before(1e-8, 1e-8, None)
+            .unwrap()
+            .cell
+            .to_cell_file();
+        let symmetry = written.find("%BLOCK SYMMETRY_OPS").unwrap();
+        assert!(written.find("%BLOCK POSITIONS_FRAC").unwrap() < symmetry);
+        assert!(symmetry < written.find("%BLOCK HUBBARD_U").unwrap());
+        assert!(written.contains("1 0 0\n0 1 0\n0 0 1\n0 0 0\n"));
+        assert!(written.contains("SPECIES_Q : O 1.0\n"));
+        assert!(!written.contains("KPOINT_MP_GRID"));
+    }
+
+    #[test]
+    fn abc_and_abs_seed() {
+        let seed = "%BLOCK LATTICE_ABC\n5 5 20\n90 90 90\n%ENDBLOCK LATTICE_ABC\n\
+            %BLOCK POSITIONS_ABS\nNi 0 0 0\nO 2.5 2.5 10\n%ENDBLOCK POSITIONS_ABS\n\
+            %BLOCK HUBBARD_U\neV\nNi 1 d: 6.0\n%ENDBLOCK HUBBARD_U\n";
+        let cell = seed.parse::<CellFile>().unwrap();
+        let frac = cell.geometry().atoms()[1].frac;
+        assert!(frac.iter().all(|x| (x - 0.5).abs() < 1e-10), "{frac:?}");
+        let gridded = cell.with_kpoint_sampling(&"0.05".parse().unwrap());
+        assert_eq!(
+            gridded.typed.kpoints_mp_grid,
+            Some(KpointsMpGrid([4, 4, 1]))
+        );
+        let written = HubbardUCell::from_cell_file(gridded)
+            .cell_before(1e-8, 1e-8, None)
+            .unwrap()
+            .cell
+            .to_cell_file();
+        assert!(written.starts_with("%BLOCK LATTICE_ABC\n5 5 20\n90 90 90\n"));
+        assert!(written.contains("%BLOCK POSITIONS_ABS\nNi 0 0 0\nO 2.5 2.5 10\n"));
+        assert!(!written.contains("LATTICE_CART"));
+        assert!(!written.contains("POSITIONS_FRAC"));
+    }
+
+    #[test]
+    fn channels_of_sites() {
+        let seed = "%BLOCK LATTICE_CART\n4.17 0 0\n0 4.17 0\n0 0 8.34\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nNi 0 0 0\nO 0.5 0.5 0.25\nNi 0.5 0.5 0.5\nO 0 0 0.75\n\
+            %ENDBLOCK POSITIONS_FRAC\n\
+            %BLOCK HUBBARD_U\neV\nO 2 p: 3.0\nNi d: 6.0\n%ENDBLOCK HUBBARD_U\n";
+        let cell = seed.parse::<CellFile>().unwrap();
+        assert_eq!(
+            cell.hubbard_channels(),
+            [
+                ("Ni".to_string(), 1, Orbital::D),
+                ("Ni".to_string(), 2, Orbital::D),
+                ("O".to_string(), 2, Orbital::P)
+            ]
+        );
+        let channel = |site: &str| cell.channel_of(&site.parse().unwrap());
+        assert_eq!(channel("Ni:2"), Some(2));
+        assert_eq!(channel("O:2:p"), Some(3));
+        assert_eq!(channel("O"), Some(3));
+        assert_eq!(channel("Ni"), None);
+        assert_eq!(channel("O:1"), None);
+    }
+
+    #[test]
+    fn species_wide_line_per_ion() {
+        let seed = "%BLOCK LATTICE_CART\n4.17 0 0\n0 4.17 0\n0 0 8.34\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nNi 0 0 0\nO 0.5 0.5 0.25\nNi 0.5 0.5 0.5\nO 0 0 0.75\n\
+            %ENDBLOCK POSITIONS_FRAC\n\
+            %BLOCK HUBBARD_U\neV\nNi d: 6.0\nO 2 p: 3.0\n%ENDBLOCK HUBBARD_U\n";
+        let cell = seed.parse::<CellFile>().unwrap();
+        let ni_2: HubbardSelector = "Ni:2".parse().unwrap();
+        let values = |block: HubbardBlock| {
+            block
+                .settings()
+                .iter()
+                .map(|item| (item.element(), item.atom_id(), item.hub_value()))
+                .collect::<Vec<_>>()
+        };
+        let before = HubbardUCell::from_cell_file(cell)
+            .cell_before(2.0, 1e-8, Some(&ni_2))
+            .unwrap();
+        assert_eq!(
+            values(HubbardBlock::try_from(before.cell.hubbard_u().unwrap()).unwrap()),
+            [
+                (ElementSymbol::Ni, Some(1), 6.0),
+                (ElementSymbol::Ni, Some(2), 2.0),
+                (ElementSymbol::O, Some(2), 3.0)
+            ]
+        );
+        let perturbed = before.update_alpha(0.05, Some(&ni_2)).unwrap();
+        assert_eq!(
+            values(HubbardBlock::try_from(perturbed.cell.hubbard_alpha().unwrap()).unwrap()),
+            [
+                (ElementSymbol::Ni, Some(1), 0.0),
+                (ElementSymbol::Ni, Some(2), 0.05),
+                (ElementSymbol::O, Some(2), 0.0)
+            ]
+        );
+        assert_eq!(perturbed.cell.channel_of(&ni_2), Some(2));
+        // Without an ion number the line is left as it is
+        let cell = seed.parse::<CellFile>().unwrap();
+        let before = HubbardUCell::from_cell_file(cell)
+            .cell_before(2.0, 1e-8, Some(&"Ni".parse().unwrap()))
+            .unwrap();
+        assert_eq!(before.cell.hubbard_u().unwrap().atom_u_values.len(), 2);
+    }
+
+    #[test]
+    fn generate_missing_hubbard_u() {
+        let seed = "%BLOCK LATTICE_CART\n4.17 0 0\n0 4.17 0\n0 0 4.17\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nNi 0 0 0\nO 0.5 0.5 0.5\n%ENDBLOCK POSITIONS_FRAC\n";
+        let cell = seed.parse::<CellFile>().unwrap();
+        assert!(cell.hubbard_u().is_none());
+        let generated = cell
+            .with_generated_hubbard_u(1e-8, &[ElementSymbol::O])
+            .unwrap();
+        let block = HubbardBlock::try_from(generated.hubbard_u().unwrap()).unwrap();
+        let sites = block
+            .settings()
+            .iter()
+            .map(|item| (item.element(), item.atom_id(), item.orbital()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sites,
+            [
+                (ElementSymbol::Ni, Some(1), Orbital::D),
+                (ElementSymbol::O, Some(1), Orbital::P)
+            ]
+        );
+        let before = HubbardUCell::from_cell_file(generated)
+            .cell_before(2.0, 1e-8, None)
+            .unwrap();
+        assert!(before.cell.to_cell_file().contains("%BLOCK HUBBARD_U"));
+        // A seed with `HUBBARD_U` is not touched, one without metal fails
+        let cell = test_cell();
+        assert_eq!(cell.with_generated_hubbard_u(1e-8, &[]).unwrap(), cell);
+        let oxygen = seed.replace("Ni 0 0 0\n", "").parse::<CellFile>().unwrap();
+        assert!(oxygen.with_generated_hubbard_u(1e-8, &[]).is_err());
+    }
+
+    #[test]
+    fn generate_for_labelled_species() {
+        let seed = "%BLOCK LATTICE_CART\n2.87 0 0\n0 2.87 0\n0 0 2.87\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nFe1 0 0 0\nFe2 0.5 0.5 0.5\nFe1 0.5 0 0\n%ENDBLOCK POSITIONS_FRAC\n";
+        let generated = seed
+            .parse::<CellFile>()
+            .unwrap()
+            .with_generated_hubbard_u(1e-8, &[])
+            .unwrap();
+        let block = HubbardBlock::try_from(generated.hubbard_u().unwrap()).unwrap();
+        let sites = block
+            .settings()
+            .iter()
+            .map(|item| (item.species(), item.element(), item.atom_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sites,
+            [
+                ("Fe1", ElementSymbol::Fe, Some(1)),
+                ("Fe2", ElementSymbol::Fe, Some(1)),
+                ("Fe1", ElementSymbol::Fe, Some(2))
+            ]
+        );
+        let site = "Fe1:2:d".parse::<HubbardSelector>().unwrap();
+        assert_eq!(generated.channel_of(&site), Some(2));
+    }
+
+    /// Content of `sh/test/GDY_111_Fe_U.cell`
+    fn test_seed() -> String {
+        let cell_path = Path::new(env!("CARGO_MANIFEST_DIR"))
             .parent()
             .unwrap()
             .join("sh/test/GDY_111_Fe_U.cell");
-        let alpha_increment = 0.05;
-        read_to_string(cell_path)
-            .as_ref()
-            .map(|input| {
-                from_str::<CellFile>(input)
-                    .map(HubbardUCell::from_cell_file)
-                    .map(|hub_u_cell| hub_u_cell.cell_before(1e-7, 5.0))
-                    .map(|hubbard_u_before| {
-                        (1..5).for_each(|perturb_step| {
-                            let perturbed_cell = hubbard_u_before
-                                .update_alpha(perturb_step as f64 * alpha_increment);
-                            dbg!(perturbed_cell.cell.hubbard_u);
-                            dbg!(perturbed_cell.cell.hubbard_alpha);
-                        });
-                    })
-                    .unwrap()
+        read_to_string(cell_path).unwrap()
+    }
+
+    fn test_cell() -> CellFile {
+        test_seed().parse::<CellFile>().unwrap()
+    }
+
+    /// Remove `%BLOCK name` ... `%ENDBLOCK name` from a `.cell`
+    fn strip_block(content: &str, name: &str) -> String {
+        let mut inside = false;
+        content
+            .lines()
+            .filter(|line| {
+                let upper = line.trim().to_uppercase();
+                if upper.starts_with("%BLOCK") && upper.ends_with(name) {
+                    inside = true;
+                }
+                let keep = !inside;
+                if upper.starts_with("%ENDBLOCK") && upper.ends_with(name) {
+                    inside = false;
+                }
+                keep
             })
-            .unwrap();
+            .collect::<Vec<&str>>()
+            .join("\n")
     }
 }
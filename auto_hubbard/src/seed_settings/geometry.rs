@@ -0,0 +1,357 @@
+use std::fmt::Display;
+
+use super::keyword_file::KeywordFile;
+
+/// Å per bohr
+const BOHR: f64 = 0.529_177_210_903;
+
+/// How the seed writes the lattice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatticeForm {
+    /// `LATTICE_CART`: the three vectors
+    Cart,
+    /// `LATTICE_ABC`: lengths and angles
+    Abc,
+}
+
+/// How the seed writes the atoms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionsForm {
+    /// `POSITIONS_FRAC`: fractions of the lattice vectors
+    Frac,
+    /// `POSITIONS_ABS`: cartesian coordinates
+    Abs,
+}
+
+/// The element of a species label, e.g.: `Fe` of `Fe1`, `Fe:up` or `FE1`.
+/// `CASTEP` reads the labels regardless of case, so the letters before any digit or `:`
+/// are the symbol, written back with a capital first letter.
+pub fn element_of(species: &str) -> String {
+    species
+        .chars()
+        .take_while(char::is_ascii_alphabetic)
+        .enumerate()
+        .map(|(i, c)| match i {
+            0 => c.to_ascii_uppercase(),
+            _ => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// An atom of `POSITIONS_FRAC` or `POSITIONS_ABS`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atom {
+    /// As written, e.g.: `Fe`, `Fe1` or `Fe:up`
+    pub species: String,
+    pub frac: [f64; 3],
+}
+
+/// The lattice and atoms of a `.cell` in any of the `LATTICE_CART`/`LATTICE_ABC` and
+/// `POSITIONS_FRAC`/`POSITIONS_ABS` combinations, held as vectors in Å and fractional coordinates.
+/// The blocks themselves are written back as the user wrote them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    /// Rows are the lattice vectors, in Å
+    lattice: [[f64; 3]; 3],
+    atoms: Vec<Atom>,
+    lattice_form: LatticeForm,
+    positions_form: PositionsForm,
+}
+
+#[derive(Debug)]
+pub struct GeometryError(String);
+
+impl Display for GeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+pub(crate) fn cross(u: &[f64; 3], v: &[f64; 3]) -> [f64; 3] {
+    [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ]
+}
+
+pub(crate) fn dot(u: &[f64; 3], v: &[f64; 3]) -> f64 {
+    u.iter().zip(v.iter()).map(|(x, y)| x * y).sum()
+}
+
+pub(crate) fn norm(u: &[f64; 3]) -> f64 {
+    dot(u, u).sqrt()
+}
+
+/// Lattice vectors from lengths and angles (degrees), `a` along x and `b` in the xy plane
+pub fn cart_from_abc(lengths: [f64; 3], angles: [f64; 3]) -> [[f64; 3]; 3] {
+    let [a, b, c] = lengths;
+    let [alpha, beta, gamma] = angles.map(f64::to_radians);
+    let cx = c * beta.cos();
+    let cy = c * (alpha.cos() - beta.cos() * gamma.cos()) / gamma.sin();
+    let cz = (c * c - cx * cx - cy * cy).max(0.0).sqrt();
+    [
+        [a, 0.0, 0.0],
+        [b * gamma.cos(), b * gamma.sin(), 0.0],
+        [cx, cy, cz],
+    ]
+}
+
+/// Lengths and angles (degrees) of the lattice vectors
+pub fn abc_from_cart(lattice: &[[f64; 3]; 3]) -> ([f64; 3], [f64; 3]) {
+    let [a, b, c] = lattice;
+    let angle = |u: &[f64; 3], v: &[f64; 3]| (dot(u, v) / (norm(u) * norm(v))).acos().to_degrees();
+    (
+        [norm(a), norm(b), norm(c)],
+        [angle(b, c), angle(a, c), angle(a, b)],
+    )
+}
+
+/// Fractional coordinates of the cartesian `position` in `lattice`
+pub fn frac_from_abs(lattice: &[[f64; 3]; 3], position: &[f64; 3]) -> [f64; 3] {
+    let [a, b, c] = lattice;
+    let volume = dot(a, &cross(b, c));
+    [cross(b, c), cross(c, a), cross(a, b)].map(|reciprocal| dot(position, &reciprocal) / volume)
+}
+
+/// Cartesian coordinates of the fractional `frac` in `lattice`
+pub fn abs_from_frac(lattice: &[[f64; 3]; 3], frac: &[f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| (0..3).map(|row| frac[row] * lattice[row][i]).sum())
+}
+
+/// Å per unit, for the length units of `CASTEP`
+fn length_unit(unit: &str) -> Option<f64> {
+    match unit.to_lowercase().as_str() {
+        "ang" => Some(1.0),
+        "bohr" | "a0" => Some(BOHR),
+        "nm" => Some(10.0),
+        "cm" => Some(1e8),
+        "m" => Some(1e10),
+        _ => None,
+    }
+}
+
+/// The block lines after the optional unit line, and the unit in Å
+fn unit_and_rows<'a>(
+    name: &str,
+    lines: &[&'a str],
+) -> Result<(f64, Vec<Vec<&'a str>>), GeometryError> {
+    let rows = lines
+        .iter()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .collect::<Vec<Vec<&str>>>();
+    match rows.first().map(Vec::as_slice) {
+        Some([unit]) if unit.parse::<f64>().is_err() => length_unit(unit)
+            .map(|scale| (scale, rows[1..].to_vec()))
+            .ok_or_else(|| GeometryError(format!("Unknown length unit `{unit}` in {name}"))),
+        _ => Ok((1.0, rows)),
+    }
+}
+
+fn parse_triple(name: &str, words: &[&str]) -> Result<[f64; 3], GeometryError> {
+    let values = words
+        .iter()
+        .take(3)
+        .map(|word| word.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .ok()
+        .filter(|values| values.len() == 3)
+        .ok_or_else(|| GeometryError(format!("Invalid line `{}` in {name}", words.join(" "))))?;
+    Ok([values[0], values[1], values[2]])
+}
+
+/// The one block present among `names`
+fn one_of<'a>(
+    file: &'a KeywordFile,
+    names: [&'static str; 2],
+) -> Result<(usize, &'static str, Vec<&'a str>), GeometryError> {
+    let found = names
+        .iter()
+        .enumerate()
+        .filter_map(|(i, name)| file.block(name).map(|lines| (i, *name, lines)))
+        .collect::<Vec<_>>();
+    match found.len() {
+        1 => Ok(found.into_iter().next().expect("Checked length")),
+        0 => Err(GeometryError(format!(
+            "Missing {}",
+            names.map(str::to_uppercase).join(" or ")
+        ))),
+        _ => Err(GeometryError(format!(
+            "Both {} are given",
+            names.map(str::to_uppercase).join(" and ")
+        ))),
+    }
+}
+
+impl Geometry {
+    /// Read the lattice and positions blocks of a `.cell`
+    pub fn from_keywords(file: &KeywordFile) -> Result<Self, GeometryError> {
+        let (lattice_index, name, lines) = one_of(file, ["lattice_cart", "lattice_abc"])?;
+        let name = name.to_uppercase();
+        let (scale, rows) = unit_and_rows(&name, &lines)?;
+        if rows.len() != 3 - lattice_index {
+            return Err(GeometryError(format!(
+                "{name} needs {} lines of numbers",
+                3 - lattice_index
+            )));
+        }
+        let rows = rows
+            .iter()
+            .map(|row| parse_triple(&name, row))
+            .collect::<Result<Vec<[f64; 3]>, GeometryError>>()?;
+        let (lattice, lattice_form) = match lattice_index {
+            0 => (
+                [rows[0], rows[1], rows[2]].map(|row| row.map(|x| x * scale)),
+                LatticeForm::Cart,
+            ),
+            _ => (
+                cart_from_abc(rows[0].map(|x| x * scale), rows[1]),
+                LatticeForm::Abc,
+            ),
+        };
+        let (positions_index, name, lines) = one_of(file, ["positions_frac", "positions_abs"])?;
+        let name = name.to_uppercase();
+        let (scale, rows) = unit_and_rows(&name, &lines)?;
+        let positions_form = match positions_index {
+            0 => PositionsForm::Frac,
+            _ => PositionsForm::Abs,
+        };
+        let atoms = rows
+            .iter()
+            .map(|row| {
+                let (species, coordinates) = row
+                    .split_first()
+                    .ok_or_else(|| GeometryError(format!("Empty line in {name}")))?;
+                let coordinates = parse_triple(&name, coordinates)?;
+                let frac = match positions_form {
+                    PositionsForm::Frac => coordinates,
+                    PositionsForm::Abs => frac_from_abs(&lattice, &coordinates.map(|x| x * scale)),
+                };
+                Ok(Atom {
+                    species: species.to_string(),
+                    frac,
+                })
+            })
+            .collect::<Result<Vec<Atom>, GeometryError>>()?;
+        Ok(Self {
+            lattice,
+            atoms,
+            lattice_form,
+            positions_form,
+        })
+    }
+
+    /// Rows of `LATTICE_CART`, in Å
+    pub fn lattice_vectors(&self) -> [[f64; 3]; 3] {
+        self.lattice
+    }
+
+    /// Lengths (Å) and angles (degrees) of `LATTICE_ABC`
+    pub fn lattice_abc(&self) -> ([f64; 3], [f64; 3]) {
+        abc_from_cart(&self.lattice)
+    }
+
+    pub fn atoms(&self) -> &[Atom] {
+        &self.atoms
+    }
+
+    /// Cartesian coordinates of the atoms, in Å
+    pub fn positions_abs(&self) -> Vec<[f64; 3]> {
+        self.atoms
+            .iter()
+            .map(|atom| abs_from_frac(&self.lattice, &atom.frac))
+            .collect()
+    }
+
+    /// The blocks used by the seed, kept for the generated jobs
+    pub fn forms(&self) -> (LatticeForm, PositionsForm) {
+        (self.lattice_form, self.positions_form)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::seed_settings::keyword_file::KeywordFile;
+
+    use super::{abc_from_cart, cart_from_abc, element_of, Geometry, LatticeForm, PositionsForm};
+
+    fn assert_close(left: &[f64], right: &[f64]) {
+        assert!(
+            left.iter().zip(right).all(|(x, y)| (x - y).abs() < 1e-8),
+            "{left:?} != {right:?}"
+        );
+    }
+
+    #[test]
+    fn lattice_conversion() {
+        let cubic = cart_from_abc([5.0, 5.0, 5.0], [90.0, 90.0, 90.0]);
+        assert_close(
+            &cubic.concat(),
+            &[5.0, 0.0, 0.0, 0.0, 5.0, 0.0, 0.0, 0.0, 5.0],
+        );
+        let hexagonal = cart_from_abc([2.46, 2.46, 10.0], [90.0, 90.0, 120.0]);
+        assert_close(&hexagonal[1], &[-1.23, 2.46 * 3f64.sqrt() / 2.0, 0.0]);
+        let triclinic = cart_from_abc([4.0, 5.0, 6.0], [80.0, 95.0, 105.0]);
+        let (lengths, angles) = abc_from_cart(&triclinic);
+        assert_close(&lengths, &[4.0, 5.0, 6.0]);
+        assert_close(&angles, &[80.0, 95.0, 105.0]);
+    }
+
+    #[test]
+    fn element_of_labels() {
+        [
+            ("Fe", "Fe"),
+            ("Fe1", "Fe"),
+            ("Fe:up", "Fe"),
+            ("FE1", "Fe"),
+            ("NI", "Ni"),
+            ("o", "O"),
+            ("Xq", "Xq"),
+        ]
+        .iter()
+        .for_each(|(species, element)| assert_eq!(element_of(species), *element));
+    }
+
+    #[test]
+    fn abc_and_abs_seed() {
+        let abs = KeywordFile::parse(
+            "%BLOCK LATTICE_ABC\nbohr\n4 4 8\n90 90 120\n%ENDBLOCK LATTICE_ABC\n\
+            %BLOCK POSITIONS_ABS\nang\nNi 0 0 0\nO 1.0 0.5 2.0 SPIN=0\n%ENDBLOCK POSITIONS_ABS\n",
+        );
+        let geometry = Geometry::from_keywords(&abs).unwrap();
+        assert_eq!(geometry.forms(), (LatticeForm::Abc, PositionsForm::Abs));
+        let (lengths, _) = geometry.lattice_abc();
+        assert_close(
+            &lengths,
+            &[4.0 * super::BOHR, 4.0 * super::BOHR, 8.0 * super::BOHR],
+        );
+        assert_eq!(geometry.atoms()[1].species, "O");
+        assert_close(&geometry.positions_abs()[1], &[1.0, 0.5, 2.0]);
+        // The same structure written with `LATTICE_CART` and `POSITIONS_FRAC`
+        let [a, b, c] = geometry.lattice_vectors().map(|row| {
+            row.iter()
+                .map(|x| format!("{x:.12}"))
+                .collect::<Vec<String>>()
+                .join(" ")
+        });
+        let frac = geometry.atoms()[1].frac;
+        let cart = KeywordFile::parse(&format!(
+            "%BLOCK LATTICE_CART\n{a}\n{b}\n{c}\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nNi 0 0 0\nO {} {} {}\n%ENDBLOCK POSITIONS_FRAC\n",
+            frac[0], frac[1], frac[2]
+        ));
+        let geometry = Geometry::from_keywords(&cart).unwrap();
+        assert_eq!(geometry.forms(), (LatticeForm::Cart, PositionsForm::Frac));
+        assert_close(&geometry.positions_abs()[1], &[1.0, 0.5, 2.0]);
+        let both = KeywordFile::parse(
+            "%BLOCK LATTICE_ABC\n4 4 8\n90 90 90\n%ENDBLOCK LATTICE_ABC\n\
+            %BLOCK LATTICE_CART\n4 0 0\n0 4 0\n0 0 8\n%ENDBLOCK LATTICE_CART\n",
+        );
+        assert!(Geometry::from_keywords(&both).is_err());
+        let missing =
+            KeywordFile::parse("%BLOCK LATTICE_ABC\n4 4 8\n90 90 90\n%ENDBLOCK LATTICE_ABC\n");
+        assert!(Geometry::from_keywords(&missing).is_err());
+    }
+}
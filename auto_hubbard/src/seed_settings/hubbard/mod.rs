@@ -1,10 +1,13 @@
 use std::{fmt::Display, str::FromStr};
 
+use castep_cell_data::cell::species::{AtomHubbardU, HubbardAlpha, HubbardU, OrbitalU};
 use castep_periodic_table::element::ElementSymbol;
-use derive_builder::Builder;
 
-use castep_cell_parser::{BlockBuilder, BlockIO, CELLParser, CellParseError, Rule};
-use pest::Parser;
+use super::{element_of, HubbardSelector};
+
+mod shells;
+
+pub use shells::hubbard_u_lines;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Orbital {
@@ -14,18 +17,39 @@ pub enum Orbital {
     F,
 }
 
+impl Orbital {
+    /// `s`, `p`, `d` or `f`
+    pub fn label(&self) -> char {
+        match self {
+            Orbital::S => 's',
+            Orbital::P => 'p',
+            Orbital::D => 'd',
+            Orbital::F => 'f',
+        }
+    }
+}
+
 impl Display for Orbital {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Orbital::S => "s",
-                Orbital::P => "p",
-                Orbital::D => "d",
-                Orbital::F => "f",
-            }
-        )
+        write!(f, "{}", self.label())
+    }
+}
+
+impl From<&OrbitalU> for Orbital {
+    fn from(orbital: &OrbitalU) -> Self {
+        match orbital {
+            OrbitalU::S(_) => Orbital::S,
+            OrbitalU::P(_) => Orbital::P,
+            OrbitalU::D(_) => Orbital::D,
+            OrbitalU::F(_) => Orbital::F,
+        }
+    }
+}
+
+/// Value of `OrbitalU`
+fn orbital_value(orbital: &OrbitalU) -> f64 {
+    match orbital {
+        OrbitalU::S(v) | OrbitalU::P(v) | OrbitalU::D(v) | OrbitalU::F(v) => *v,
     }
 }
 
@@ -35,13 +59,20 @@ pub enum HubbardType {
     Alpha,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Builder)]
-#[builder()]
+/// One orbital value in `HUBBARD_U` or `HUBBARD_ALPHA`, e.g. the `d: 0.5` of `Fe 1 d: 0.5`
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct HubbardItem {
+    /// As written, e.g.: `Fe`, `Fe1` or `Fe:up`
+    species: String,
+    /// The element of `species`
     element: ElementSymbol,
     atom_id: Option<usize>,
     orbital: Orbital,
     hub_value: f64,
+    /// Position of the source line in the block
+    line: usize,
+    /// Position of the orbital in the source line
+    orbital_index: usize,
 }
 
 impl Display for HubbardItem {
@@ -49,7 +80,7 @@ impl Display for HubbardItem {
         write!(
             f,
             "{:>4} {} {}: {:>20.15}",
-            format!("{:?}", self.element),
+            self.species,
             self.atom_id.map_or(String::new(), |v| format!("{v}")),
             self.orbital,
             self.hub_value
@@ -58,42 +89,22 @@ impl Display for HubbardItem {
 }
 
 impl HubbardItem {
-    pub fn element(&self) -> ElementSymbol {
-        self.element
+    pub fn species(&self) -> &str {
+        &self.species
     }
 
-    pub fn element_mut(&mut self) -> &mut ElementSymbol {
-        &mut self.element
-    }
-
-    pub fn set_element(&mut self, element: ElementSymbol) {
-        self.element = element;
+    pub fn element(&self) -> ElementSymbol {
+        self.element
     }
 
     pub fn atom_id(&self) -> Option<usize> {
         self.atom_id
     }
 
-    pub fn atom_id_mut(&mut self) -> &mut Option<usize> {
-        &mut self.atom_id
-    }
-
-    pub fn set_atom_id(&mut self, atom_id: Option<usize>) {
-        self.atom_id = atom_id;
-    }
-
     pub fn orbital(&self) -> Orbital {
         self.orbital
     }
 
-    pub fn orbital_mut(&mut self) -> &mut Orbital {
-        &mut self.orbital
-    }
-
-    pub fn set_orbital(&mut self, orbital: Orbital) {
-        self.orbital = orbital;
-    }
-
     pub fn hub_value(&self) -> f64 {
         self.hub_value
     }
@@ -105,21 +116,72 @@ impl HubbardItem {
     pub fn set_hub_value(&mut self, u_value: f64) {
         self.hub_value = u_value;
     }
+
+    /// Every item is selected without selector.
+    pub fn is_selected_by(&self, selector: Option<&HubbardSelector>) -> bool {
+        selector.is_none_or(|selector| {
+            selector.matches_atom(&self.species, self.atom_id.map(|id| id as u32))
+                && selector.matches_orbital(self.orbital.label())
+        })
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug)]
+pub struct HubbardSpeciesError(String);
+
+impl Display for HubbardSpeciesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` in a Hubbard block is neither an element nor a label of one, e.g.: Fe1 or Fe:up",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for HubbardSpeciesError {}
+
+/// Orbital-level view of `HUBBARD_U` or `HUBBARD_ALPHA`.
+/// The values edited through the items are written back by `atom_u_values`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct HubbardBlock {
-    // unit: EnergyUnit
     hubbard_type: HubbardType,
+    lines: Vec<AtomHubbardU>,
     settings: Vec<HubbardItem>,
 }
 
 impl HubbardBlock {
-    pub fn new(hubbard_type: HubbardType, settings: Vec<HubbardItem>) -> Self {
-        Self {
+    /// Fails on a species that is not an element or a label of one
+    pub fn new(
+        hubbard_type: HubbardType,
+        lines: &[AtomHubbardU],
+    ) -> Result<Self, HubbardSpeciesError> {
+        let mut settings = Vec::new();
+        for (line, atom) in lines.iter().enumerate() {
+            let species = atom.species.to_string();
+            let element = ElementSymbol::from_str(&element_of(&species))
+                .map_err(|_| HubbardSpeciesError(species.clone()))?;
+            let atom_id = atom.ion_number.map(|id| id as usize);
+            settings.extend(
+                atom.orbitals
+                    .iter()
+                    .enumerate()
+                    .map(|(orbital_index, orbital)| HubbardItem {
+                        species: species.clone(),
+                        element,
+                        atom_id,
+                        orbital: Orbital::from(orbital),
+                        hub_value: orbital_value(orbital),
+                        line,
+                        orbital_index,
+                    }),
+            );
+        }
+        Ok(Self {
             hubbard_type,
+            lines: lines.to_vec(),
             settings,
-        }
+        })
     }
 
     pub fn settings(&self) -> &[HubbardItem] {
@@ -130,9 +192,6 @@ impl HubbardBlock {
         &mut self.settings
     }
 
-    pub fn set_settings(&mut self, settings: Vec<HubbardItem>) {
-        self.settings = settings;
-    }
     pub fn filter_item_mut<F: FnMut(&&mut HubbardItem) -> bool>(
         &mut self,
         item_condition: F,
@@ -140,154 +199,150 @@ impl HubbardBlock {
         self.settings_mut().iter_mut().filter(item_condition)
     }
 
+    /// Set `value` on the items picked by `selector`, or on all items without one.
+    pub fn set_selected(&mut self, value: f64, selector: Option<&HubbardSelector>) {
+        self.filter_item_mut(|item| item.is_selected_by(selector))
+            .for_each(|item| item.set_hub_value(value));
+    }
+
     pub fn hubbard_type(&self) -> HubbardType {
         self.hubbard_type
     }
+
+    /// The block lines with the edited values
+    pub fn atom_u_values(&self) -> Vec<AtomHubbardU> {
+        let mut lines = self.lines.clone();
+        self.settings.iter().for_each(|item| {
+            lines[item.line].orbitals[item.orbital_index].set_u_value(item.hub_value)
+        });
+        lines
+    }
 }
 
-impl BlockIO for HubbardBlock {
-    type Item = HubbardBlock;
-
-    fn from_block(block: &castep_cell_parser::Block) -> Result<Self::Item, CellParseError> {
-        let hub_type = match block.name().to_lowercase().as_str() {
-            "hubbard_u" => Ok(HubbardType::U),
-            "hubbard_alpha" => Ok(HubbardType::Alpha),
-            _ => Err(CellParseError::UnexpectedBlockType((
-                "HUBBARD_U or HUBBARD_ALPHA".to_string(),
-                block.name().to_string(),
-            ))),
-        }?;
-        let settings = block
-            .values()
-            .iter()
-            .flat_map(|line| {
-                let parsed = CELLParser::parse(Rule::hubbard_line, line).unwrap();
-                let atom_id = parsed
-                    .find_first_tagged("atom_id")
-                    .and_then(|p| p.as_str().parse::<usize>().ok());
-                let element_symbol = parsed
-                    .find_first_tagged("symbol")
-                    .and_then(|p| ElementSymbol::from_str(p.as_str()).ok())
-                    .unwrap();
-                // Collect hubbard settings from each line
-                parsed
-                    .filter_map(|pair| {
-                        if matches!(pair.as_rule(), Rule::hubbard_value) {
-                            let mut inner = pair.into_inner();
-                            let orbital = inner
-                                .next()
-                                .and_then(|orbital| {
-                                    match orbital.as_str().to_lowercase().as_str() {
-                                        "s" => Some(Orbital::S),
-                                        "p" => Some(Orbital::P),
-                                        "d" => Some(Orbital::D),
-                                        "f" => Some(Orbital::F),
-                                        _ => None,
-                                    }
-                                })
-                                .unwrap();
-                            let value = inner
-                                .next()
-                                .and_then(|value| value.as_str().parse::<f64>().ok())
-                                .unwrap();
-                            Some(
-                                HubbardItemBuilder::default()
-                                    .element(element_symbol)
-                                    .orbital(orbital)
-                                    .atom_id(atom_id)
-                                    .hub_value(value)
-                                    .build()
-                                    .unwrap(),
-                            )
-                        } else {
-                            None
-                        }
+/// Replace each line without ion number, such as `Fe d: 5.0`, by one line per ion of the species
+/// when `selector` picks a single ion of it, so that editing the selected ion leaves the other
+/// ions as they are. Ions with a line of their own keep it. `ion_count` is the number of ions
+/// of a species in the positions.
+pub fn split_species_lines<F: Fn(&str) -> u32>(
+    lines: &[AtomHubbardU],
+    selector: Option<&HubbardSelector>,
+    ion_count: F,
+) -> Vec<AtomHubbardU> {
+    lines
+        .iter()
+        .flat_map(|line| {
+            let species = line.species.to_string();
+            let split = line.ion_number.is_none()
+                && selector.is_some_and(|selector| {
+                    selector.ion_number().is_some() && selector.matches_atom(&species, None)
+                });
+            if !split {
+                return vec![line.clone()];
+            }
+            (1..=ion_count(&species))
+                .filter(|ion| {
+                    !lines.iter().any(|other| {
+                        other.ion_number == Some(*ion)
+                            && other.species.to_string().eq_ignore_ascii_case(&species)
                     })
-                    .collect::<Vec<HubbardItem>>()
-            })
-            .collect::<Vec<HubbardItem>>();
-        Ok(Self::new(hub_type, settings))
+                })
+                .map(|ion| {
+                    let mut per_ion = line.clone();
+                    per_ion.ion_number = Some(ion);
+                    per_ion
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl TryFrom<&HubbardU> for HubbardBlock {
+    type Error = HubbardSpeciesError;
+
+    fn try_from(hubbard_u: &HubbardU) -> Result<Self, Self::Error> {
+        Self::new(HubbardType::U, &hubbard_u.atom_u_values)
     }
+}
 
-    fn to_block(&self, order: usize) -> castep_cell_parser::Block {
-        let name = match self.hubbard_type {
-            HubbardType::U => "HUBBARD_U",
-            HubbardType::Alpha => "HUBBARD_ALPHA",
-        };
-        BlockBuilder::default()
-            .order(order)
-            .name(name.to_string())
-            .values(
-                self.settings()
-                    .iter()
-                    .map(|v| v.to_string())
-                    .collect::<Vec<String>>(),
-            )
-            .build()
-            .unwrap()
+impl TryFrom<&HubbardAlpha> for HubbardBlock {
+    type Error = HubbardSpeciesError;
+
+    fn try_from(hubbard_alpha: &HubbardAlpha) -> Result<Self, Self::Error> {
+        Self::new(HubbardType::Alpha, &hubbard_alpha.atom_u_values)
     }
 }
+
 #[cfg(test)]
 mod test {
-    use std::str::FromStr;
+    use std::{fs::read_to_string, path::Path};
 
-    use castep_cell_parser::{BlockIO, CELLParser, Rule};
     use castep_periodic_table::element::ElementSymbol;
-    use pest::Parser;
 
-    use crate::seed_settings::hubbard::{HubbardItem, HubbardItemBuilder, HubbardType, Orbital};
+    use crate::seed_settings::CellFile;
 
-    use super::HubbardBlock;
+    use super::{HubbardBlock, HubbardType, Orbital};
 
     #[test]
     fn test_hubbard_u() {
-        let hubbard_u_line = "    U 2  d: 1.2 f: 2.1";
-        let parsed = CELLParser::parse(Rule::hubbard_line, hubbard_u_line).unwrap();
-        let atom_id = parsed
-            .find_first_tagged("atom_id")
-            .and_then(|p| p.as_str().parse::<usize>().ok());
-        let element_symbol = parsed
-            .find_first_tagged("symbol")
-            .and_then(|p| ElementSymbol::from_str(p.as_str()).ok())
+        let cell_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .join("sh/test/GDY_111_Fe_U.cell");
+        let cell = read_to_string(cell_path)
+            .unwrap()
+            .parse::<CellFile>()
             .unwrap();
-        let values = parsed
-            .filter_map(|pair| {
-                if matches!(pair.as_rule(), Rule::hubbard_value) {
-                    let mut inner = pair.into_inner();
-                    let orbital = inner
-                        .next()
-                        .and_then(|orbital| match orbital.as_str().to_lowercase().as_str() {
-                            "s" => Some(Orbital::S),
-                            "p" => Some(Orbital::P),
-                            "d" => Some(Orbital::D),
-                            "f" => Some(Orbital::F),
-                            _ => None,
-                        })
-                        .unwrap();
-                    let value = inner
-                        .next()
-                        .and_then(|value| value.as_str().parse::<f64>().ok())
-                        .unwrap();
-                    Some(
-                        HubbardItemBuilder::default()
-                            .element(element_symbol)
-                            .orbital(orbital)
-                            .atom_id(atom_id)
-                            .hub_value(value)
-                            .build()
-                            .unwrap(),
-                    )
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<HubbardItem>>();
-        values.iter().for_each(|item| println!("{item}"));
-        let mut hubbard_u_block = HubbardBlock::new(HubbardType::U, values);
-        println!("{}", hubbard_u_block.to_block(1));
+        let hubbard_u = cell.hubbard_u().unwrap().clone();
+        let mut hubbard_u_block = HubbardBlock::try_from(&hubbard_u).unwrap();
+        assert_eq!(hubbard_u_block.hubbard_type(), HubbardType::U);
+        let item = &hubbard_u_block.settings()[0];
+        assert_eq!(item.element(), ElementSymbol::Fe);
+        assert_eq!(item.atom_id(), Some(1));
+        assert_eq!(item.orbital(), Orbital::D);
+        assert_eq!(item.hub_value(), 0.5);
+        hubbard_u_block
+            .settings()
+            .iter()
+            .for_each(|item| println!("{item}"));
         hubbard_u_block
-            .filter_item_mut(|item| item.element() == ElementSymbol::U)
+            .filter_item_mut(|item| item.element() == ElementSymbol::Fe)
             .for_each(|item| item.set_hub_value(3.2));
-        println!("{}", hubbard_u_block.to_block(1));
+        let edited = HubbardBlock::new(HubbardType::U, &hubbard_u_block.atom_u_values()).unwrap();
+        assert_eq!(edited.settings()[0].hub_value(), 3.2);
+        // Unchanged values are written back as they are
+        assert_eq!(
+            HubbardBlock::try_from(&hubbard_u).unwrap().atom_u_values(),
+            hubbard_u.atom_u_values
+        );
+    }
+
+    #[test]
+    fn labelled_species() {
+        let seed = "%BLOCK LATTICE_CART\n4.17 0 0\n0 4.17 0\n0 0 4.17\n%ENDBLOCK LATTICE_CART\n\
+            %BLOCK POSITIONS_FRAC\nFe1 0 0 0\nFe:up 0.5 0.5 0.5\n%ENDBLOCK POSITIONS_FRAC\n\
+            %BLOCK HUBBARD_U\neV\nFe1 1 d: 4.0\nFe:up d: 3.0\n%ENDBLOCK HUBBARD_U\n";
+        let cell = seed.parse::<CellFile>().unwrap();
+        let block = HubbardBlock::try_from(cell.hubbard_u().unwrap()).unwrap();
+        let sites = block
+            .settings()
+            .iter()
+            .map(|item| (item.species(), item.element(), item.atom_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sites,
+            [
+                ("Fe1", ElementSymbol::Fe, Some(1)),
+                ("Fe:up", ElementSymbol::Fe, None)
+            ]
+        );
+        let fe1 = "Fe1:1".parse().unwrap();
+        assert!(block.settings()[0].is_selected_by(Some(&fe1)));
+        assert!(!block.settings()[1].is_selected_by(Some(&fe1)));
+        // An unknown species is an error, not a panic
+        let unknown = seed
+            .replace("Fe1 1 d: 4.0", "Xq 1 d: 4.0")
+            .parse::<CellFile>()
+            .unwrap();
+        assert!(HubbardBlock::try_from(unknown.hubbard_u().unwrap()).is_err());
     }
 }
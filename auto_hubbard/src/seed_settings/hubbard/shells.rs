@@ -0,0 +1,108 @@
+use std::str::FromStr;
+
+use castep_periodic_table::{
+    data::ELEMENT_TABLE,
+    element::{ElementSymbol, LookupElement},
+};
+
+use crate::seed_settings::{element_of, Atom};
+
+use super::Orbital;
+
+/// The shell given a U, from the atomic number: d for the transition metals of the
+/// 3d to 6d series, f for the lanthanides and actinides, `None` for the other elements
+pub fn correlated_shell(element: ElementSymbol) -> Option<Orbital> {
+    match ELEMENT_TABLE.get_by_symbol(element).atomic_number() {
+        57..=71 | 89..=103 => Some(Orbital::F),
+        21..=30 | 39..=48 | 72..=80 | 104..=112 => Some(Orbital::D),
+        _ => None,
+    }
+}
+
+/// Lines of a `HUBBARD_U` block setting `u_value` on every ion with a correlated shell,
+/// and on the p shell of every ion of `ligands`, e.g.: `Fe 1 d: 0.00000001`.
+/// Ions are numbered within their species label, in the order of the positions,
+/// and the shell is chosen from the element of the label, e.g.: `Fe1 2 d` or `Fe:up 1 d`.
+pub fn hubbard_u_lines(atoms: &[Atom], u_value: f64, ligands: &[ElementSymbol]) -> Vec<String> {
+    let mut ion_numbers: Vec<(&str, usize)> = Vec::new();
+    atoms
+        .iter()
+        .filter_map(|atom| {
+            let species = atom.species.as_str();
+            let ion = match ion_numbers.iter_mut().find(|(known, _)| *known == species) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count
+                }
+                None => {
+                    ion_numbers.push((species, 1));
+                    1
+                }
+            };
+            let element = ElementSymbol::from_str(&element_of(species)).ok()?;
+            correlated_shell(element)
+                .or_else(|| ligands.contains(&element).then_some(Orbital::P))
+                .map(|orbital| format!("{species} {ion} {orbital}: {u_value}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use castep_periodic_table::element::ElementSymbol;
+
+    use crate::seed_settings::{hubbard::Orbital, Atom};
+
+    use super::{correlated_shell, hubbard_u_lines};
+
+    #[test]
+    fn shells_of_positions() {
+        assert_eq!(correlated_shell(ElementSymbol::Fe), Some(Orbital::D));
+        assert_eq!(correlated_shell(ElementSymbol::Ce), Some(Orbital::F));
+        assert_eq!(correlated_shell(ElementSymbol::U), Some(Orbital::F));
+        assert_eq!(correlated_shell(ElementSymbol::O), None);
+        assert_eq!(correlated_shell(ElementSymbol::Lu), Some(Orbital::F));
+        assert_eq!(correlated_shell(ElementSymbol::Hf), Some(Orbital::D));
+        assert_eq!(correlated_shell(ElementSymbol::Ga), None);
+        let atoms = ["Ni", "O", "Ni", "O", "C"]
+            .map(|species| Atom {
+                species: species.to_string(),
+                frac: [0.0; 3],
+            })
+            .to_vec();
+        assert_eq!(
+            hubbard_u_lines(&atoms, 1e-8, &[]),
+            ["Ni 1 d: 0.00000001", "Ni 2 d: 0.00000001"]
+        );
+        assert_eq!(
+            hubbard_u_lines(&atoms, 1e-8, &[ElementSymbol::O, ElementSymbol::N]),
+            [
+                "Ni 1 d: 0.00000001",
+                "O 1 p: 0.00000001",
+                "Ni 2 d: 0.00000001",
+                "O 2 p: 0.00000001"
+            ]
+        );
+    }
+
+    #[test]
+    fn labelled_species() {
+        let atoms = ["Fe1", "O", "Fe2", "Fe1", "Fe:up", "O1"]
+            .map(|species| Atom {
+                species: species.to_string(),
+                frac: [0.0; 3],
+            })
+            .to_vec();
+        assert_eq!(
+            hubbard_u_lines(&atoms, 1e-8, &[ElementSymbol::O]),
+            [
+                "Fe1 1 d: 0.00000001",
+                "O 1 p: 0.00000001",
+                "Fe2 1 d: 0.00000001",
+                "Fe1 2 d: 0.00000001",
+                "Fe:up 1 d: 0.00000001",
+                "O1 1 p: 0.00000001"
+            ]
+        );
+    }
+}
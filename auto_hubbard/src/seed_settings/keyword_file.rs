@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+/// One piece of a `.param` or `.cell`, kept as written
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    /// `key : value`, `key = value` or `key value`
+    Keyword { name: String, line: String },
+    /// `%BLOCK name` ... `%ENDBLOCK name`, with both marker lines
+    Block { name: String, lines: Vec<String> },
+    /// Comments, blank lines, or anything else
+    Other(String),
+}
+
+impl Entry {
+    /// Lowercase keyword or block name, `None` for the other lines
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Entry::Keyword { name, .. } | Entry::Block { name, .. } => Some(name),
+            Entry::Other(_) => None,
+        }
+    }
+
+    /// The lines as read, several for a block
+    pub fn lines(&self) -> Vec<&str> {
+        match self {
+            Entry::Keyword { line, .. } | Entry::Other(line) => vec![line],
+            Entry::Block { lines, .. } => lines.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// The keywords and blocks of a `.param` or `.cell` in their original order,
+/// so the ones we do not model are written back verbatim.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordFile {
+    entries: Vec<Entry>,
+    /// `\r\n` for the files from Materials Studio on Windows
+    line_ending: &'static str,
+}
+
+/// Lowercase name of a keyword line, without the `:`/`=` separator and comments
+fn keyword_name(line: &str) -> Option<String> {
+    let content = line.split(['!', '#']).next().unwrap_or_default().trim();
+    content
+        .split(|c: char| c == ':' || c == '=' || c.is_whitespace())
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Lowercase name of `%BLOCK name` or `%ENDBLOCK name`
+fn block_marker(line: &str, marker: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    words
+        .next()
+        .filter(|word| word.eq_ignore_ascii_case(marker))
+        .and(words.next())
+        .map(str::to_lowercase)
+}
+
+impl KeywordFile {
+    pub fn parse(content: &str) -> Self {
+        let line_ending = if content.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut entries = Vec::new();
+        let mut lines = content.lines();
+        while let Some(line) = lines.next() {
+            if let Some(name) = block_marker(line, "%BLOCK") {
+                let mut block = vec![line.to_string()];
+                for inner in lines.by_ref() {
+                    block.push(inner.to_string());
+                    if block_marker(inner, "%ENDBLOCK").is_some() {
+                        break;
+                    }
+                }
+                entries.push(Entry::Block { name, lines: block });
+            } else if let Some(name) = keyword_name(line) {
+                entries.push(Entry::Keyword {
+                    name,
+                    line: line.to_string(),
+                });
+            } else {
+                entries.push(Entry::Other(line.to_string()));
+            }
+        }
+        Self {
+            entries,
+            line_ending,
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Value of the keyword `name`, without the separator and comments
+    pub fn value(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.entries.iter().find_map(|entry| match entry {
+            Entry::Keyword {
+                name: keyword,
+                line,
+            } if *keyword == name => {
+                let content = line.split(['!', '#']).next().unwrap_or_default().trim();
+                Some(
+                    content
+                        .get(keyword.len()..)
+                        .unwrap_or_default()
+                        .trim_start()
+                        .trim_start_matches([':', '='])
+                        .trim(),
+                )
+            }
+            _ => None,
+        })
+    }
+
+    /// Lines between `%BLOCK name` and `%ENDBLOCK name`, without comments and blank lines
+    pub fn block(&self, name: &str) -> Option<Vec<&str>> {
+        let name = name.to_lowercase();
+        self.entries.iter().find_map(|entry| match entry {
+            Entry::Block { name: block, lines } if *block == name => Some(
+                lines
+                    .iter()
+                    .skip(1)
+                    .take(lines.len().saturating_sub(2))
+                    .map(|line| line.split(['!', '#']).next().unwrap_or_default().trim())
+                    .filter(|line| !line.is_empty())
+                    .collect(),
+            ),
+            _ => None,
+        })
+    }
+
+    /// Read the keyword or block `from` as `to`, e.g. the `KPOINT_LIST` alias as `KPOINTS_LIST`.
+    /// Only the name changes; the entry is written as it was read unless merged over.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        let (from, to) = (from.to_lowercase(), to.to_lowercase());
+        self.entries.iter_mut().for_each(|entry| match entry {
+            Entry::Keyword { name, .. } | Entry::Block { name, .. } if *name == from => {
+                *name = to.clone()
+            }
+            _ => {}
+        });
+    }
+
+    /// Whether the keyword or block `name` (case insensitive) is present
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.entries
+            .iter()
+            .any(|entry| entry.name() == Some(name.as_str()))
+    }
+
+    /// Write the file again with the keywords and blocks of `rendered` in place of ours.
+    /// Those among `managed` missing from `rendered` are removed, those new to us are
+    /// appended at the end. Everything else is written as it was read.
+    pub fn merge(&self, rendered: &str, managed: &[&str]) -> String {
+        let managed = managed
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect::<Vec<String>>();
+        let rendered = KeywordFile::parse(rendered);
+        let mut replacements = rendered
+            .entries
+            .iter()
+            .filter_map(|entry| entry.name().map(|name| (name.to_string(), entry)))
+            .collect::<HashMap<String, &Entry>>();
+        let kept = self.entries.iter().filter_map(|entry| match entry.name() {
+            Some(name) if managed.iter().any(|managed| managed == name) => {
+                replacements.remove(name)
+            }
+            _ => Some(entry),
+        });
+        let kept = kept.collect::<Vec<&Entry>>();
+        let appended = rendered.entries.iter().filter(|entry| {
+            entry
+                .name()
+                .is_some_and(|name| replacements.contains_key(name))
+        });
+        let mut output = kept
+            .into_iter()
+            .chain(appended)
+            .flat_map(Entry::lines)
+            .collect::<Vec<&str>>()
+            .join(self.line_ending);
+        output.push_str(self.line_ending);
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, KeywordFile};
+
+    #[test]
+    fn merge_keeps_unknown_entries() {
+        let content = "task : SinglePoint\r\ncomment : from Materials Studio\r\n\
+            ! a comment\r\ngrid_scale :   2.0\r\npage_wvfns :  0\r\n\
+            %BLOCK devel_code\r\nSCF: DEBUG\r\n%ENDBLOCK devel_code\r\nfine_grid_scale = 3\r\n";
+        let file = KeywordFile::parse(content);
+        assert_eq!(file.entries().len(), 7);
+        assert!(matches!(
+            &file.entries()[5],
+            Entry::Block { name, lines } if name == "devel_code" && lines.len() == 3
+        ));
+        assert!(file.contains("PAGE_WVFNS"));
+        assert_eq!(file.block("DEVEL_CODE"), Some(vec!["SCF: DEBUG"]));
+        assert_eq!(file.block("species_pot"), None);
+        assert_eq!(file.value("grid_scale"), Some("2.0"));
+        assert_eq!(file.value("FINE_GRID_SCALE"), Some("3"));
+        // Nothing managed gives the input back
+        assert_eq!(file.merge("", &[]), content);
+        let merged = file.merge(
+            "task : SinglePoint\ngrid_scale : 1.75\ncontinuation : default\n",
+            &["task", "grid_scale", "fine_grid_scale", "continuation"],
+        );
+        assert_eq!(
+            merged,
+            "task : SinglePoint\r\ncomment : from Materials Studio\r\n\
+            ! a comment\r\ngrid_scale : 1.75\r\npage_wvfns :  0\r\n\
+            %BLOCK devel_code\r\nSCF: DEBUG\r\n%ENDBLOCK devel_code\r\ncontinuation : default\r\n"
+        );
+    }
+}
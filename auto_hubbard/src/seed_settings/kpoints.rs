@@ -0,0 +1,125 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::geometry::{cross, dot, norm};
+
+/// The k-point sampling written into the `.cell` of every job instead of the one in the seed,
+/// e.g. a Monkhorst-Pack grid in place of the `KPOINTS_LIST` of a cluster.
+/// Written as `4x4x1` (or `4,4,1`) for a grid, or as a spacing in 1/Å, e.g.: `0.05`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KpointSampling {
+    MpGrid([u32; 3]),
+    /// Turned into the grid with at most this spacing along each reciprocal lattice vector
+    MpSpacing(f64),
+}
+
+impl KpointSampling {
+    /// The grid for the lattice vectors `lattice` (rows of `LATTICE_CART`, in Å)
+    pub fn mp_grid(&self, lattice: &[[f64; 3]; 3]) -> [u32; 3] {
+        match self {
+            KpointSampling::MpGrid(grid) => *grid,
+            KpointSampling::MpSpacing(spacing) => reciprocal_lengths(lattice)
+                .map(|length| ((length / spacing - 1e-8).ceil() as u32).max(1)),
+        }
+    }
+}
+
+/// Lengths of the reciprocal lattice vectors `(b × c) / V`, ..., without the 2π factor,
+/// as `CASTEP` measures `KPOINTS_MP_SPACING`
+pub fn reciprocal_lengths(lattice: &[[f64; 3]; 3]) -> [f64; 3] {
+    let [a, b, c] = lattice;
+    let bc = cross(b, c);
+    let volume = dot(a, &bc).abs();
+    [bc, cross(c, a), cross(a, b)].map(|v| norm(&v) / volume)
+}
+
+impl Display for KpointSampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KpointSampling::MpGrid([a, b, c]) => write!(f, "{a}x{b}x{c}"),
+            KpointSampling::MpSpacing(spacing) => write!(f, "{spacing}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct KpointSamplingParsingError(String);
+
+impl Display for KpointSamplingParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid k-point sampling `{}`, expected a grid like `4x4x1` or a spacing in 1/Å like `0.05`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for KpointSamplingParsingError {}
+
+impl FromStr for KpointSampling {
+    type Err = KpointSamplingParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || KpointSamplingParsingError(s.to_string());
+        let parts = s
+            .split(['x', 'X', ','])
+            .map(str::trim)
+            .collect::<Vec<&str>>();
+        match parts.as_slice() {
+            [spacing] => spacing
+                .parse::<f64>()
+                .ok()
+                .filter(|spacing| *spacing > 0.0)
+                .map(KpointSampling::MpSpacing)
+                .ok_or_else(error),
+            [_, _, _] => {
+                let grid = parts
+                    .iter()
+                    .map(|n| n.parse::<u32>().ok().filter(|n| *n > 0))
+                    .collect::<Option<Vec<u32>>>()
+                    .ok_or_else(error)?;
+                Ok(KpointSampling::MpGrid([grid[0], grid[1], grid[2]]))
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KpointSampling;
+
+    #[test]
+    fn parse_sampling() {
+        let grid: KpointSampling = "4x4x1".parse().unwrap();
+        assert_eq!(grid, KpointSampling::MpGrid([4, 4, 1]));
+        assert_eq!(grid.to_string(), "4x4x1");
+        assert_eq!(
+            "2, 2, 2".parse::<KpointSampling>().unwrap().to_string(),
+            "2x2x2"
+        );
+        assert_eq!(
+            "0.05".parse::<KpointSampling>().unwrap(),
+            KpointSampling::MpSpacing(0.05)
+        );
+        assert!("4x4".parse::<KpointSampling>().is_err());
+        assert!("0x1x1".parse::<KpointSampling>().is_err());
+        assert!("-0.1".parse::<KpointSampling>().is_err());
+    }
+
+    #[test]
+    fn grid_from_spacing() {
+        // |b_i| = 1 / 5 = 0.2 1/Å for a 5 Å cube, 1 / 20 for the 20 Å vacuum direction
+        let lattice = [[5.0, 0.0, 0.0], [0.0, 5.0, 0.0], [0.0, 0.0, 20.0]];
+        assert_eq!(KpointSampling::MpSpacing(0.3).mp_grid(&lattice), [1, 1, 1]);
+        assert_eq!(KpointSampling::MpSpacing(0.05).mp_grid(&lattice), [4, 4, 1]);
+        assert_eq!(KpointSampling::MpSpacing(0.03).mp_grid(&lattice), [7, 7, 2]);
+        assert_eq!(
+            KpointSampling::MpGrid([1, 1, 1]).mp_grid(&lattice),
+            [1, 1, 1]
+        );
+    }
+}
@@ -1,7 +1,12 @@
 mod cell_setup;
+mod geometry;
+mod hubbard;
 mod job_type;
+mod keyword_file;
+mod kpoints;
 mod param_setup;
 mod private;
+mod selector;
 mod stage;
 mod hubbard_value {
 
@@ -25,7 +30,12 @@ mod hubbard_value {
     }
 }
 
-pub use cell_setup::{CellFile, HubbardUCell};
+pub use cell_setup::{CellFile, CellFileParsingError, HubbardUCell};
+pub use geometry::{element_of, Atom, Geometry, GeometryError, LatticeForm, PositionsForm};
+pub use hubbard::{HubbardBlock, HubbardItem, HubbardSpeciesError};
 pub use job_type::{JobType, JobTypeParsingError};
-pub use param_setup::{HubbardUParam, ParamFile};
+pub use keyword_file::{Entry, KeywordFile};
+pub use kpoints::{KpointSampling, KpointSamplingParsingError};
+pub use param_setup::{HubbardUParam, ParamFile, ParamFileParsingError, ParamOverrides};
+pub use selector::{HubbardSelector, HubbardSelectorParsingError};
 pub use stage::{BeforePerturb, Init, Perturbed, Stage};
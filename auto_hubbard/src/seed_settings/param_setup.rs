@@ -1,64 +1,235 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, str::FromStr};
 
 use castep_cell_data::{
+    from_str,
     param::{
-        basis_set::{CutOffEnergy, FineGridScale, FiniteBasisCorr, GridScale},
-        density_mixing::{
-            MixChargeAmp, MixChargeGmax, MixHistoryLength, MixSpinAmp, MixSpinGmax, MixingScheme,
-        },
-        electronic::{NextraBands, Spin},
-        electronic_minimisation::{
-            ElecEnergyTol, FixOccupancy, MaxScfCycles, MetalsMethod, NumDumpCycles, SmearingWidth,
-            SpinFix,
-        },
-        exchange_correlation::{SpinPolarized, XcFunctional},
-        general::{
-            CalculateDensdiff, CalculateElf, CalculateHirshfeld, CalculateStress, Continuation,
-            Iprint, OptStrategy, Task,
-        },
-        population_analysis::{PdosCalculateWeights, PopnBondCutoff, PopnCalculate},
+        basis_set::{CutOffEnergy, FineGridScale, GridScale},
+        density_mixing::{MixChargeAmp, MixSpinAmp, MixingScheme},
+        electronic_minimisation::{ElecEnergyTol, MaxScfCycles, MetalsMethod},
+        general::{Continuation, Task},
     },
-    ToCellFileDerive,
+    ToCellFile, ToCellFileDerive,
 };
 use serde::{Deserialize, Serialize};
 
-use super::{BeforePerturb, Init, Perturbed, Stage};
+use crate::pipeline::Adjustment;
 
-#[derive(Debug, Clone, Serialize, Deserialize, ToCellFileDerive)]
-pub struct ParamFile {
-    task: Task,
+use super::{
+    keyword_file::{Entry, KeywordFile},
+    BeforePerturb, Init, Perturbed, Stage,
+};
+
+/// `CASTEP` default of `max_scf_cycles`
+const DEFAULT_MAX_SCF_CYCLES: u32 = 30;
+/// `CASTEP` default of `mix_charge_amp`
+const DEFAULT_MIX_CHARGE_AMP: f64 = 0.8;
+/// `CASTEP` default of `mix_spin_amp`
+const DEFAULT_MIX_SPIN_AMP: f64 = 2.0;
+/// `CASTEP` default of `elec_energy_tol`, in eV
+const DEFAULT_ELEC_ENERGY_TOL: f64 = 1e-5;
+
+/// The keywords we read or change, every one optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToCellFileDerive)]
+struct TypedParam {
+    task: Option<Task>,
     continuation: Option<Continuation>,
-    xc_functional: XcFunctional,
-    spin_polarized: SpinPolarized,
-    spin: Spin,
-    opt_strategy: OptStrategy,
-    cut_off_energy: CutOffEnergy,
-    grid_scale: GridScale,
+    cut_off_energy: Option<CutOffEnergy>,
+    grid_scale: Option<GridScale>,
     fine_grid_scale: Option<FineGridScale>,
-    finite_basis_corr: FiniteBasisCorr,
-    elec_energy_tol: ElecEnergyTol,
-    max_scf_cycles: MaxScfCycles,
-    fix_occupancy: FixOccupancy,
-    metals_method: MetalsMethod,
-    mixing_scheme: MixingScheme,
-    mix_charge_amp: MixChargeAmp,
-    mix_spin_amp: MixSpinAmp,
-    mix_charge_gmax: MixChargeGmax,
-    mix_spin_gmax: MixSpinGmax,
-    mix_history_length: MixHistoryLength,
-    nextra_bands: NextraBands,
-    smearing_width: SmearingWidth,
-    spin_fix: SpinFix,
-    num_dump_cycles: NumDumpCycles,
-    #[serde(alias = "calculate_ELF")]
-    calculate_elf: CalculateElf,
-    calculate_stress: CalculateStress,
-    popn_calculate: PopnCalculate,
-    calculate_hirshfeld: CalculateHirshfeld,
-    calculate_densdiff: CalculateDensdiff,
-    popn_bond_cutoff: PopnBondCutoff,
-    pdos_calculate_weights: PdosCalculateWeights,
-    iprint: Iprint,
+    elec_energy_tol: Option<ElecEnergyTol>,
+    max_scf_cycles: Option<MaxScfCycles>,
+    metals_method: Option<MetalsMethod>,
+    mix_charge_amp: Option<MixChargeAmp>,
+    mix_spin_amp: Option<MixSpinAmp>,
+    mixing_scheme: Option<MixingScheme>,
+}
+
+impl TypedParam {
+    fn elec_energy_tol(&self) -> ElecEnergyTol {
+        self.elec_energy_tol.unwrap_or(ElecEnergyTol {
+            value: DEFAULT_ELEC_ENERGY_TOL,
+            unit: None,
+        })
+    }
+}
+
+/// Keywords of `TypedParam`, which are written from it
+const TYPED_KEYWORDS: [&str; 11] = [
+    "task",
+    "continuation",
+    "cut_off_energy",
+    "grid_scale",
+    "fine_grid_scale",
+    "elec_energy_tol",
+    "max_scf_cycles",
+    "metals_method",
+    "mix_charge_amp",
+    "mix_spin_amp",
+    "mixing_scheme",
+];
+
+/// A `.param` with the keywords we change typed, and all the others
+/// (`comment`, `page_wvfns`, ...) written back verbatim in their original order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamFile {
+    typed: TypedParam,
+    keywords: KeywordFile,
+}
+
+#[derive(Debug)]
+pub struct ParamFileParsingError(String);
+
+impl std::fmt::Display for ParamFileParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid `.param`: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParamFileParsingError {}
+
+impl FromStr for ParamFile {
+    type Err = ParamFileParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keywords = KeywordFile::parse(s);
+        // Only the typed keywords go through `castep_cell_data`
+        let typed_content = keywords
+            .entries()
+            .iter()
+            .filter(|entry| {
+                entry
+                    .name()
+                    .is_some_and(|name| TYPED_KEYWORDS.contains(&name))
+            })
+            .flat_map(Entry::lines)
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let typed = match typed_content.trim().is_empty() {
+            true => TypedParam::default(),
+            false => from_str::<TypedParam>(&typed_content)
+                .map_err(|e| ParamFileParsingError(e.to_string()))?,
+        };
+        Ok(Self { typed, keywords })
+    }
+}
+
+impl ParamFile {
+    /// The `.param` content, the typed keywords at their original place
+    pub fn to_param_file(&self) -> String {
+        self.keywords
+            .merge(&self.typed.to_cell_file(), &TYPED_KEYWORDS)
+    }
+
+    pub fn max_scf_cycles(&self) -> usize {
+        self.typed
+            .max_scf_cycles
+            .map_or(DEFAULT_MAX_SCF_CYCLES, |cycles| cycles.0) as usize
+    }
+
+    /// Whether `metals_method` is `dm`, the `CASTEP` default
+    pub fn uses_density_mixing(&self) -> bool {
+        matches!(self.typed.metals_method, None | Some(MetalsMethod::Dm))
+    }
+
+    /// Whether `mixing_scheme` is `pulay`, rather than the `CASTEP` default `broyden`
+    pub fn uses_pulay_mixing(&self) -> bool {
+        matches!(self.typed.mixing_scheme, Some(MixingScheme::Pulay))
+    }
+
+    /// `elec_energy_tol`, the `CASTEP` default if not given
+    pub fn elec_energy_tol(&self) -> ElecEnergyTol {
+        self.typed.elec_energy_tol()
+    }
+
+    /// Value of any keyword as written, e.g.: `spin_polarized`
+    pub fn keyword_value(&self, name: &str) -> Option<&str> {
+        self.keywords.value(name)
+    }
+
+    /// `continuation`, e.g.: `default` to restart from the `.check` of the seed
+    pub fn continuation(&self) -> Option<&str> {
+        self.typed
+            .continuation
+            .as_ref()
+            .map(|continuation| continuation.0.as_str())
+    }
+
+    fn with_typed(&self, typed: TypedParam) -> Self {
+        Self {
+            typed,
+            keywords: self.keywords.clone(),
+        }
+    }
+
+    /// Same settings with the recovery `adjustments` applied in order
+    pub fn adjusted(&self, adjustments: &[Adjustment]) -> Self {
+        let typed =
+            adjustments
+                .iter()
+                .fold(self.typed.clone(), |typed, adjustment| match adjustment {
+                    Adjustment::MoreScfCycles(factor) => TypedParam {
+                        max_scf_cycles: Some(MaxScfCycles(
+                            typed
+                                .max_scf_cycles
+                                .map_or(DEFAULT_MAX_SCF_CYCLES, |cycles| cycles.0)
+                                * factor,
+                        )),
+                        ..typed
+                    },
+                    Adjustment::DampMixing(factor) => TypedParam {
+                        mix_charge_amp: Some(MixChargeAmp(
+                            typed
+                                .mix_charge_amp
+                                .map_or(DEFAULT_MIX_CHARGE_AMP, |amp| amp.0)
+                                * factor,
+                        )),
+                        mix_spin_amp: Some(MixSpinAmp(
+                            typed.mix_spin_amp.map_or(DEFAULT_MIX_SPIN_AMP, |amp| amp.0) * factor,
+                        )),
+                        ..typed
+                    },
+                    Adjustment::SwitchToPulay => TypedParam {
+                        mixing_scheme: Some(MixingScheme::Pulay),
+                        ..typed
+                    },
+                    Adjustment::SwitchToEdft => TypedParam {
+                        metals_method: Some(MetalsMethod::Edft),
+                        ..typed
+                    },
+                    Adjustment::LoosenEnergyTol(factor) => {
+                        let tol = typed.elec_energy_tol();
+                        TypedParam {
+                            elec_energy_tol: Some(ElecEnergyTol {
+                                value: tol.value * factor,
+                                unit: tol.unit,
+                            }),
+                            ..typed
+                        }
+                    }
+                });
+        self.with_typed(typed)
+    }
+
+    /// Same settings, restarting from the `.check` of the seed with `continuation : default`
+    pub fn continued(&self) -> Self {
+        self.with_typed(TypedParam {
+            continuation: Some(Continuation("default".to_string())),
+            ..self.typed.clone()
+        })
+    }
+}
+
+/// Settings of the unperturbed jobs given on the command line, in place of the seed's
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamOverrides {
+    pub elec_energy_tol: ElecEnergyTol,
+    /// In eV, the seed's if `None`
+    pub cut_off_energy: Option<f64>,
+    pub grid_scale: f64,
+    /// Left to `CASTEP` (same as `grid_scale`) if `None`
+    pub fine_grid_scale: Option<f64>,
+    /// The seed's if `None`
+    pub max_scf_cycles: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -75,17 +246,23 @@ impl HubbardUParam<Init> {
         }
     }
     /// Create a new `.param` for our task.
-    pub fn param_before_perturb(
-        &self,
-        init_elec_energy_tol: ElecEnergyTol,
-    ) -> HubbardUParam<BeforePerturb> {
-        let new_param = ParamFile {
+    pub fn param_before_perturb(&self, overrides: &ParamOverrides) -> HubbardUParam<BeforePerturb> {
+        let typed = &self.param.typed;
+        let new_param = self.param.with_typed(TypedParam {
             continuation: None,
-            elec_energy_tol: init_elec_energy_tol,
-            fine_grid_scale: None,
-            grid_scale: GridScale(1.75),
-            ..self.param.clone()
-        };
+            elec_energy_tol: Some(overrides.elec_energy_tol),
+            cut_off_energy: overrides
+                .cut_off_energy
+                .map(|value| CutOffEnergy { value, unit: None })
+                .or(typed.cut_off_energy),
+            fine_grid_scale: overrides.fine_grid_scale.map(FineGridScale),
+            grid_scale: Some(GridScale(overrides.grid_scale)),
+            max_scf_cycles: overrides
+                .max_scf_cycles
+                .map(MaxScfCycles)
+                .or(typed.max_scf_cycles),
+            ..typed.clone()
+        });
         HubbardUParam {
             param: new_param,
             stage: PhantomData,
@@ -96,17 +273,21 @@ impl HubbardUParam<Init> {
 impl HubbardUParam<BeforePerturb> {
     /// Create a new `.param` for perturbation.
     /// `continuation` is set to `default`
-    /// The `elec_energy_tol` will be divided by 10
-    pub fn param_after_perturb(&self) -> HubbardUParam<Perturbed> {
+    /// The `elec_energy_tol` will be `elec_energy_tol`, or divided by 10 if `None`
+    pub fn param_after_perturb(
+        &self,
+        elec_energy_tol: Option<ElecEnergyTol>,
+    ) -> HubbardUParam<Perturbed> {
+        let init_tol = self.param.elec_energy_tol();
         HubbardUParam {
-            param: ParamFile {
+            param: self.param.with_typed(TypedParam {
                 continuation: Some(Continuation("default".to_string())),
-                elec_energy_tol: ElecEnergyTol {
-                    value: self.param.elec_energy_tol.value / 10.0,
-                    unit: self.param.elec_energy_tol.unit,
-                },
-                ..self.param.clone()
-            },
+                elec_energy_tol: Some(elec_energy_tol.unwrap_or(ElecEnergyTol {
+                    value: init_tol.value / 10.0,
+                    unit: init_tol.unit,
+                })),
+                ..self.param.typed.clone()
+            }),
             stage: PhantomData,
         }
     }
@@ -116,31 +297,134 @@ impl HubbardUParam<BeforePerturb> {
 mod test {
     use std::fs::read_to_string;
 
-    use castep_cell_data::{from_str, param::electronic_minimisation::ElecEnergyTol};
+    use castep_cell_data::param::{basis_set::GridScale, electronic_minimisation::ElecEnergyTol};
+
+    use crate::pipeline::Adjustment;
+
+    use super::{HubbardUParam, ParamFile, ParamOverrides};
 
-    use super::{HubbardUParam, ParamFile};
+    fn default_overrides() -> ParamOverrides {
+        ParamOverrides {
+            elec_energy_tol: ElecEnergyTol {
+                value: 1e-6,
+                unit: None,
+            },
+            cut_off_energy: None,
+            grid_scale: 1.75,
+            fine_grid_scale: None,
+            max_scf_cycles: None,
+        }
+    }
 
     #[test]
     fn test_param() {
         let param_path = "../sh/test/GDY_111_Fe_U.param";
-        let param_file = from_str::<ParamFile>(&read_to_string(param_path).unwrap())
+        let param_file = read_to_string(param_path)
+            .unwrap()
+            .parse::<ParamFile>()
             .map(HubbardUParam::from_param)
             .unwrap();
         dbg!(&param_file);
-        let before_perturb = param_file.param_before_perturb(ElecEnergyTol {
-            value: 1e-6,
-            unit: None,
-        });
-        dbg!(&before_perturb.param.elec_energy_tol);
+        let before_perturb = param_file.param_before_perturb(&default_overrides());
+        dbg!(&before_perturb.param.typed.elec_energy_tol);
         assert_eq!(
-            before_perturb.param.grid_scale,
-            castep_cell_data::param::basis_set::GridScale(1.7500),
+            before_perturb.param.typed.grid_scale,
+            Some(GridScale(1.7500)),
             "We are testing grid_scale {:?} {:?}",
-            before_perturb.param.grid_scale,
-            castep_cell_data::param::basis_set::GridScale(1.7500),
+            before_perturb.param.typed.grid_scale,
+            GridScale(1.7500),
         );
-        let after_perturb = before_perturb.param_after_perturb();
-        dbg!(&after_perturb.param.continuation);
-        dbg!(&after_perturb.param.elec_energy_tol);
+        let after_perturb = before_perturb.param_after_perturb(None);
+        dbg!(&after_perturb.param.typed.continuation);
+        dbg!(&after_perturb.param.typed.elec_energy_tol);
+    }
+
+    #[test]
+    fn param_overrides() {
+        let param_path = "../sh/test/GDY_111_Fe_U.param";
+        let param_file = read_to_string(param_path)
+            .unwrap()
+            .parse::<ParamFile>()
+            .map(HubbardUParam::from_param)
+            .unwrap();
+        let kept = param_file.param_before_perturb(&default_overrides());
+        assert_eq!(
+            kept.param.typed.cut_off_energy,
+            param_file.param.typed.cut_off_energy
+        );
+        assert_eq!(kept.param.max_scf_cycles(), 6000);
+        assert!(kept.param.typed.fine_grid_scale.is_none());
+        let before = param_file.param_before_perturb(&ParamOverrides {
+            cut_off_energy: Some(500.0),
+            grid_scale: 2.0,
+            fine_grid_scale: Some(3.0),
+            max_scf_cycles: Some(200),
+            ..default_overrides()
+        });
+        assert_eq!(before.param.typed.cut_off_energy.unwrap().value, 500.0);
+        assert_eq!(before.param.typed.grid_scale.unwrap().0, 2.0);
+        assert_eq!(
+            before.param.typed.fine_grid_scale.map(|scale| scale.0),
+            Some(3.0)
+        );
+        assert_eq!(before.param.max_scf_cycles(), 200);
+        let divided = before.param_after_perturb(None);
+        assert!((divided.param.elec_energy_tol().value - 1e-7).abs() < 1e-20);
+        let tol = ElecEnergyTol {
+            value: 5e-7,
+            unit: None,
+        };
+        let given = before.param_after_perturb(Some(tol));
+        assert_eq!(given.param.elec_energy_tol().value, 5e-7);
+        assert_eq!(given.param.max_scf_cycles(), 200);
+    }
+
+    #[test]
+    fn recovery_adjustments() {
+        let param = read_to_string("../sh/test/GDY_111_Fe_U.param")
+            .unwrap()
+            .parse::<ParamFile>()
+            .unwrap();
+        assert!(param.uses_density_mixing());
+        assert!(param.uses_pulay_mixing());
+        let adjusted = param.adjusted(&[
+            Adjustment::MoreScfCycles(2),
+            Adjustment::DampMixing(0.5),
+            Adjustment::SwitchToEdft,
+        ]);
+        assert_eq!(adjusted.max_scf_cycles(), 12000);
+        assert_eq!(adjusted.typed.mix_charge_amp.unwrap().0, 0.25);
+        assert!(!adjusted.uses_density_mixing());
+        // Broyden, the `CASTEP` default, switched to Pulay
+        let broyden = "task : SinglePoint\n".parse::<ParamFile>().unwrap();
+        assert!(!broyden.uses_pulay_mixing());
+        let pulay = broyden.adjusted(&[Adjustment::SwitchToPulay]);
+        assert!(pulay.uses_pulay_mixing());
+        assert!(pulay.to_param_file().contains("mixing_scheme"));
+    }
+
+    #[test]
+    fn unknown_keywords_round_trip() {
+        let content = read_to_string("../sh/test/GDY_111_Fe_U.param").unwrap();
+        let param = content.parse::<ParamFile>().unwrap();
+        let written = param.continued().to_param_file();
+        let keys = written
+            .lines()
+            .map(|line| line.split(':').next().unwrap().trim())
+            .collect::<Vec<&str>>();
+        assert_eq!(&keys[..3], ["task", "comment", "xc_functional"]);
+        assert_eq!(keys[6], "page_wvfns");
+        assert_eq!(keys.last(), Some(&"continuation"));
+        // A hand-written seed without most of the keywords
+        let minimal = "task : SinglePoint\ncut_off_energy : 400\nspin_polarized : true\n"
+            .parse::<ParamFile>()
+            .unwrap();
+        assert_eq!(minimal.max_scf_cycles(), 30);
+        assert!(minimal.uses_density_mixing());
+        let before = HubbardUParam::from_param(minimal).param_before_perturb(&default_overrides());
+        let written = before.param.to_param_file();
+        assert!(written.starts_with("task : SinglePoint\ncut_off_energy"));
+        assert!(written.contains("spin_polarized : true\n"));
+        assert!(written.contains("grid_scale"));
     }
 }
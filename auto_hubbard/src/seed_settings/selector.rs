@@ -0,0 +1,198 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Select the Hubbard sites to set U and alpha on, leaving the others as they are in the seed.
+/// Written as `species[:ion_number[:orbital]]`, e.g.: `Fe`, `Fe:1`, `Fe:1:d`.
+/// The species may be a label holding a colon itself, e.g.: `Fe:up:1:d`, so the ion number
+/// and orbital are read from the right; `Fe:1::` selects all ions of the label `Fe:1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HubbardSelector {
+    species: String,
+    ion_number: Option<u32>,
+    orbital: Option<char>,
+}
+
+impl HubbardSelector {
+    pub fn new(species: &str, ion_number: Option<u32>, orbital: Option<char>) -> Self {
+        Self {
+            species: species.to_string(),
+            ion_number,
+            orbital: orbital.map(|o| o.to_ascii_lowercase()),
+        }
+    }
+
+    pub fn species(&self) -> &str {
+        &self.species
+    }
+
+    pub fn ion_number(&self) -> Option<u32> {
+        self.ion_number
+    }
+
+    pub fn orbital(&self) -> Option<char> {
+        self.orbital
+    }
+
+    /// An atom line in `HUBBARD_U`/`HUBBARD_ALPHA` is selected when the species matches,
+    /// and the ion number matches if the selector has one.
+    /// A line without ion number applies to every ion of the species, so it is selected
+    /// by any ion number.
+    pub fn matches_atom(&self, species: &str, ion_number: Option<u32>) -> bool {
+        self.species.eq_ignore_ascii_case(species)
+            && match (self.ion_number, ion_number) {
+                (Some(selected), Some(ion)) => selected == ion,
+                _ => true,
+            }
+    }
+
+    /// `orbital` is one of `s`, `p`, `d`, `f`
+    pub fn matches_orbital(&self, orbital: char) -> bool {
+        self.orbital
+            .is_none_or(|selected| selected == orbital.to_ascii_lowercase())
+    }
+
+    /// The selector in a folder name, without colons: the colon of a species label
+    /// becomes `.` and the separators `-`, e.g.: `Fe-1-d`, `Fe.up-1`
+    pub fn folder_tag(&self) -> String {
+        let mut tag = self.species.replace(':', ".");
+        if let Some(ion) = self.ion_number {
+            tag.push_str(&format!("-{ion}"));
+        }
+        if let Some(orbital) = self.orbital {
+            tag.push_str(&format!("-{orbital}"));
+        }
+        tag
+    }
+}
+
+impl Display for HubbardSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.species)?;
+        // The number of a label such as `Fe:1` would be read back as an ion number
+        let numbered_label = self
+            .species
+            .split_once(':')
+            .is_some_and(|(_, label)| label.parse::<u32>().is_ok());
+        match (self.ion_number, self.orbital) {
+            (Some(ion), Some(orbital)) => write!(f, ":{ion}:{orbital}"),
+            (Some(ion), None) => write!(f, ":{ion}"),
+            (None, Some(orbital)) => write!(f, "::{orbital}"),
+            (None, None) if numbered_label => f.write_str("::"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HubbardSelectorParsingError(String);
+
+impl Display for HubbardSelectorParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid Hubbard site `{}`, expected `species[:ion_number[:orbital]]`, e.g.: Fe:1:d",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for HubbardSelectorParsingError {}
+
+impl FromStr for HubbardSelector {
+    type Err = HubbardSelectorParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || HubbardSelectorParsingError(s.to_string());
+        let parts = s.split(':').map(str::trim).collect::<Vec<&str>>();
+        let is_ion = |part: &str| part.is_empty() || part.parse::<u32>().is_ok();
+        let is_orbital =
+            |part: &str| matches!(part, "" | "s" | "p" | "d" | "f" | "S" | "P" | "D" | "F");
+        // Read `orbital` and `ion_number` from the right, the rest is the species label
+        let (species, ion, orbital) = match parts.as_slice() {
+            [species @ .., ion, orbital]
+                if !species.is_empty() && is_ion(ion) && is_orbital(orbital) =>
+            {
+                (species.to_vec(), *ion, *orbital)
+            }
+            [species @ .., ion] if !species.is_empty() && is_ion(ion) => {
+                (species.to_vec(), *ion, "")
+            }
+            species => (species.to_vec(), "", ""),
+        };
+        // A label holds at most one colon, e.g.: `Fe:up`
+        if species.len() > 2 || species.iter().any(|part| part.is_empty()) {
+            return Err(error());
+        }
+        let ion_number = Some(ion)
+            .filter(|ion| !ion.is_empty())
+            .map(|ion| ion.parse::<u32>().map_err(|_| error()))
+            .transpose()?;
+        Ok(Self::new(
+            &species.join(":"),
+            ion_number,
+            orbital.chars().next(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HubbardSelector;
+
+    #[test]
+    fn parse_selector() {
+        let selector: HubbardSelector = "Fe:1:D".parse().unwrap();
+        assert_eq!(selector, HubbardSelector::new("Fe", Some(1), Some('d')));
+        assert_eq!(selector.to_string(), "Fe:1:d");
+        assert!(selector.matches_atom("Fe", Some(1)));
+        assert!(selector.matches_atom("Fe", None));
+        assert!(!selector.matches_atom("Fe", Some(2)));
+        assert!(!selector.matches_atom("Ni", Some(1)));
+        assert!(selector.matches_orbital('d'));
+        assert!(!selector.matches_orbital('p'));
+        let species_only: HubbardSelector = "Ni".parse().unwrap();
+        assert!(species_only.matches_atom("Ni", Some(3)));
+        assert!(species_only.matches_orbital('p'));
+        assert!("Fe:1:g".parse::<HubbardSelector>().is_err());
+        assert!("Fe:x:y".parse::<HubbardSelector>().is_err());
+        assert!(":1".parse::<HubbardSelector>().is_err());
+        assert_eq!(selector.folder_tag(), "Fe-1-d");
+    }
+
+    #[test]
+    fn labelled_selector() {
+        let selector: HubbardSelector = "Fe:up:1:d".parse().unwrap();
+        assert_eq!(selector, HubbardSelector::new("Fe:up", Some(1), Some('d')));
+        assert!(selector.matches_atom("Fe:up", Some(1)));
+        assert!(!selector.matches_atom("Fe", Some(1)));
+        assert_eq!(selector.folder_tag(), "Fe.up-1-d");
+        let label: HubbardSelector = "Fe:up".parse().unwrap();
+        assert_eq!(label, HubbardSelector::new("Fe:up", None, None));
+        assert_eq!(
+            "Fe::d".parse::<HubbardSelector>().unwrap(),
+            HubbardSelector::new("Fe", None, Some('d'))
+        );
+        // A numbered label needs the empty ion number and orbital
+        assert_eq!(
+            "Fe:1".parse::<HubbardSelector>().unwrap(),
+            HubbardSelector::new("Fe", Some(1), None)
+        );
+        let numbered = HubbardSelector::new("Fe:1", None, None);
+        assert_eq!("Fe:1::".parse::<HubbardSelector>().unwrap(), numbered);
+        [
+            selector,
+            label,
+            numbered,
+            HubbardSelector::new("Fe", None, Some('d')),
+            HubbardSelector::new("Fe:1", Some(2), None),
+        ]
+        .into_iter()
+        .for_each(|selector| {
+            assert_eq!(
+                selector.to_string().parse::<HubbardSelector>().unwrap(),
+                selector
+            )
+        });
+    }
+}
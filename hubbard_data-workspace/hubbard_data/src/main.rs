@@ -4,9 +4,10 @@ use std::{
 };
 
 use hubbard_data_analyze::{
-    Alpha, CsvWriter, HubbardUPlot, JobType, LazyFrame, Pipeline, SerWriter, TotalView, U,
+    Alpha, CsvWriter, DataFrame, HubbardUPlot, JobType, LazyFrame, Pipeline, ResponseMatrixView,
+    ScfUView, SerWriter, TotalView, U,
 };
-use hubbard_data_args::{HubbardDataCli, Parser};
+use hubbard_data_args::{HubbardDataCli, Parser, Unconverged};
 use hubbard_data_plot::PlotHub;
 
 /// Can run for both U and Alpha results or U results only.
@@ -18,9 +19,11 @@ fn main() -> Result<(), anyhow::Error> {
         hubbard_data_args::Mode::U => {
             let dest_dir = cli.result_folder().join(format!("plot_{}", U::job_type()));
             create_dir_all(&dest_dir).ok();
-            let df = <U>::csv_path(cli.result_folder())
-                .process_data(cli.perturb_value().try_into_single()?)?;
+            let perturb_val = cli.perturb_value()?.try_into_single()?;
+            let df = total_view::<U>(cli, perturb_val)?;
             write_channel_total_view(&df, &dest_dir)?;
+            write_channel_fit_views(&df, &dest_dir)?;
+            write_response_view::<U>(cli, perturb_val, &dest_dir)?;
             df.channels()
                 .into_iter()
                 .map(|i| (i, df.to_channel_view(i).to_mean_view()))
@@ -30,6 +33,7 @@ fn main() -> Result<(), anyhow::Error> {
                     let file =
                         File::create(dest_dir.join(format!("channel_{}_mean_U.csv", channel_id)))?;
                     CsvWriter::new(file).finish(df_mean.data_mut())?;
+                    write_scf_view(df_mean.to_scf_view()?, channel_id, &dest_dir)?;
                     let (xs, ys) = (df_mean.xs(), df_mean.ys());
                     let ploter = PlotHub::new(&xs, &ys, channel_id, &dest_dir);
                     ploter.plot_channel_mean()?;
@@ -42,9 +46,11 @@ fn main() -> Result<(), anyhow::Error> {
                 .result_folder()
                 .join(format!("plot_{}", Alpha::job_type()));
             create_dir_all(&dest_dir).ok();
-            let df = <Alpha>::csv_path(cli.result_folder())
-                .process_data(cli.perturb_value().try_into_single()?)?;
+            let perturb_val = cli.perturb_value()?.try_into_single()?;
+            let df = total_view::<Alpha>(cli, perturb_val)?;
             write_channel_total_view(&df, &dest_dir)?;
+            write_channel_fit_views(&df, &dest_dir)?;
+            write_response_view::<Alpha>(cli, perturb_val, &dest_dir)?;
             df.channels()
                 .into_iter()
                 .map(|i| (i, df.to_channel_view(i).to_mean_view()))
@@ -55,6 +61,7 @@ fn main() -> Result<(), anyhow::Error> {
                         dest_dir.join(format!("channel_{}_mean_Alpha.csv", channel_id)),
                     )?;
                     CsvWriter::new(file).finish(df_mean.data_mut())?;
+                    write_scf_view(df_mean.to_scf_view()?, channel_id, &dest_dir)?;
                     let (xs, ys) = (df_mean.xs(), df_mean.ys());
                     let ploter = PlotHub::new(&xs, &ys, channel_id, &dest_dir);
                     ploter.plot_channel_mean()?;
@@ -68,10 +75,15 @@ fn main() -> Result<(), anyhow::Error> {
 /// Main function for analyzing both csv together.
 fn analyze_both(cli: &HubbardDataCli) -> Result<(), anyhow::Error> {
     let src_dir = cli.result_folder();
-    let df_u = U::csv_path(src_dir).process_data(cli.u_perturb_val())?;
-    let df_alpha = Alpha::csv_path(src_dir).process_data(cli.alpha_perturb_val())?;
+    let (u_perturb_val, alpha_perturb_val) = cli.perturb_value()?.try_into_both()?;
+    let df_u = total_view::<U>(cli, u_perturb_val)?;
+    let df_alpha = total_view::<Alpha>(cli, alpha_perturb_val)?;
     let dest_dir = src_dir.join("plot");
     create_dir_all(&dest_dir).ok();
+    write_channel_fit_views(&df_u, &dest_dir)?;
+    write_channel_fit_views(&df_alpha, &dest_dir)?;
+    write_response_view::<U>(cli, u_perturb_val, &dest_dir)?;
+    write_response_view::<Alpha>(cli, alpha_perturb_val, &dest_dir)?;
     let channels_u = df_u.channels();
     let channels_alpha = df_alpha.channels();
     channels_u
@@ -90,6 +102,7 @@ fn analyze_both(cli: &HubbardDataCli) -> Result<(), anyhow::Error> {
             let mut concat_mean = concat_mean?;
             let file = File::create(dest_dir.join(format!("channel_{}_mean.csv", c_u)))?;
             CsvWriter::new(file).finish(concat_mean.data_mut())?;
+            write_scf_view(concat_mean.to_scf_view()?, c_u, &dest_dir)?;
             let (xs, ys) = (concat_mean.xs(), concat_mean.ys());
             let ploter = PlotHub::new(&xs, &ys, c_u, &dest_dir);
             ploter.plot_channel_mean()?;
@@ -98,6 +111,35 @@ fn analyze_both(cli: &HubbardDataCli) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Process the result csv of `T`, listing the unconverged jobs on stderr unless asked to keep
+/// them silently, and leaving them out if asked
+fn total_view<T: JobType>(
+    cli: &HubbardDataCli,
+    perturb_val: f64,
+) -> Result<Pipeline<T, TotalView<T>, LazyFrame>, anyhow::Error> {
+    let df = T::csv_path(cli.result_folder()).process_data(perturb_val)?;
+    let policy = cli.unconverged();
+    if policy == Unconverged::Keep {
+        return Ok(df);
+    }
+    let unconverged = df.unconverged_points()?;
+    if unconverged.height() > 0 {
+        let action = match policy {
+            Unconverged::Exclude => "excluded",
+            _ => "kept",
+        };
+        eprintln!(
+            "Warning: {} perturbation points of {} jobs did not converge ({action}):\n{unconverged}",
+            unconverged.height(),
+            T::job_type()
+        );
+    }
+    Ok(match policy {
+        Unconverged::Exclude => df.exclude_unconverged(),
+        _ => df,
+    })
+}
+
 fn write_channel_total_view<T: JobType>(
     df: &Pipeline<T, TotalView<T>, LazyFrame>,
     dest_dir: &Path,
@@ -115,3 +157,54 @@ fn write_channel_total_view<T: JobType>(
         })?;
     Ok(())
 }
+
+/// Write the self-consistent U extrapolated from the U ladder to `channel_[id]_u_scf_[jobtype].csv`
+fn write_scf_view<T: JobType>(
+    mut scf: Pipeline<T, ScfUView<T>, DataFrame>,
+    channel_id: u32,
+    dest_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    println!("Channel {channel_id} U_scf:\n{}", scf.data());
+    let file = File::create(dest_dir.join(format!(
+        "channel_{}_u_scf_{}.csv",
+        channel_id,
+        T::job_type()
+    )))?;
+    CsvWriter::new(file).finish(scf.data_mut())?;
+    Ok(())
+}
+
+/// Write the linear-regression fits of each channel to `channel_[id]_fit_[jobtype].csv`
+fn write_channel_fit_views<T: JobType>(
+    df: &Pipeline<T, TotalView<T>, LazyFrame>,
+    dest_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    df.channels().into_iter().try_for_each(|i| {
+        let mut fit = df.to_fit_view(i)?;
+        let file = File::create(dest_dir.join(format!("channel_{}_fit_{}.csv", i, T::job_type())))?;
+        CsvWriter::new(file).finish(fit.data_mut())?;
+        Ok::<(), anyhow::Error>(())
+    })
+}
+
+/// Write the per-site `U` solved from the response matrices to `response_[jobtype].csv`,
+/// with the runs given by `--combine` for the off-diagonal elements
+fn write_response_view<T: JobType>(
+    cli: &HubbardDataCli,
+    perturb_val: f64,
+    dest_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    let runs = std::iter::once(cli.result_folder())
+        .chain(cli.combine())
+        .map(T::csv_path)
+        .collect::<Vec<_>>();
+    let matrices = Pipeline::combine_response(
+        &runs,
+        perturb_val,
+        cli.unconverged() == Unconverged::Exclude,
+    )?;
+    let mut view = Pipeline::<T, ResponseMatrixView<T>, DataFrame>::from_matrices(&matrices)?;
+    let file = File::create(dest_dir.join(format!("response_{}.csv", T::job_type())))?;
+    CsvWriter::new(file).finish(view.data_mut())?;
+    Ok(())
+}
@@ -10,7 +10,9 @@ use crate::job_type::{Alpha, JobType, U};
 
 use super::{Pipeline, ViewColumn, ViewType};
 
+pub mod fit_view;
 pub mod mean_view;
+pub use fit_view::ChannelFitView;
 pub use mean_view::ChannelMeanView;
 
 /// The processed channel view will have the following columns:
@@ -40,13 +42,23 @@ impl ViewColumn<Alpha> for ChannelView<Alpha> {
 }
 
 impl<T: JobType> Pipeline<T, ChannelView<T>, LazyFrame> {
-    /// Aggregate the delta slope to mean value.
+    /// Aggregate the delta slope to mean value,
+    /// along with the number of perturbation points it is averaged over.
     pub fn to_mean_view(self) -> Result<Pipeline<T, ChannelMeanView<T>, DataFrame>, PolarsError> {
         Ok(Pipeline::new(
             self.data
                 .group_by_stable([col("U")])
-                .agg([col(T::delta_slope_col_alias()).mean()])
-                .select([col("U"), col(T::delta_slope_col_alias())])
+                .agg([
+                    col(T::delta_slope_col_alias()).mean(),
+                    col(T::delta_slope_col_alias())
+                        .count()
+                        .alias(T::points_col_alias()),
+                ])
+                .select([
+                    col("U"),
+                    col(T::delta_slope_col_alias()),
+                    col(T::points_col_alias()),
+                ])
                 .collect()?,
         ))
     }
@@ -0,0 +1,77 @@
+use std::marker::PhantomData;
+
+use polars::{
+    error::PolarsError,
+    frame::DataFrame,
+    prelude::{DataType, Expr, LazyFrame, col, lit},
+};
+
+use crate::{
+    JobType,
+    analysis::{Pipeline, ViewType, total_view::TotalView},
+};
+
+#[derive(Debug, Clone)]
+/// Least-squares fit of the occupation response `ΔN` against the perturbation
+/// over all perturbation steps, at each U value for one channel.
+/// The dataframe has the following columns:
+/// ["Channel ID", "U",
+/// "slope_first", "intercept_first", "r2_first", "stderr_first",
+/// "slope_final", "intercept_final", "r2_final", "stderr_final",
+/// "n1-nF_fit", "points"]
+/// where `_first` fits `S1-S0` (bare response) and `_final` fits `SF-S0` (converged response),
+/// `n1-nF_fit = 1 / slope_first - 1 / slope_final`,
+/// and `points` is the number of perturbation steps in the fit.
+pub struct ChannelFitView<T: JobType>(PhantomData<T>);
+
+impl<T: JobType> ViewType<T> for ChannelFitView<T> {}
+
+/// Aggregations of the simple linear regression `y = slope * x + intercept` in each group.
+/// `stderr` is the standard error of the slope.
+pub(crate) fn regression_exprs(x: &str, y: &str, suffix: &str) -> [Expr; 4] {
+    let dx = || col(x) - col(x).mean();
+    let dy = || col(y) - col(y).mean();
+    let sxx = || (dx() * dx()).sum();
+    let syy = || (dy() * dy()).sum();
+    let sxy = || (dx() * dy()).sum();
+    let slope = || sxy() / sxx();
+    let n = col(y).count().cast(DataType::Float64);
+    [
+        slope().alias(format!("slope_{suffix}")),
+        (col(y).mean() - slope() * col(x).mean()).alias(format!("intercept_{suffix}")),
+        (sxy() * sxy() / (sxx() * syy())).alias(format!("r2_{suffix}")),
+        ((syy() - slope() * sxy()) / (n - lit(2.0)) / sxx())
+            .sqrt()
+            .alias(format!("stderr_{suffix}")),
+    ]
+}
+
+impl<T: JobType> Pipeline<T, TotalView<T>, LazyFrame> {
+    /// Fit the bare and converged responses of one channel at each U value.
+    pub fn to_fit_view(
+        &self,
+        channel_id: u32,
+    ) -> Result<Pipeline<T, ChannelFitView<T>, DataFrame>, PolarsError> {
+        let perturbation = T::nth_perturb_col_alias();
+        let regressions = regression_exprs(&perturbation, "S1-S0", "first")
+            .into_iter()
+            .chain(regression_exprs(&perturbation, "SF-S0", "final"))
+            .chain([col("SF-S0").count().alias("points")])
+            .collect::<Vec<Expr>>();
+        Ok(Pipeline::new(
+            self.data
+                .clone()
+                .filter(col("Channel ID").eq(lit(channel_id)))
+                // Responses of excluded jobs are null
+                .filter(col("SF-S0").is_not_null())
+                .with_column(col(&perturbation).cast(DataType::Float64))
+                .group_by_stable([col("Channel ID"), col("U")])
+                .agg(regressions)
+                .with_column(
+                    (lit(1.0) / col("slope_first") - lit(1.0) / col("slope_final"))
+                        .alias("n1-nF_fit"),
+                )
+                .collect()?,
+        ))
+    }
+}
@@ -9,18 +9,22 @@ use crate::{
 
 #[derive(Debug, Clone)]
 /// A dataframe that computes the mean values of the U/Alpha perturbation responses at each U value
-/// for each channel
+/// for each channel, and the number of points behind each mean:
+/// ["U", "n1-nF_U | n1-nF_Alpha", "points_U | points_Alpha"]
 pub struct ChannelMeanView<T: JobType>(PhantomData<T>);
 
 impl ViewColumn<U> for ChannelMeanView<U> {
     fn column_names() -> Vec<String> {
-        vec!["U", "n1-nF_U"].into_iter().map(String::from).collect()
+        vec!["U", "n1-nF_U", "points_U"]
+            .into_iter()
+            .map(String::from)
+            .collect()
     }
 }
 
 impl ViewColumn<Alpha> for ChannelMeanView<Alpha> {
     fn column_names() -> Vec<String> {
-        vec!["U", "n1-nF_Alpha"]
+        vec!["U", "n1-nF_Alpha", "points_Alpha"]
             .into_iter()
             .map(String::from)
             .collect()
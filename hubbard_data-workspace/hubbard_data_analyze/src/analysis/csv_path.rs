@@ -26,13 +26,20 @@ impl<T: JobType> Pipeline<T, CSVPath<T>, PathBuf> {
     /// And calculate the perturbation response at different perturbation values:
     /// `n1-nF = perturb_val * perturb_times / (s1-s0) - perturb_val * perturb_times / (sf-s0)`
     /// The processed total view will have the followig columns:
-    /// [ "Channel ID", "U", "S1-S0", "SF-S0", "u/S1-S0 | alpha/s1-S0", "u/SF-S0 | alpha/SF-S0", "u_pert | alpha_pert", "n1-nF"]
+    /// [ "Channel ID", "U", "S1-S0", "SF-S0", "u/S1-S0 | alpha/s1-S0", "u/SF-S0 | alpha/SF-S0", "u_pert | alpha_pert", "n1-nF", "Converged"]
+    /// where "Converged" is true when the SCF of the job converged for both spins.
     pub fn process_data(
         &self,
         perturb_val: f64,
     ) -> Result<Pipeline<T, TotalView<T>, LazyFrame>, PolarsError> {
-        let [jobname, channel_id, scf_0, scf_1, scf_last] =
-            ["Jobname", "Channel ID", "Before SCF", "1st SCF", "Last SCF"];
+        let [jobname, channel_id, scf_0, scf_1, scf_last, converged] = [
+            "Jobname",
+            "Channel ID",
+            "Before SCF",
+            "1st SCF",
+            "Last SCF",
+            "Converged",
+        ];
         let dataframe = LazyCsvReader::new(&self.data)
             .with_has_header(true)
             .finish()?
@@ -42,6 +49,7 @@ impl<T: JobType> Pipeline<T, CSVPath<T>, PathBuf> {
                 col(scf_0).cast(DataType::Float64),
                 col(scf_1).cast(DataType::Float64),
                 col(scf_last).cast(DataType::Float64),
+                col(converged).cast(DataType::Boolean),
             ])
             .filter(
                 // Remove non-perturbed entries, e.g.: ./U_0_u/ZnO_LR
@@ -55,9 +63,10 @@ impl<T: JobType> Pipeline<T, CSVPath<T>, PathBuf> {
             .group_by_stable([col(jobname), col(channel_id)])
             // Aggregate the `LazyGroupBy`
             .agg([
-                col("Before SCF").sum().alias("S0"),
-                col("1st SCF").sum().alias("S1"),
-                col("Last SCF").sum().alias("SF"),
+                col(scf_0).sum().alias("S0"),
+                col(scf_1).sum().alias("S1"),
+                col(scf_last).sum().alias("SF"),
+                col(converged).all(false),
             ])
             .select([
                 col(jobname),
@@ -73,6 +82,7 @@ impl<T: JobType> Pipeline<T, CSVPath<T>, PathBuf> {
                 T::perturb_expr(),
                 sum_horizontal([col("S1"), col("S0") * lit(-1)], false)?.alias("S1-S0"),
                 sum_horizontal([col("SF"), col("S0") * lit(-1)], false)?.alias("SF-S0"),
+                col(converged),
             ])
             // "u_pert"/"alpha_pert"
             .with_columns(T::slope_expr(perturb_val))
@@ -97,6 +107,7 @@ impl<T: JobType> Pipeline<T, CSVPath<T>, PathBuf> {
                 col(T::slope_final_col_alias()),
                 col(T::nth_perturb_col_alias()) * lit(perturb_val),
                 col("n1-nF"),
+                col(converged),
             ]);
         Ok(Pipeline::new(dataframe))
     }
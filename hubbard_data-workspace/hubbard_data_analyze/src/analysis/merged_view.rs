@@ -63,19 +63,20 @@ impl Pipeline<U, ChannelView<U>, LazyFrame> {
 impl Pipeline<U, ChannelMergedView, LazyFrame> {
     /// The finish line (or, currently) of our pipeline.
     /// Produces a channel_{id}_mean dataframe:
-    ///┌─────┬────────────┬─────────────┐
-    ///│ U   ┆ n1-nF_U    ┆ n1-nF_Alpha │
-    ///│ --- ┆ ---        ┆ ---         │
-    ///│ i32 ┆ f64        ┆ f64         │
-    ///╞═════╪════════════╪═════════════╡
-    ///│ 0   ┆ 7.4593e-18 ┆ 31.308104   │
-    ///│ 2   ┆ 1.253893   ┆ 8.065987    │
-    ///│ 4   ┆ 2.824379   ┆ 2.05173     │
-    ///│ 6   ┆ 4.357392   ┆ 1.033147    │
-    ///│ 8   ┆ 4.234919   ┆ 8.160309    │
-    ///│ 10  ┆ 11.621275  ┆ 13.932687   │
-    ///│ 12  ┆ 6.091781   ┆ -2.91931    │
-    ///└─────┴────────────┴─────────────┘
+    ///┌─────┬────────────┬─────────────┬──────────┬──────────────┐
+    ///│ U   ┆ n1-nF_U    ┆ n1-nF_Alpha ┆ points_U ┆ points_Alpha │
+    ///│ --- ┆ ---        ┆ ---         ┆ ---      ┆ ---          │
+    ///│ i32 ┆ f64        ┆ f64         ┆ u32      ┆ u32          │
+    ///╞═════╪════════════╪═════════════╪══════════╪══════════════╡
+    ///│ 0   ┆ 7.4593e-18 ┆ 31.308104   ┆ 5        ┆ 5            │
+    ///│ 2   ┆ 1.253893   ┆ 8.065987    ┆ 5        ┆ 5            │
+    ///│ 4   ┆ 2.824379   ┆ 2.05173     ┆ 5        ┆ 5            │
+    ///│ 6   ┆ 4.357392   ┆ 1.033147    ┆ 5        ┆ 5            │
+    ///│ 8   ┆ 4.234919   ┆ 8.160309    ┆ 5        ┆ 5            │
+    ///│ 10  ┆ 11.621275  ┆ 13.932687   ┆ 5        ┆ 5            │
+    ///│ 12  ┆ 6.091781   ┆ -2.91931    ┆ 5        ┆ 5            │
+    ///└─────┴────────────┴─────────────┴──────────┴──────────────┘
+    /// where `points_*` counts the perturbation points averaged in each mean.
     pub fn view_mean(self) -> Result<Pipeline<U, ChannelMergedMeanView, DataFrame>, PolarsError> {
         Ok(Pipeline::new(
             self.data
@@ -83,11 +84,19 @@ impl Pipeline<U, ChannelMergedView, LazyFrame> {
                 .agg([
                     col(U::delta_slope_col_alias()).mean(),
                     col(Alpha::delta_slope_col_alias()).mean(),
+                    col(U::delta_slope_col_alias())
+                        .count()
+                        .alias(U::points_col_alias()),
+                    col(Alpha::delta_slope_col_alias())
+                        .count()
+                        .alias(Alpha::points_col_alias()),
                 ])
                 .select([
                     col("U"),
                     col(U::delta_slope_col_alias()),
                     col(Alpha::delta_slope_col_alias()),
+                    col(U::points_col_alias()),
+                    col(Alpha::points_col_alias()),
                 ])
                 .collect()?,
         ))
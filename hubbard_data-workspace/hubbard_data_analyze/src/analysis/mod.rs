@@ -5,6 +5,8 @@ use crate::JobType;
 pub mod channel_view;
 pub mod csv_path;
 pub mod merged_view;
+pub mod response_view;
+pub mod scf_view;
 pub mod total_view;
 
 /// A trait to represent the type indicates the current view type
@@ -0,0 +1,312 @@
+use std::{collections::BTreeSet, marker::PhantomData, path::PathBuf};
+
+use polars::{
+    error::{PolarsError, polars_err},
+    frame::DataFrame,
+    prelude::{
+        DataType, IntoColumn, LazyCsvReader, LazyFileListReader, LazyFrame, NamedFrom, Series,
+        UnionArgs, col, concat, lit,
+    },
+};
+
+use crate::JobType;
+
+use super::{Pipeline, ViewType, csv_path::CSVPath};
+
+/// Csv column recording the channel whose alpha was shifted in the job, written by
+/// `auto_hubbard` from its `--site`. 0 or without the column, every channel was perturbed at once.
+pub const PERTURBED_CHANNEL_COL: &str = "Perturbed Channel";
+
+#[derive(Debug, Clone, Copy)]
+/// Per-site view of the linear response, solving the inter-site response matrices:
+/// `U = chi_0^-1 - chi^-1`
+/// The dataframe has the following columns:
+/// ["U", "Channel ID", "chi0", "chi", "U_site"]
+/// where "chi0" and "chi" are the diagonal elements of the bare and converged response matrices.
+pub struct ResponseMatrixView<T: JobType>(PhantomData<T>);
+
+impl<T: JobType> ViewType<T> for ResponseMatrixView<T> {}
+
+/// The bare (`chi0`) and converged (`chi`) response matrices at one input `U`.
+/// Element `[i][j]` is the response of `channels[i]` to the perturbation on `channels[j]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseMatrices {
+    /// Input `U`
+    pub u: i32,
+    /// Channel IDs, in the order of matrix rows and columns
+    pub channels: Vec<u32>,
+    /// Bare response, from the 1st SCF
+    pub chi0: Vec<Vec<f64>>,
+    /// Converged response, from the last SCF
+    pub chi: Vec<Vec<f64>>,
+}
+
+impl ResponseMatrices {
+    /// `U_I = (chi_0^-1 - chi^-1)_II`, `None` if any matrix is singular.
+    pub fn site_u(&self) -> Option<Vec<f64>> {
+        let chi0_inv = invert(&self.chi0)?;
+        let chi_inv = invert(&self.chi)?;
+        Some(
+            (0..self.channels.len())
+                .map(|i| chi0_inv[i][i] - chi_inv[i][i])
+                .collect(),
+        )
+    }
+}
+
+impl<T: JobType> Pipeline<T, CSVPath<T>, PathBuf> {
+    /// Build the response matrices of every input `U` from the per-channel data.
+    /// The occupations of both spins are summed, then each element is the mean of
+    /// `ΔN_I / Δalpha_J` over all perturbation steps, leaving out the steps whose SCF
+    /// did not converge when `exclude_unconverged`.
+    pub fn process_response(
+        &self,
+        perturb_val: f64,
+        exclude_unconverged: bool,
+    ) -> Result<Vec<ResponseMatrices>, PolarsError> {
+        Self::combine_response(std::slice::from_ref(self), perturb_val, exclude_unconverged)
+    }
+
+    /// Build the response matrices from the result csvs of several runs, e.g. one
+    /// `auto_hubbard calc --site` run per Hubbard site: each run gives the column of the
+    /// channel it perturbed, so together they give the off-diagonal elements.
+    pub fn combine_response(
+        runs: &[Self],
+        perturb_val: f64,
+        exclude_unconverged: bool,
+    ) -> Result<Vec<ResponseMatrices>, PolarsError> {
+        let frames = runs
+            .iter()
+            .enumerate()
+            .map(|(run, csv)| {
+                let frame = csv.response_frame(run as u32, perturb_val)?;
+                Ok(match exclude_unconverged {
+                    true => frame.filter(col("Converged")),
+                    false => frame,
+                })
+            })
+            .collect::<Result<Vec<LazyFrame>, PolarsError>>()?;
+        let data = concat(frames, UnionArgs::default())?
+            .group_by_stable([col("U"), col("Channel ID"), col(PERTURBED_CHANNEL_COL)])
+            .agg([col("chi0").mean(), col("chi").mean()])
+            .collect()?;
+        response_matrices(&data)
+    }
+
+    /// `ΔN_I / Δalpha_J` of each perturbed job in the csv, with columns
+    /// ["U", "Channel ID", "Perturbed Channel", "chi0", "chi", "Converged"]
+    /// where "Converged" is true when the SCF of the job converged for both spins.
+    fn response_frame(&self, run: u32, perturb_val: f64) -> Result<LazyFrame, PolarsError> {
+        let frame = LazyCsvReader::new(&self.data)
+            .with_has_header(true)
+            .finish()?;
+        let has_perturbed_channel = frame
+            .clone()
+            .collect_schema()?
+            .contains(PERTURBED_CHANNEL_COL);
+        let perturbed_channel = if has_perturbed_channel {
+            col(PERTURBED_CHANNEL_COL).cast(DataType::UInt32)
+        } else {
+            // 0 stands for "all channels"
+            lit(0).cast(DataType::UInt32).alias(PERTURBED_CHANNEL_COL)
+        };
+        Ok(frame
+            .select([
+                col("Jobname"),
+                col("Channel ID").cast(DataType::UInt32),
+                perturbed_channel,
+                col("Before SCF").cast(DataType::Float64),
+                col("1st SCF").cast(DataType::Float64),
+                col("Last SCF").cast(DataType::Float64),
+                col("Converged").cast(DataType::Boolean),
+            ])
+            // Keep perturbed jobs only
+            .filter(
+                col("Jobname")
+                    .str()
+                    .count_matches(lit("/"), true)
+                    .eq(lit(3)),
+            )
+            // The runs share their job names
+            .with_column(lit(run).alias("Run"))
+            .group_by_stable([
+                col("Run"),
+                col("Jobname"),
+                col("Channel ID"),
+                col(PERTURBED_CHANNEL_COL),
+            ])
+            .agg([
+                col("Before SCF").sum().alias("S0"),
+                col("1st SCF").sum().alias("S1"),
+                col("Last SCF").sum().alias("SF"),
+                col("Converged").all(false),
+            ])
+            .select([
+                col("Jobname")
+                    .str()
+                    .extract(lit(r"U_(\d+)"), 1)
+                    .cast(DataType::Int32)
+                    .alias("U"),
+                col("Channel ID"),
+                col(PERTURBED_CHANNEL_COL),
+                T::perturb_expr(),
+                (col("S1") - col("S0")).alias("S1-S0"),
+                (col("SF") - col("S0")).alias("SF-S0"),
+                col("Converged"),
+            ])
+            .with_column(T::perturbation_expr(perturb_val).alias("dalpha"))
+            .select([
+                col("U"),
+                col("Channel ID"),
+                col(PERTURBED_CHANNEL_COL),
+                (col("S1-S0") / col("dalpha")).alias("chi0"),
+                (col("SF-S0") / col("dalpha")).alias("chi"),
+                col("Converged"),
+            ]))
+    }
+}
+
+/// Assemble the matrices from the frame with columns
+/// ["U", "Channel ID", "Perturbed Channel", "chi0", "chi"]
+/// The matrices run over the perturbed channels, the responses of the other channels are left out.
+/// Without any perturbed channel recorded, every channel was perturbed at once:
+/// only the diagonal is known.
+fn response_matrices(data: &DataFrame) -> Result<Vec<ResponseMatrices>, PolarsError> {
+    let us = data.column("U")?.i32()?;
+    let channel_ids = data.column("Channel ID")?.u32()?;
+    let perturbed = data.column(PERTURBED_CHANNEL_COL)?.u32()?;
+    let chi0s = data.column("chi0")?.f64()?;
+    let chis = data.column("chi")?.f64()?;
+    let rows = (0..data.height())
+        .map(|i| {
+            Some((
+                us.get(i)?,
+                channel_ids.get(i)?,
+                perturbed.get(i)?,
+                chi0s.get(i)?,
+                chis.get(i)?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| polars_err!(ComputeError: "null values in the response data"))?;
+    let u_values = rows.iter().map(|row| row.0).collect::<BTreeSet<i32>>();
+    Ok(u_values
+        .into_iter()
+        .map(|u| {
+            let rows_at_u = rows.iter().filter(|row| row.0 == u).collect::<Vec<_>>();
+            let perturbed_channels = rows_at_u
+                .iter()
+                .map(|row| row.2)
+                .filter(|&channel| channel != 0)
+                .collect::<BTreeSet<u32>>();
+            let channels = match perturbed_channels.is_empty() {
+                true => rows_at_u.iter().map(|row| row.1).collect::<BTreeSet<u32>>(),
+                false => perturbed_channels,
+            }
+            .into_iter()
+            .collect::<Vec<u32>>();
+            let n = channels.len();
+            let (mut chi0, mut chi) = (vec![vec![0.0; n]; n], vec![vec![0.0; n]; n]);
+            rows_at_u
+                .iter()
+                .for_each(|&&(_, channel, perturbed, c0, c)| {
+                    let Some(i) = channels.iter().position(|&id| id == channel) else {
+                        return;
+                    };
+                    // Simultaneous perturbation only gives the diagonal approximation
+                    let j = channels.iter().position(|&id| id == perturbed).unwrap_or(i);
+                    chi0[i][j] = c0;
+                    chi[i][j] = c;
+                });
+            ResponseMatrices {
+                u,
+                channels,
+                chi0,
+                chi,
+            }
+        })
+        .collect())
+}
+
+impl<T: JobType> Pipeline<T, ResponseMatrixView<T>, DataFrame> {
+    /// Report the diagonal response and the resulting `U` of each channel.
+    pub fn from_matrices(matrices: &[ResponseMatrices]) -> Result<Self, PolarsError> {
+        let rows = matrices
+            .iter()
+            .flat_map(|m| {
+                let site_u = m
+                    .site_u()
+                    .unwrap_or_else(|| vec![f64::NAN; m.channels.len()]);
+                m.channels
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, &channel)| (m.u, channel, m.chi0[i][i], m.chi[i][i], site_u[i]))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let df = DataFrame::new(vec![
+            Series::new("U".into(), rows.iter().map(|r| r.0).collect::<Vec<i32>>()).into_column(),
+            Series::new(
+                "Channel ID".into(),
+                rows.iter().map(|r| r.1).collect::<Vec<u32>>(),
+            )
+            .into_column(),
+            Series::new(
+                "chi0".into(),
+                rows.iter().map(|r| r.2).collect::<Vec<f64>>(),
+            )
+            .into_column(),
+            Series::new("chi".into(), rows.iter().map(|r| r.3).collect::<Vec<f64>>()).into_column(),
+            Series::new(
+                "U_site".into(),
+                rows.iter().map(|r| r.4).collect::<Vec<f64>>(),
+            )
+            .into_column(),
+        ])?;
+        Ok(Pipeline::new(df))
+    }
+}
+
+/// Gauss-Jordan elimination with partial pivoting.
+fn invert(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut a = matrix.to_vec();
+    let mut inv = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect::<Vec<Vec<f64>>>();
+    for k in 0..n {
+        let pivot = (k..n).max_by(|&x, &y| a[x][k].abs().total_cmp(&a[y][k].abs()))?;
+        if a[pivot][k].abs() < f64::EPSILON {
+            return None;
+        }
+        a.swap(k, pivot);
+        inv.swap(k, pivot);
+        let p = a[k][k];
+        a[k].iter_mut().for_each(|v| *v /= p);
+        inv[k].iter_mut().for_each(|v| *v /= p);
+        for r in (0..n).filter(|&r| r != k) {
+            let factor = a[r][k];
+            for c in 0..n {
+                a[r][c] -= factor * a[k][c];
+                inv[r][c] -= factor * inv[k][c];
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::invert;
+
+    #[test]
+    fn invert_2x2() {
+        let inv = invert(&[vec![4.0, 7.0], vec![2.0, 6.0]]).unwrap();
+        let expected = [[0.6, -0.7], [-0.2, 0.4]];
+        inv.iter()
+            .flatten()
+            .zip(expected.iter().flatten())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-12));
+        assert!(invert(&[vec![1.0, 2.0], vec![2.0, 4.0]]).is_none());
+    }
+}
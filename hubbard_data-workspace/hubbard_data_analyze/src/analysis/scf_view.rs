@@ -0,0 +1,80 @@
+use std::marker::PhantomData;
+
+use polars::{
+    error::PolarsError,
+    frame::DataFrame,
+    prelude::{DataType, IntoLazy, col, lit},
+};
+
+use crate::{Alpha, ChannelMeanView, JobType, U};
+
+use super::{
+    Pipeline, ViewType, channel_view::fit_view::regression_exprs,
+    merged_view::ChannelMergedMeanView,
+};
+
+#[derive(Debug, Clone, Copy)]
+/// The self-consistent U of one channel, extrapolated from the U ladder.
+/// Following Cococcioni and Kulik, the computed `U_out` depends linearly on the input `U_in`:
+/// `U_out = U_scf - U_in / m`
+/// so `U_scf` is the intercept of the linear fit of `U_out` against `U_in`.
+/// The dataframe has the following columns, one row per response (`n1-nF_U`, `n1-nF_Alpha`):
+/// ["Response", "U_scf", "U_scf_stderr", "slope", "r2", "points"]
+pub struct ScfUView<T: JobType>(PhantomData<T>);
+
+impl<T: JobType> ViewType<T> for ScfUView<T> {}
+
+/// Fit the `responses` columns against column "U", skipping non-finite values.
+fn extrapolate(data: &DataFrame, responses: &[String]) -> Result<DataFrame, PolarsError> {
+    let frames = responses
+        .iter()
+        .map(|response| {
+            let [slope, intercept, r2, slope_stderr] = regression_exprs("U_in", response, "fit");
+            let u_in = || col("U_in");
+            let n = || col(response).count().cast(DataType::Float64);
+            // Var(intercept) = Var(slope) * (Σ(x - x̄)² / n + x̄²)
+            let spread = ((u_in() - u_in().mean()) * (u_in() - u_in().mean())).sum() / n()
+                + u_in().mean() * u_in().mean();
+            data.clone()
+                .lazy()
+                .select([
+                    col("U").cast(DataType::Float64).alias("U_in"),
+                    col(response),
+                ])
+                .filter(col(response).is_finite())
+                .with_column(lit(response.as_str()).alias("Response"))
+                .group_by([col("Response")])
+                .agg([
+                    intercept.alias("U_scf"),
+                    (slope_stderr * spread.sqrt()).alias("U_scf_stderr"),
+                    slope.alias("slope"),
+                    r2.alias("r2"),
+                    n().cast(DataType::UInt32).alias("points"),
+                ])
+                .collect()
+        })
+        .collect::<Result<Vec<DataFrame>, PolarsError>>()?;
+    frames
+        .into_iter()
+        .try_fold(DataFrame::empty(), |acc, frame| acc.vstack(&frame))
+}
+
+impl Pipeline<U, ChannelMergedMeanView, DataFrame> {
+    /// Extrapolate the self-consistent U from both `n1-nF_U` and `n1-nF_Alpha`.
+    pub fn to_scf_view(&self) -> Result<Pipeline<U, ScfUView<U>, DataFrame>, PolarsError> {
+        Ok(Pipeline::new(extrapolate(
+            &self.data,
+            &[U::delta_slope_col_alias(), Alpha::delta_slope_col_alias()],
+        )?))
+    }
+}
+
+impl<T: JobType> Pipeline<T, ChannelMeanView<T>, DataFrame> {
+    /// Extrapolate the self-consistent U from the mean response of this job type.
+    pub fn to_scf_view(&self) -> Result<Pipeline<T, ScfUView<T>, DataFrame>, PolarsError> {
+        Ok(Pipeline::new(extrapolate(
+            &self.data,
+            &[T::delta_slope_col_alias()],
+        )?))
+    }
+}
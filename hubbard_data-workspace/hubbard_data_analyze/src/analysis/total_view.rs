@@ -1,6 +1,10 @@
 use std::marker::PhantomData;
 
-use polars::prelude::{ChunkUnique, LazyFrame, col, lit};
+use polars::{
+    error::PolarsError,
+    frame::DataFrame,
+    prelude::{ChunkUnique, DataType, LazyFrame, NULL, col, lit, when},
+};
 
 use crate::{Alpha, U, analysis::ViewType, job_type::JobType};
 
@@ -8,7 +12,7 @@ use super::{Pipeline, ViewColumn, channel_view::ChannelView};
 
 #[derive(Debug, Copy, Clone)]
 /// The processed total view will have the following columns:
-/// [ "Channel ID", "U", "S1-S0", "SF-S0", "u/S1-S0 | alpha/s1-S0", "u/SF-S0 | alpha/SF-S0", "u_pert | alpha_pert", "n1-nF"]
+/// [ "Channel ID", "U", "S1-S0", "SF-S0", "u/S1-S0 | alpha/s1-S0", "u/SF-S0 | alpha/SF-S0", "u_pert | alpha_pert", "n1-nF", "Converged"]
 pub struct TotalView<T: JobType>(PhantomData<T>);
 
 impl<T: JobType> ViewType<T> for TotalView<T> {}
@@ -24,6 +28,7 @@ impl ViewColumn<U> for TotalView<U> {
             "u/SF-S0",
             "u_pert",
             "n1-nF",
+            "Converged",
         ]
         .into_iter()
         .map(String::from)
@@ -42,6 +47,7 @@ impl ViewColumn<Alpha> for TotalView<Alpha> {
             "alpha/SF-S0",
             "alpha_pert",
             "n1-nF",
+            "Converged",
         ]
         .into_iter()
         .map(String::from)
@@ -67,6 +73,42 @@ impl<T: JobType> Pipeline<T, TotalView<T>, LazyFrame> {
         )
     }
 
+    /// Blank out the responses of the jobs whose SCF did not converge,
+    /// so they are left out of the means and fits while the rows stay aligned
+    /// with the other job type when both are merged.
+    pub fn exclude_unconverged(self) -> Self {
+        let responses = [
+            "S1-S0".to_string(),
+            "SF-S0".to_string(),
+            T::slope_first_col_alias(),
+            T::slope_final_col_alias(),
+            "n1-nF".to_string(),
+        ];
+        Pipeline::new(
+            self.data.with_columns(
+                responses
+                    .iter()
+                    .map(|response| {
+                        when(col("Converged"))
+                            .then(col(response))
+                            .otherwise(lit(NULL).cast(DataType::Float64))
+                            .alias(response)
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        )
+    }
+
+    /// The perturbation points from jobs whose SCF did not converge:
+    /// ["Channel ID", "U", "u_pert | alpha_pert"]
+    pub fn unconverged_points(&self) -> Result<DataFrame, PolarsError> {
+        self.data
+            .clone()
+            .filter(col("Converged").not())
+            .select([col("Channel ID"), col("U"), col(T::nth_perturb_col_alias())])
+            .collect()
+    }
+
     /// Get the list of unique channel ids in the frame.
     pub fn channels(&self) -> Vec<u32> {
         self.data
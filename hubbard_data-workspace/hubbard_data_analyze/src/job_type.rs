@@ -26,8 +26,16 @@ pub trait JobType: Sized {
     fn slope_final_col_alias() -> String;
     /// Alias for column of slope for `slope_first` - `slope_final`
     fn delta_slope_col_alias() -> String;
+    /// Alias for column of the number of perturbation points behind a mean, e.g.: "points_U"
+    fn points_col_alias() -> String {
+        format!("points_{}", Self::job_type())
+    }
     /// Generate a column marking the perturbation step from column "Jobname" of the csv
     fn perturb_expr() -> Expr;
+    /// The perturbation applied in each job, the numerator of the slope in `slope_expr`
+    fn perturbation_expr(perturb_step_val: f64) -> Expr {
+        col(Self::nth_perturb_col_alias()) * lit(perturb_step_val)
+    }
     /// Calculate the slope from data
     fn slope_expr(perturb_step_val: f64) -> [Expr; 2] {
         let matched_perturb_step_col = Self::nth_perturb_col_alias();
@@ -146,6 +154,11 @@ impl JobType for Alpha {
         "Alpha".into()
     }
 
+    /// U_10_alpha_5 = 10 (alpha base value) + 5 (perturb step) * perturb_step_val
+    fn perturbation_expr(perturb_step_val: f64) -> Expr {
+        col(Self::nth_perturb_col_alias()) * lit(perturb_step_val) + col("U")
+    }
+
     fn slope_expr(perturb_step_val: f64) -> [Expr; 2] {
         let matched_perturb_step_col = Self::nth_perturb_col_alias();
         let calc_expr = |u_col: Expr, perturb_step_col: Expr, scf_col: Expr, alias: &str| {
@@ -9,9 +9,11 @@ mod job_type;
 
 pub use analysis::{
     HubbardUPlot, Pipeline,
-    channel_view::{ChannelMeanView, ChannelView},
+    channel_view::{ChannelFitView, ChannelMeanView, ChannelView},
     csv_path::CSVPath,
     merged_view::{ChannelMergedMeanView, ChannelMergedView},
+    response_view::{PERTURBED_CHANNEL_COL, ResponseMatrices, ResponseMatrixView},
+    scf_view::ScfUView,
     total_view::TotalView,
 };
 pub use job_type::{Alpha, JobType, U};
@@ -20,11 +22,11 @@ pub use polars::prelude::{CsvWriter, DataFrame, LazyFrame};
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use polars::{error::PolarsError, frame::DataFrame};
 
-    use crate::{Alpha, ChannelMergedMeanView, JobType, Pipeline, U};
+    use crate::{Alpha, ChannelMergedMeanView, JobType, Pipeline, ResponseMatrixView, U};
 
     #[test]
     fn it_works() {
@@ -57,6 +59,184 @@ mod tests {
                 Ok::<(), PolarsError>(())
             }).expect("Pipeline demonstration fail");
     }
+    #[test]
+    fn scf_view() {
+        let result_folder = Path::new("../../sorting");
+        let result_df_u = U::csv_path(result_folder).process_data(0.05).unwrap();
+        let result_df_alpha = Alpha::csv_path(result_folder).process_data(0.05).unwrap();
+        let channel = result_df_u.channels()[0];
+        let scf = result_df_u
+            .to_channel_view(channel)
+            .concat_alpha(result_df_alpha.to_channel_view(channel))
+            .and_then(|concated| concated.view_mean())
+            .and_then(|mean| mean.to_scf_view())
+            .unwrap();
+        assert_eq!(scf.data().height(), 2);
+        let points = scf.data().column("points").unwrap().u32().unwrap();
+        assert_eq!(points.get(0), Some(7));
+        println!("{}", scf.data());
+    }
+    #[test]
+    fn fit_view() {
+        let result_folder = Path::new("../../sorting");
+        let result_df_u = U::csv_path(result_folder).process_data(0.05).unwrap();
+        result_df_u.channels().into_iter().for_each(|channel| {
+            let fit = result_df_u.to_fit_view(channel).unwrap();
+            let fit = fit.data();
+            assert_eq!(fit.height(), 7);
+            fit.column("r2_final")
+                .unwrap()
+                .f64()
+                .unwrap()
+                .iter()
+                .flatten()
+                .for_each(|r2| assert!((0.0..=1.0 + 1e-12).contains(&r2)));
+            println!("{fit}");
+        });
+    }
+    #[test]
+    fn response_matrices() {
+        let result_folder = Path::new("../../sorting");
+        let matrices = U::csv_path(result_folder)
+            .process_response(0.05, false)
+            .unwrap();
+        assert_eq!(matrices.len(), 7);
+        let scalar = U::csv_path(result_folder).process_data(0.05).unwrap();
+        let mut channels = scalar.channels();
+        channels.sort();
+        matrices.iter().for_each(|m| {
+            assert_eq!(m.channels, channels);
+            assert!(m.site_u().is_some());
+        });
+        let view =
+            Pipeline::<U, ResponseMatrixView<U>, DataFrame>::from_matrices(&matrices).unwrap();
+        // Without "Perturbed Channel", every channel was perturbed at once: diagonal only
+        matrices.iter().for_each(|m| {
+            (0..m.channels.len()).for_each(|i| {
+                (0..m.channels.len())
+                    .filter(|&j| j != i)
+                    .for_each(|j| assert_eq!(m.chi[i][j], 0.0));
+            })
+        });
+        println!("{}", view.data());
+    }
+
+    #[test]
+    fn off_diagonal_response() {
+        // Two runs perturbing channel 1 and channel 2, channel 3 is not perturbed
+        let chi0 = [[-1.0, 0.2], [0.3, -0.8], [0.05, 0.04]];
+        let chi = [[-0.5, 0.1], [0.15, -0.4], [0.02, 0.01]];
+        let folder = test_dir("hubbard_data_analyze_off_diagonal");
+        let runs = (0..2)
+            .map(|j| {
+                let run = folder.join(format!("NiO_Ni-{}-d_u", j + 1));
+                std::fs::create_dir_all(&run).unwrap();
+                let mut csv = String::from(
+                    "Jobname,Channel ID,Spin,Before SCF,1st SCF,Last SCF,Converged,Perturbed Channel\n",
+                );
+                for step in 0..=2 {
+                    let job_name = match step {
+                        0 => "./U_0_u/NiO".to_string(),
+                        _ => format!("./U_0_u/U_0_u_{step}/NiO"),
+                    };
+                    let dalpha = step as f64 * 0.05;
+                    for i in 0..3 {
+                        for spin in 1..=2 {
+                            csv.push_str(&format!(
+                                "{job_name},{},{spin},4.0,{},{},true,{}\n",
+                                i + 1,
+                                4.0 + chi0[i][j] * dalpha / 2.0,
+                                4.0 + chi[i][j] * dalpha / 2.0,
+                                j + 1
+                            ));
+                        }
+                    }
+                }
+                std::fs::write(run.join("result_u_final.csv"), csv).unwrap();
+                U::csv_path(run)
+            })
+            .collect::<Vec<_>>();
+        let matrices = Pipeline::combine_response(&runs, 0.05, false).unwrap();
+        assert_eq!(matrices.len(), 1);
+        let m = &matrices[0];
+        assert_eq!(m.channels, vec![1, 2]);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-10;
+        (0..2).for_each(|i| {
+            (0..2).for_each(|j| {
+                assert!(close(m.chi0[i][j], chi0[i][j]), "{:?}", m.chi0);
+                assert!(close(m.chi[i][j], chi[i][j]), "{:?}", m.chi);
+            })
+        });
+        // Diagonal of the 2x2 inverse: `[d, a] / (ad - bc)`
+        let inverse_diagonal = |m: [[f64; 2]; 3]| {
+            let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+            [m[1][1] / det, m[0][0] / det]
+        };
+        let (chi0_inv, chi_inv) = (inverse_diagonal(chi0), inverse_diagonal(chi));
+        let site_u = m.site_u().unwrap();
+        (0..2).for_each(|i| assert!(close(site_u[i], chi0_inv[i] - chi_inv[i])));
+        // The off-diagonal response changes U from the scalar `1/chi0 - 1/chi` of the site
+        assert!(!close(site_u[0], 1.0 / chi0[0][0] - 1.0 / chi[0][0]));
+        // One run alone gives the 1x1 matrix of its channel
+        let single = runs[1].process_response(0.05, false).unwrap();
+        assert_eq!(single[0].channels, vec![2]);
+        assert!(close(
+            single[0].site_u().unwrap()[0],
+            1.0 / chi0[1][1] - 1.0 / chi[1][1]
+        ));
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn exclude_unconverged() {
+        let content = std::fs::read_to_string("../../sorting/result_u_final.csv").unwrap();
+        let result_folder = test_dir("hubbard_data_analyze_unconverged");
+        std::fs::write(
+            result_folder.join("result_u_final.csv"),
+            content
+                .lines()
+                .map(|line| match line.starts_with("./U_2_u/U_2_u_1/") {
+                    true => line.replace("true", "false"),
+                    false => line.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+        )
+        .unwrap();
+        let total = U::csv_path(&result_folder).process_data(0.05).unwrap();
+        let channel = total.channels()[0];
+        let unconverged = total.unconverged_points().unwrap();
+        assert_eq!(unconverged.height(), total.channels().len());
+        let points = |mean: &DataFrame| -> Vec<u32> {
+            mean.column(&U::points_col_alias())
+                .unwrap()
+                .u32()
+                .unwrap()
+                .iter()
+                .flatten()
+                .collect()
+        };
+        let kept = total.to_channel_view(channel).to_mean_view().unwrap();
+        assert_eq!(points(kept.data()), vec![5; 7]);
+        let total = total.exclude_unconverged();
+        let excluded = total.to_channel_view(channel).to_mean_view().unwrap();
+        assert_eq!(points(excluded.data()), vec![5, 4, 5, 5, 5, 5, 5]);
+        let fit = total.to_fit_view(channel).unwrap();
+        let fit_points = fit.data().column("points").unwrap().u32().unwrap();
+        assert_eq!(fit_points.get(1), Some(4));
+        let csv = U::csv_path(&result_folder);
+        let kept = csv.process_response(0.05, false).unwrap();
+        let excluded = csv.process_response(0.05, true).unwrap();
+        assert_eq!(kept.len(), excluded.len());
+        kept.iter()
+            .zip(excluded.iter())
+            .for_each(|(k, e)| match k.u {
+                2 => assert_ne!(k.chi, e.chi),
+                _ => assert_eq!(k, e),
+            });
+        std::fs::remove_dir_all(result_folder).unwrap();
+    }
+
     #[test]
     fn it_works_single() {
         let result_folder = Path::new("../../NiO");
@@ -75,4 +255,13 @@ mod tests {
                 println!("{}", mean.data());
             });
     }
+
+    /// A new empty folder in the temp dir named after the test and the process,
+    /// so that two runs of the suite do not share one
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 }
@@ -1,5 +1,9 @@
 use clap::ValueEnum;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::{
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
 pub use clap::Parser;
@@ -11,14 +15,30 @@ pub use clap::Parser;
 pub struct HubbardDataCli {
     #[arg(short = 's')]
     result_folder: PathBuf,
+    /// Perturbation value of the `U` run, read from its `run.toml` if not given
     #[arg(short, long)]
-    u_perturb_val: f64,
+    u_perturb_val: Option<f64>,
+    /// Perturbation value of the `Alpha` run, read from its `run.toml` if not given
     #[arg(short, long)]
-    alpha_perturb_val: f64,
+    alpha_perturb_val: Option<f64>,
     #[arg(short, long)]
     verbose: Option<bool>,
     #[arg(short, long)]
     mode: Option<Mode>,
+    /// Leave the perturbation points of jobs whose SCF did not converge
+    /// out of the means, fits and response matrices.
+    #[arg(long, group = "unconverged_points")]
+    exclude_unconverged: bool,
+    /// Keep the perturbation points of unconverged jobs and list them in a warning (default)
+    #[arg(long, group = "unconverged_points")]
+    warn_unconverged: bool,
+    /// Keep the perturbation points of unconverged jobs without the warning listing them
+    #[arg(long, group = "unconverged_points")]
+    keep_unconverged: bool,
+    /// Result folders of other runs of the seed perturbing one site each (`auto_hubbard calc --site`),
+    /// combined with this one into the inter-site response matrices
+    #[arg(long, num_args = 1..)]
+    combine: Vec<PathBuf>,
 }
 
 impl HubbardDataCli {
@@ -26,12 +46,54 @@ impl HubbardDataCli {
         &self.result_folder
     }
 
-    pub fn u_perturb_val(&self) -> f64 {
-        self.u_perturb_val
+    pub fn u_perturb_val(&self) -> Result<f64, PerturbValueError> {
+        match self.u_perturb_val {
+            Some(perturb_val) => Ok(perturb_val),
+            None => self
+                .manifest_perturb_val("u")?
+                .ok_or(PerturbValueError::Missing("u")),
+        }
+    }
+
+    pub fn alpha_perturb_val(&self) -> Result<f64, PerturbValueError> {
+        match self.alpha_perturb_val {
+            Some(perturb_val) => Ok(perturb_val),
+            None => self
+                .manifest_perturb_val("alpha")?
+                .ok_or(PerturbValueError::Missing("alpha")),
+        }
     }
 
-    pub fn alpha_perturb_val(&self) -> f64 {
-        self.alpha_perturb_val
+    /// Perturbation step recorded by the `run.toml` of the `job_type` run in the result folder,
+    /// or else in one of its subfolders. Several `job_type` runs in the subfolders are an error,
+    /// as nothing tells which of them the csv comes from.
+    fn manifest_perturb_val(
+        &self,
+        job_type: &'static str,
+    ) -> Result<Option<f64>, PerturbValueError> {
+        let mut found = find_manifests(&self.result_folder)
+            .into_iter()
+            .filter(|(_, manifest)| manifest.job_type() == job_type)
+            .collect::<Vec<(PathBuf, RunManifest)>>();
+        match found
+            .iter()
+            .position(|(folder, _)| *folder == self.result_folder)
+        {
+            Some(own) => Ok(Some(found.swap_remove(own).1.perturb_step())),
+            None if found.len() > 1 => Err(PerturbValueError::Ambiguous(
+                job_type,
+                found
+                    .iter()
+                    .map(|(folder, _)| folder.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            )),
+            None => Ok(found.pop().map(|(_, manifest)| manifest.perturb_step())),
+        }
+    }
+
+    pub fn combine(&self) -> &[PathBuf] {
+        &self.combine
     }
 
     pub fn verbose(&self) -> Option<bool> {
@@ -42,17 +104,105 @@ impl HubbardDataCli {
         self.mode
     }
 
+    pub fn unconverged(&self) -> Unconverged {
+        if self.exclude_unconverged {
+            Unconverged::Exclude
+        } else if self.keep_unconverged {
+            Unconverged::Keep
+        } else {
+            Unconverged::Warn
+        }
+    }
+
     /// Return the perturb value(s) in `PerturbValue` enum
     /// based on `Mode`
-    pub fn perturb_value(&self) -> PerturbValue {
-        match self.mode.unwrap_or_default() {
-            Mode::Both => PerturbValue::Both((self.u_perturb_val(), self.alpha_perturb_val())),
-            Mode::U => PerturbValue::Single(self.u_perturb_val()),
-            Mode::Alpha => PerturbValue::Single(self.alpha_perturb_val()),
-        }
+    pub fn perturb_value(&self) -> Result<PerturbValue, PerturbValueError> {
+        Ok(match self.mode.unwrap_or_default() {
+            Mode::Both => PerturbValue::Both((self.u_perturb_val()?, self.alpha_perturb_val()?)),
+            Mode::U => PerturbValue::Single(self.u_perturb_val()?),
+            Mode::Alpha => PerturbValue::Single(self.alpha_perturb_val()?),
+        })
     }
 }
 
+/// File name of the manifest written by `auto_hubbard calc` into each result folder
+pub const MANIFEST_FILE: &str = "run.toml";
+
+/// The parts of the `auto_hubbard` run manifest needed for the analysis.
+/// `auto_hubbard` tests that its manifest reads back through this one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunManifest {
+    hub_arguments: ManifestArguments,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ManifestArguments {
+    job_type: String,
+    alpha_step: f64,
+}
+
+impl RunManifest {
+    /// Load `run.toml` in `folder`
+    pub fn load<P: AsRef<Path>>(folder: P) -> Result<Self, ManifestError> {
+        Ok(toml::from_str(&read_to_string(
+            folder.as_ref().join(MANIFEST_FILE),
+        )?)?)
+    }
+
+    /// `u` or `alpha`
+    pub fn job_type(&self) -> String {
+        self.hub_arguments.job_type.to_lowercase()
+    }
+
+    /// Increment of the perturbation, which is the perturbation value of the analysis
+    pub fn perturb_step(&self) -> f64 {
+        self.hub_arguments.alpha_step
+    }
+}
+
+/// Manifests in `folder` and its direct subfolders with the folder holding each,
+/// skipping the unreadable ones. The subfolders are sorted by name.
+pub fn find_manifests<P: AsRef<Path>>(folder: P) -> Vec<(PathBuf, RunManifest)> {
+    let folder = folder.as_ref();
+    let mut subfolders = read_dir(folder)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_dir())
+                .collect::<Vec<PathBuf>>()
+        })
+        .unwrap_or_default();
+    subfolders.sort();
+    std::iter::once(folder.to_path_buf())
+        .chain(subfolders)
+        .filter_map(|path| {
+            RunManifest::load(&path)
+                .ok()
+                .map(|manifest| (path, manifest))
+        })
+        .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Failed to read the run manifest: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid run manifest: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum PerturbValueError {
+    #[error(
+        "No perturbation value of the `{0}` run: pass it on the command line or keep its `run.toml`"
+    )]
+    Missing(&'static str),
+    #[error(
+        "Several `{0}` runs in the result folder ({1}): pass the perturbation value on the command line"
+    )]
+    Ambiguous(&'static str, String),
+}
+
 /// An enum to hold two different return types of `HubbardDataCli::perturb_value(&self)`
 #[derive(Debug, Clone, Copy)]
 pub enum PerturbValue {
@@ -88,6 +238,18 @@ impl PerturbValue {
     }
 }
 
+/// How to treat the jobs whose SCF did not converge, according to the `Converged` column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unconverged {
+    /// Use them like the others
+    Keep,
+    /// Use them, but list them
+    #[default]
+    Warn,
+    /// List them and leave them out
+    Exclude,
+}
+
 /// An enum to define the job mode of the program
 #[derive(Debug, Clone, Copy, Default, ValueEnum)]
 pub enum Mode {
@@ -109,4 +271,76 @@ mod tests {
     fn it_works() {
         HubbardDataCli::command().debug_assert();
     }
+
+    #[test]
+    fn perturb_value_from_manifest() {
+        let folder = test_dir("hubbard_data_manifest_test");
+        let u_run = folder.join("NiO_u_0_2_12_0.05_0.05_0.25_STEPS_5");
+        std::fs::create_dir_all(&u_run).unwrap();
+        std::fs::write(
+            u_run.join(MANIFEST_FILE),
+            "seed_name = \"NiO\"\n[hub_arguments]\njob_type = \"U\"\nalpha_step = 0.05\nu_start = 0.0\n",
+        )
+        .unwrap();
+        let cli = HubbardDataCli::parse_from([
+            "hubbard_data",
+            "-s",
+            folder.to_str().unwrap(),
+            "-a",
+            "0.1",
+        ]);
+        assert_eq!(cli.u_perturb_val().unwrap(), 0.05);
+        assert_eq!(cli.alpha_perturb_val().unwrap(), 0.1);
+        // A second `U` run leaves the subfolders ambiguous
+        let other_run = folder.join("NiO_u_0_2_12_0.1_0.1_0.5_STEPS_5");
+        std::fs::create_dir_all(&other_run).unwrap();
+        std::fs::write(
+            other_run.join(MANIFEST_FILE),
+            "seed_name = \"NiO\"\n[hub_arguments]\njob_type = \"U\"\nalpha_step = 0.1\nu_start = 0.0\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            cli.u_perturb_val(),
+            Err(PerturbValueError::Ambiguous("u", _))
+        ));
+        // Unless the result folder is the run itself
+        let cli = HubbardDataCli::parse_from(["hubbard_data", "-s", other_run.to_str().unwrap()]);
+        assert_eq!(cli.u_perturb_val().unwrap(), 0.1);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn unconverged_flags() {
+        let parse = |flags: &[&str]| {
+            HubbardDataCli::try_parse_from(
+                ["hubbard_data", "-s", ".", "-u", "0.05", "-a", "0.05"]
+                    .iter()
+                    .chain(flags),
+            )
+        };
+        assert_eq!(parse(&[]).unwrap().unconverged(), Unconverged::Warn);
+        assert_eq!(
+            parse(&["--warn-unconverged"]).unwrap().unconverged(),
+            Unconverged::Warn
+        );
+        assert_eq!(
+            parse(&["--keep-unconverged"]).unwrap().unconverged(),
+            Unconverged::Keep
+        );
+        assert_eq!(
+            parse(&["--exclude-unconverged"]).unwrap().unconverged(),
+            Unconverged::Exclude
+        );
+        assert!(parse(&["--exclude-unconverged", "--keep-unconverged"]).is_err());
+        assert!(parse(&["--exclude-unconverged", "--warn-unconverged"]).is_err());
+    }
+
+    /// A new empty folder in the temp dir named after the test and the process,
+    /// so that two runs of the suite do not share one
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 }